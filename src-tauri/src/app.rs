@@ -0,0 +1,404 @@
+// The desktop app around the compression core: tauri commands, the bundled sidecars and the
+// webview as the event sink. Only built with the `app` feature, the CLI doesn't need any of it.
+
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager, State, WindowEvent};
+use tauri_plugin_shell::process::{CommandChild, CommandEvent};
+use tauri_plugin_shell::ShellExt;
+use tokio::sync::mpsc::channel;
+
+use crate::audio::AudioOptions;
+use crate::engine::{
+    ai_engine_binary, kill_tree, spawn_native, ChildProcess, Engine, EventSink, ProcessEvent,
+    ProcessRunner, SpawnedTool, Tool,
+};
+use crate::error::{CompressError, Result};
+use crate::ladder::{StreamingManifest, StreamingOptions};
+use crate::loudness::LoudnessReport;
+use crate::pipeline::{self, VideoOptions, VideoReport};
+use crate::process::JobScope;
+use crate::{hardware, jobs, presets, probe, process};
+
+// ffmpeg/ffprobe from the sidecars bundled with the desktop app, the AI engine from its resource folder
+pub struct SidecarRunner {
+    app: AppHandle,
+    ai_engine: PathBuf,
+}
+
+impl SidecarRunner {
+    pub fn new(app: AppHandle, ai_engine_dir: &Path) -> Self {
+        SidecarRunner {
+            app,
+            ai_engine: ai_engine_binary(ai_engine_dir),
+        }
+    }
+}
+
+impl ProcessRunner for SidecarRunner {
+    fn spawn(&self, tool: Tool, args: Vec<String>) -> Result<SpawnedTool> {
+        match tool {
+            Tool::AiEngine => spawn_native(&self.ai_engine, args),
+            _ => spawn_sidecar(&self.app, tool, args),
+        }
+    }
+}
+
+struct SidecarChild(CommandChild);
+
+impl ChildProcess for SidecarChild {
+    fn kill(self: Box<Self>) {
+        kill_tree(self.0.pid());
+        let _ = self.0.kill();
+    }
+}
+
+fn spawn_sidecar(app: &AppHandle, tool: Tool, args: Vec<String>) -> Result<SpawnedTool> {
+    let (mut rx, child) = app.shell().sidecar(tool.name())?.args(args).spawn()?;
+    let pid = child.pid();
+    let (tx, events) = channel(64);
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let event = match event {
+                CommandEvent::Stdout(line) => ProcessEvent::Stdout(line),
+                CommandEvent::Stderr(line) => ProcessEvent::Stderr(line),
+                CommandEvent::Terminated(payload) => ProcessEvent::Exited(payload.code),
+                _ => continue,
+            };
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(SpawnedTool {
+        pid,
+        events,
+        handle: Box::new(SidecarChild(child)),
+    })
+}
+
+// Events from the pipelines go straight to the webview
+struct AppEvents(AppHandle);
+
+impl EventSink for AppEvents {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.0.emit(event, payload);
+    }
+}
+
+// 🟢 THE AI ENHANCER COMMAND (FIXED TO USE AVAILABLE MODELS)
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn enhance_image(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    scale: String,
+    format: String,
+    model_type: String,
+    face_restore: bool,
+    hyper_detail: bool,
+    tile_size: String,
+    job_id: Option<String>,
+) -> Result<()> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::enhance_image(
+        &scope,
+        input,
+        output,
+        scale,
+        format,
+        model_type,
+        face_restore,
+        hyper_detail,
+        tile_size,
+    )
+    .await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn enhance_video(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    ai_scale: String,
+    model_type: String,
+    face_restore: bool,
+    ai_fps: String,
+    denoise: bool,
+    stabilize: bool,
+    hyper_detail: bool,
+    tile_size: String,
+    auto_gpu: bool,
+    job_id: Option<String>,
+) -> Result<()> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::enhance_video(
+        &scope,
+        input,
+        output,
+        ai_scale,
+        model_type,
+        face_restore,
+        ai_fps,
+        denoise,
+        stabilize,
+        hyper_detail,
+        tile_size,
+        auto_gpu,
+    )
+    .await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn compress_video(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: Option<VideoOptions>,
+    job_id: Option<String>,
+) -> Result<Option<VideoReport>> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_video(
+        &scope,
+        input,
+        output,
+        auto_gpu,
+        preset,
+        options.unwrap_or_default(),
+    )
+    .await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn compress_video_streaming(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: Option<StreamingOptions>,
+    job_id: Option<String>,
+) -> Result<StreamingManifest> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_video_streaming(
+        &scope,
+        input,
+        output,
+        auto_gpu,
+        preset,
+        options.unwrap_or_default(),
+    )
+    .await
+}
+
+#[tauri::command]
+async fn compress_image(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    width: String,
+    height: String,
+    preset: Option<String>,
+    job_id: Option<String>,
+) -> Result<()> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_image(&scope, input, output, width, height, preset).await
+}
+
+#[tauri::command]
+fn read_file_bytes(path: String) -> Result<Vec<u8>> {
+    if path.contains("..") {
+        return Err(CompressError::invalid(
+            "Security Error: Path traversal detected",
+        ));
+    }
+
+    let p = std::path::Path::new(&path);
+    if !p.is_absolute() {
+        return Err(CompressError::invalid(
+            "Security Error: Only absolute paths are allowed",
+        ));
+    }
+
+    Ok(std::fs::read(path)?)
+}
+
+#[tauri::command]
+fn show_in_folder(path: String) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        std::process::Command::new("explorer")
+            .args(["/select,", &path])
+            .spawn()?;
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("open")
+            .args(["-R", &path])
+            .spawn()?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let parent = std::path::Path::new(&path)
+            .parent()
+            .unwrap_or(std::path::Path::new(""));
+        std::process::Command::new("xdg-open").arg(parent).spawn()?;
+    }
+    Ok(())
+}
+
+// Raw ffprobe JSON, kept for callers that read `format.duration` themselves. New code should use `probe_media`.
+#[tauri::command]
+async fn probe_video(engine: State<'_, Engine>, input: String) -> Result<String> {
+    let scope = JobScope::new(&engine, None);
+    probe::run_ffprobe(&scope, &input).await
+}
+
+#[tauri::command]
+fn get_file_size(path: String) -> Result<u64> {
+    Ok(std::fs::metadata(path)?.len())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn compress_video_target_size(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    target_size_kb: f64,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: Option<VideoOptions>,
+    job_id: Option<String>,
+) -> Result<VideoReport> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_video_target_size(
+        &scope,
+        input,
+        output,
+        target_size_kb,
+        auto_gpu,
+        preset,
+        options.unwrap_or_default(),
+    )
+    .await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn compress_image_target_size(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    target_size_kb: f64,
+    width: String,
+    height: String,
+    preset: Option<String>,
+    job_id: Option<String>,
+) -> Result<()> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_image_target_size(
+        &scope,
+        input,
+        output,
+        target_size_kb,
+        width,
+        height,
+        preset,
+    )
+    .await
+}
+
+#[tauri::command]
+async fn compress_audio(
+    engine: State<'_, Engine>,
+    input: String,
+    output: String,
+    preset: Option<String>,
+    options: Option<AudioOptions>,
+    job_id: Option<String>,
+) -> Result<Option<LoudnessReport>> {
+    let scope = JobScope::new(&engine, job_id);
+    pipeline::compress_audio(&scope, input, output, preset, options.unwrap_or_default()).await
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_process::init())
+        .manage(jobs::JobQueue::default())
+        .setup(|app| {
+            let ai_engine_dir = app
+                .path()
+                .resource_dir()?
+                .join("binaries")
+                .join("ai_engine");
+            let engine = Engine::new(
+                Box::new(SidecarRunner::new(app.handle().clone(), &ai_engine_dir)),
+                Box::new(AppEvents(app.handle().clone())),
+                app.path().temp_dir()?,
+                ai_engine_dir,
+            );
+            engine
+                .presets
+                .load(app.path().app_config_dir()?.join("presets.json"));
+            app.manage(engine);
+
+            // Restore the persisted queue and pick up where the last session left off
+            let queue_file = app.path().app_data_dir()?.join("jobs.json");
+            app.state::<jobs::JobQueue>().load(queue_file);
+            jobs::pump(app.handle());
+            let probe_file = app.path().app_data_dir()?.join("hardware.json");
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                hardware::restore(&handle.state::<Engine>(), probe_file).await;
+            });
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            compress_video,
+            compress_video_streaming,
+            compress_image,
+            compress_audio,
+            process::stop_job,
+            enhance_image,
+            enhance_video,
+            read_file_bytes,
+            probe_video,
+            probe::probe_media,
+            get_file_size,
+            compress_video_target_size,
+            compress_image_target_size,
+            show_in_folder,
+            jobs::enqueue_job,
+            jobs::list_jobs,
+            jobs::cancel_job,
+            jobs::pause_job,
+            jobs::resume_job,
+            jobs::set_queue_concurrency,
+            jobs::clear_finished_jobs,
+            hardware::detect_hardware,
+            presets::list_presets,
+            presets::create_preset,
+            presets::update_preset,
+            presets::delete_preset,
+            presets::import_presets,
+            presets::export_presets
+        ])
+        .on_window_event(|window, event| {
+            if let WindowEvent::Destroyed = event {
+                // Only our own children; other ffmpeg processes on the machine are left alone
+                window.app_handle().state::<Engine>().registry.cancel_all();
+            }
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
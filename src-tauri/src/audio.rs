@@ -0,0 +1,443 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CompressError, Result};
+use crate::loudness::LoudnessTarget;
+use crate::presets::{MetadataPolicy, Preset};
+use crate::probe::MediaInfo;
+
+// --- Audio-only outputs ---
+// The output extension decides the container and which codecs it can take; the job picks among
+// them and sets rate control, sample rate and channels. The first audio track of the source is
+// encoded, its cover art (an attached picture) is copied into containers that hold one and tags
+// come along like ffmpeg always does, except that Ogg keeps them on the stream instead of the file.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodecChoice {
+    // The container's usual codec: MP3, AAC for .aac/.m4a, Opus for .ogg/.opus, FLAC, PCM for .wav
+    #[default]
+    Auto,
+    Mp3,
+    Aac,
+    Opus,
+    // Lossless
+    Flac,
+    Alac,
+    Pcm,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BitrateMode {
+    // Constant bitrate, what old players and some streaming tools want
+    Cbr,
+    // Variable bitrate around the asked one, smaller for the same quality
+    Vbr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelLayout {
+    // Downmixed to one channel, for speech
+    Mono,
+    // Surround sources are downmixed to two channels
+    Stereo,
+}
+
+// Per-job audio settings on top of the preset. Everything left at its default keeps the
+// per-extension behavior the app always had.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AudioOptions {
+    pub codec: AudioCodecChoice,
+    // None is VBR for Opus, and for MP3 unless a bitrate is set
+    pub mode: Option<BitrateMode>,
+    // Overrides the preset's audio bitrate; lossless codecs have none
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    // None keeps the source's channels
+    pub channels: Option<ChannelLayout>,
+    // Two-pass loudness normalization (see loudness.rs)
+    pub loudness: Option<LoudnessTarget>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AudioPlan {
+    // Maps, codec, rate control and format args, after -i and -af
+    pub args: Vec<String>,
+    // Anything the user should know, like cover art that can't come along
+    pub note: Option<String>,
+}
+
+impl AudioCodecChoice {
+    fn name(self) -> &'static str {
+        match self {
+            AudioCodecChoice::Auto => "auto",
+            AudioCodecChoice::Mp3 => "MP3",
+            AudioCodecChoice::Aac => "AAC",
+            AudioCodecChoice::Opus => "Opus",
+            AudioCodecChoice::Flac => "FLAC",
+            AudioCodecChoice::Alac => "ALAC",
+            AudioCodecChoice::Pcm => "PCM",
+        }
+    }
+
+    fn is_lossless(self) -> bool {
+        matches!(
+            self,
+            AudioCodecChoice::Flac | AudioCodecChoice::Alac | AudioCodecChoice::Pcm
+        )
+    }
+}
+
+// Codecs each container takes, the usual one first. Other extensions (.mka...) take any codec and
+// leave the pick to ffmpeg on Auto.
+fn container_codecs(container: &str) -> Option<&'static [AudioCodecChoice]> {
+    use AudioCodecChoice::*;
+    match container {
+        "mp3" => Some(&[Mp3]),
+        "aac" => Some(&[Aac]),
+        "m4a" => Some(&[Aac, Alac]),
+        "ogg" => Some(&[Opus, Flac]),
+        "opus" => Some(&[Opus]),
+        "flac" => Some(&[Flac]),
+        "wav" => Some(&[Pcm]),
+        _ => None,
+    }
+}
+
+// Containers that store cover art, and the picture formats they store
+fn holds_cover(container: &str, codec: &str) -> bool {
+    matches!(container, "mp3" | "m4a" | "flac") && matches!(codec, "mjpeg" | "png")
+}
+
+// LAME's VBR levels (-q:a 0-9) and the bitrate each averages on music
+const LAME_VBR_KBPS: &[u32] = &[245, 225, 190, 175, 165, 130, 115, 100, 85, 65];
+// The app's MP3 default, V4 (~165k)
+const DEFAULT_MP3_QUALITY: usize = 4;
+
+fn lame_quality(kbps: u32) -> usize {
+    (0..LAME_VBR_KBPS.len())
+        .min_by_key(|&q| LAME_VBR_KBPS[q].abs_diff(kbps))
+        .unwrap_or(DEFAULT_MP3_QUALITY)
+}
+
+fn check_sample_rate(codec: AudioCodecChoice, rate: u32) -> Result<()> {
+    let supported = match codec {
+        AudioCodecChoice::Opus => matches!(rate, 8000 | 12000 | 16000 | 24000 | 48000),
+        AudioCodecChoice::Mp3 => matches!(
+            rate,
+            8000 | 11025 | 12000 | 16000 | 22050 | 24000 | 32000 | 44100 | 48000
+        ),
+        AudioCodecChoice::Aac => (8000..=96000).contains(&rate),
+        _ => (8000..=384000).contains(&rate),
+    };
+    if supported {
+        return Ok(());
+    }
+    Err(CompressError::unsupported(format!(
+        "{} can't be encoded at {} Hz",
+        codec.name(),
+        rate
+    )))
+}
+
+// PCM keeps a 24/32-bit or float source's sample format, anything else becomes 16-bit
+fn pcm_encoder(info: &MediaInfo) -> String {
+    match info.audio.first().map(|a| a.codec.as_str()) {
+        Some(codec @ ("pcm_s24le" | "pcm_s32le" | "pcm_f32le")) => codec.to_string(),
+        _ => "pcm_s16le".to_string(),
+    }
+}
+
+pub fn plan(
+    container: &str,
+    info: &MediaInfo,
+    preset: &Preset,
+    options: &AudioOptions,
+) -> Result<AudioPlan> {
+    let allowed = container_codecs(container);
+    let codec = match (options.codec, allowed) {
+        (AudioCodecChoice::Auto, Some(allowed)) => allowed[0],
+        (codec, Some(allowed)) if !allowed.contains(&codec) => {
+            return Err(CompressError::unsupported(format!(
+                ".{} files can't hold {} audio",
+                container,
+                codec.name()
+            )));
+        }
+        (codec, _) => codec,
+    };
+    if options
+        .bitrate_kbps
+        .map(|kbps| !(8..=510).contains(&kbps))
+        .unwrap_or(false)
+    {
+        return Err(CompressError::invalid(
+            "Audio bitrate must be between 8 and 510 kbps",
+        ));
+    }
+    if codec.is_lossless() && (options.bitrate_kbps.is_some() || options.mode.is_some()) {
+        return Err(CompressError::invalid(format!(
+            "{} is lossless, it has no bitrate to set",
+            codec.name()
+        )));
+    }
+    if codec == AudioCodecChoice::Auto && options.mode.is_some() {
+        return Err(CompressError::invalid(format!(
+            "Pick a codec for .{} files to set the bitrate mode",
+            container
+        )));
+    }
+    if let Some(rate) = options.sample_rate {
+        check_sample_rate(codec, rate)?;
+    }
+
+    let first = info
+        .audio
+        .first()
+        .ok_or_else(|| CompressError::MissingStream {
+            kind: "audio".to_string(),
+        })?;
+    let mut plan = AudioPlan {
+        args: vec!["-map".to_string(), format!("0:{}", first.index)],
+        note: None,
+    };
+    let bitrate = options.bitrate_kbps.or(preset.audio_bitrate_kbps);
+    let kbps = |default_kbps: u32| format!("{}k", bitrate.unwrap_or(default_kbps));
+    let args = &mut plan.args;
+    match codec {
+        AudioCodecChoice::Mp3 => {
+            args.extend(["-c:a".to_string(), "libmp3lame".to_string()]);
+            // VBR -q:a 4 unless a bitrate is asked for, which is CBR unless VBR is
+            match (options.mode, bitrate) {
+                (Some(BitrateMode::Vbr), Some(kbps)) => {
+                    args.extend(["-q:a".to_string(), lame_quality(kbps).to_string()])
+                }
+                (Some(BitrateMode::Vbr), None) | (None, None) => {
+                    args.extend(["-q:a".to_string(), DEFAULT_MP3_QUALITY.to_string()])
+                }
+                (Some(BitrateMode::Cbr), _) | (None, Some(_)) => {
+                    args.extend(["-b:a".to_string(), kbps(192)])
+                }
+            }
+        }
+        AudioCodecChoice::Aac => {
+            // ffmpeg's own AAC encoder only has an experimental VBR mode
+            if options.mode == Some(BitrateMode::Vbr) {
+                return Err(CompressError::unsupported(
+                    "AAC is only encoded at a constant bitrate, pick Opus or MP3 for VBR",
+                ));
+            }
+            args.extend([
+                "-c:a".to_string(),
+                "aac".to_string(),
+                "-b:a".to_string(),
+                kbps(128),
+            ]);
+        }
+        AudioCodecChoice::Opus => {
+            args.extend([
+                "-c:a".to_string(),
+                "libopus".to_string(),
+                "-b:a".to_string(),
+                kbps(96),
+            ]);
+            match options.mode {
+                Some(BitrateMode::Cbr) => args.extend(["-vbr".to_string(), "off".to_string()]),
+                Some(BitrateMode::Vbr) => args.extend(["-vbr".to_string(), "on".to_string()]),
+                None => {}
+            }
+        }
+        AudioCodecChoice::Flac => args.extend(["-c:a".to_string(), "flac".to_string()]),
+        AudioCodecChoice::Alac => args.extend(["-c:a".to_string(), "alac".to_string()]),
+        AudioCodecChoice::Pcm => args.extend(["-c:a".to_string(), pcm_encoder(info)]),
+        AudioCodecChoice::Auto => args.extend(["-b:a".to_string(), kbps(128)]),
+    }
+    if let Some(rate) = options.sample_rate {
+        args.extend(["-ar".to_string(), rate.to_string()]);
+    }
+    match options.channels {
+        Some(ChannelLayout::Mono) => args.extend(["-ac".to_string(), "1".to_string()]),
+        Some(ChannelLayout::Stereo) => args.extend(["-ac".to_string(), "2".to_string()]),
+        None => {}
+    }
+
+    if let Some(cover) = info.video.iter().find(|v| v.attached_pic) {
+        if holds_cover(container, &cover.codec) {
+            args.extend([
+                "-map".to_string(),
+                format!("0:{}", cover.index),
+                "-c:v".to_string(),
+                "copy".to_string(),
+                "-disposition:v:0".to_string(),
+                "attached_pic".to_string(),
+            ]);
+        } else {
+            plan.note = Some(format!(
+                "⚠️ The cover art can't be stored in a .{} file, leaving it out",
+                container
+            ));
+        }
+    }
+
+    if preset.metadata == MetadataPolicy::Keep {
+        let ogg_source = info.format_name.split(',').any(|f| f == "ogg");
+        let ogg_output = matches!(container, "ogg" | "opus");
+        if ogg_source && !ogg_output {
+            // Ogg comments live on the audio stream, the other containers want them on the file
+            args.extend(["-map_metadata".to_string(), format!("0:s:{}", first.index)]);
+        } else if ogg_output && !ogg_source {
+            args.extend(["-map_metadata:s:a:0".to_string(), "0:g".to_string()]);
+        }
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::default_preset;
+    use crate::testing::{planning, strings, Script, ScriptedRunner};
+
+    // MP3 with a JPEG cover, tagged with ID3
+    const PROBE_MP3_COVER: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_type": "audio", "codec_name": "mp3", "channels": 2, "sample_rate": "44100", "bit_rate": "320000" },
+        { "index": 1, "codec_type": "video", "codec_name": "mjpeg", "width": 500, "height": 500, "disposition": { "attached_pic": 1 } }
+      ],
+      "format": { "format_name": "mp3", "duration": "180.000000", "size": "7200000", "tags": { "title": "Song", "artist": "Band" } }
+    }"#;
+
+    // Audio planning never runs a tool, the runner only stands in for the engine
+    fn audio_plan(container: &str, options: AudioOptions) -> Result<AudioPlan> {
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+        planning(PROBE_MP3_COVER, runner, |_, info| {
+            plan(container, info, &default_preset(), &options)
+        })
+    }
+
+    #[test]
+    fn flac_keeps_the_cover_art() {
+        let plan = audio_plan("flac", AudioOptions::default()).unwrap();
+
+        assert_eq!(
+            plan.args,
+            strings(&[
+                "-map",
+                "0:0",
+                "-c:a",
+                "flac",
+                "-map",
+                "0:1",
+                "-c:v",
+                "copy",
+                "-disposition:v:0",
+                "attached_pic"
+            ])
+        );
+        assert_eq!(plan.note, None);
+    }
+
+    #[test]
+    fn opus_cbr_mono_moves_tags_onto_the_stream() {
+        let options = AudioOptions {
+            mode: Some(BitrateMode::Cbr),
+            bitrate_kbps: Some(48),
+            sample_rate: Some(24000),
+            channels: Some(ChannelLayout::Mono),
+            ..Default::default()
+        };
+
+        let plan = audio_plan("opus", options).unwrap();
+
+        assert_eq!(
+            plan.args,
+            strings(&[
+                "-map",
+                "0:0",
+                "-c:a",
+                "libopus",
+                "-b:a",
+                "48k",
+                "-vbr",
+                "off",
+                "-ar",
+                "24000",
+                "-ac",
+                "1",
+                "-map_metadata:s:a:0",
+                "0:g"
+            ])
+        );
+        // Ogg can't hold the cover
+        assert!(plan.note.is_some());
+    }
+
+    #[test]
+    fn mp3_vbr_picks_the_nearest_lame_level() {
+        let plan = audio_plan(
+            "mp3",
+            AudioOptions {
+                mode: Some(BitrateMode::Vbr),
+                bitrate_kbps: Some(128),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.args[2..6],
+            strings(&["-c:a", "libmp3lame", "-q:a", "5"])
+        );
+    }
+
+    #[test]
+    fn options_the_container_cant_take_are_rejected() {
+        let alac_in_wav = audio_plan(
+            "wav",
+            AudioOptions {
+                codec: AudioCodecChoice::Alac,
+                ..Default::default()
+            },
+        );
+        let flac_bitrate = audio_plan(
+            "flac",
+            AudioOptions {
+                bitrate_kbps: Some(320),
+                ..Default::default()
+            },
+        );
+        let opus_44k = audio_plan(
+            "ogg",
+            AudioOptions {
+                sample_rate: Some(44100),
+                ..Default::default()
+            },
+        );
+        let aac_vbr = audio_plan(
+            "m4a",
+            AudioOptions {
+                mode: Some(BitrateMode::Vbr),
+                ..Default::default()
+            },
+        );
+
+        assert!(matches!(
+            alac_in_wav,
+            Err(CompressError::UnsupportedFormat { .. })
+        ));
+        assert!(matches!(
+            flac_bitrate,
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            opus_44k,
+            Err(CompressError::UnsupportedFormat { .. })
+        ));
+        assert!(matches!(
+            aac_vbr,
+            Err(CompressError::UnsupportedFormat { .. })
+        ));
+    }
+}
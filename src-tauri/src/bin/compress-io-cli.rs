@@ -0,0 +1,4 @@
+// Headless compressor, see src/cli.rs
+fn main() {
+    std::process::exit(universal_compressor_lib::cli::main());
+}
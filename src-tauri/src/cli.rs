@@ -0,0 +1,925 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::audio::{AudioCodecChoice, AudioOptions};
+use crate::edit::{EditList, KeepRange};
+use crate::engine::{ai_engine_binary, Engine, EventSink, NativeRunner, Tool};
+use crate::error::{CompressError, Result};
+use crate::ladder::{Rendition, StreamingOptions};
+use crate::loudness::LoudnessTarget;
+use crate::pipeline::VideoOptions;
+use crate::process::JobScope;
+use crate::quality::{QualityMetric, QualityTarget};
+use crate::subtitles::{SubtitleMode, SubtitleOptions};
+use crate::transform::{CropRect, TransformOptions};
+use crate::{hardware, pipeline, probe};
+
+// --- compress-io-cli ---
+// Headless front end for batch jobs and CI. Runs the same pipelines as the desktop app, with
+// ffmpeg/ffprobe taken from the flags or PATH instead of the bundled sidecars.
+// stdout is JSON only: one result object at the end, preceded by NDJSON progress events with --progress.
+
+const USAGE: &str = "\
+Usage: compress-io-cli [global options] <command> [options]
+
+Commands:
+  video <input> <output>          --gpu --preset ID --codec auto|h264|hevc|av1|vp9 --profile baseline|main|high
+                                  --level 3.0-5.2 --target-size-kb N --max-width N --max-height N --max-fps N
+                                  --vmaf SCORE | --ssim SCORE  (search the CRF that reaches it)
+                                  --start T --end T | --keep T-T,T-T...  --append FILE[{sep}FILE...]
+                                  --hdr preserve|sdr  --parallel N  (encode N segments at once on the CPU)
+                                  --subtitles FILE | --subtitle-track N  --burn  --sub-lang eng --sub-title T --sub-default
+                                  --rotate 90|180|270 --hflip --vflip --crop W:H:X:Y | --auto-crop
+                                  --loudness LUFS (-14, -16, -23...) --true-peak DBTP
+  stream <input> <folder>         --gpu --preset ID --ladder 1080:5000,720:2800... (short side:kbps) --segment SECONDS
+                                  --audio-kbps N --dash  (HLS ladder, plus a DASH manifest with --dash)
+  image <input> <output>          --width N --height N --preset ID --target-size-kb N
+  audio <input> <output>          --preset ID --codec auto|mp3|aac|opus|flac|alac|pcm --bitrate-mode cbr|vbr
+                                  --bitrate KBPS --sample-rate HZ --channels mono|stereo
+                                  --loudness LUFS --true-peak DBTP
+  enhance-image <input> <output>  --scale N --format EXT --model general|anime --tile N --hyper-detail
+  enhance-video <input> <output>  --scale N --model general|anime --fps N --denoise --stabilize --hyper-detail --tile N --gpu
+  probe <input>
+  hardware                        --refresh
+  presets
+
+Global options:
+  --ffmpeg PATH      ffmpeg binary (default: ffmpeg on PATH)
+  --ffprobe PATH     ffprobe binary (default: next to --ffmpeg, then PATH)
+  --ai-engine DIR    folder with realesrgan-ncnn-vulkan and its models
+  --presets FILE     user preset file to load (as exported by the app)
+  --progress         print job-progress events as JSON lines before the result
+  --verbose          copy ffmpeg / AI engine log lines to stderr
+  --help
+
+Times are seconds or [hh:]mm:ss(.ms). --append takes the OS path list separator ({sep}).
+
+Exit codes: 0 ok, 1 internal error, 2 bad usage or request, 3 input not found,
+4 unsupported input or settings, 5 encoder or AI engine unavailable,
+6 ffmpeg/ffprobe/AI engine failed, 7 I/O error or disk full, 130 cancelled";
+
+const SWITCHES: &[&str] = &[
+    "gpu",
+    "dash",
+    "burn",
+    "sub-default",
+    "hflip",
+    "vflip",
+    "auto-crop",
+    "hyper-detail",
+    "denoise",
+    "stabilize",
+    "refresh",
+    "progress",
+    "verbose",
+    "help",
+];
+
+struct CliEvents {
+    progress: bool,
+    verbose: bool,
+}
+
+impl EventSink for CliEvents {
+    fn emit(&self, event: &str, payload: Value) {
+        match event {
+            "job-progress" if self.progress => {
+                println!("{}", json!({ "event": event, "payload": payload }))
+            }
+            "ffmpeg-progress" | "enhance-progress" if self.verbose => {
+                if let Some(line) = payload.as_str() {
+                    eprintln!("{}", line);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Args {
+    command: String,
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(raw: Vec<String>) -> Result<Args> {
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+        let mut iter = raw.into_iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(flag) => {
+                    let (name, value) = match flag.split_once('=') {
+                        Some((name, value)) => (name.to_string(), value.to_string()),
+                        None if SWITCHES.contains(&flag) => (flag.to_string(), "true".to_string()),
+                        None => {
+                            let value = iter.next().ok_or_else(|| {
+                                CompressError::invalid(format!("--{} needs a value", flag))
+                            })?;
+                            (flag.to_string(), value)
+                        }
+                    };
+                    options.insert(name, value);
+                }
+                None => positional.push(arg),
+            }
+        }
+        let command = if positional.is_empty() {
+            String::new()
+        } else {
+            positional.remove(0)
+        };
+        Ok(Args {
+            command,
+            positional,
+            options,
+        })
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options
+            .get(name)
+            .map(|v| v != "false")
+            .unwrap_or(false)
+    }
+
+    fn opt(&self, name: &str) -> Option<String> {
+        self.options.get(name).cloned()
+    }
+
+    fn opt_or(&self, name: &str, default: &str) -> String {
+        self.opt(name).unwrap_or_else(|| default.to_string())
+    }
+
+    fn number(&self, name: &str) -> Result<Option<f64>> {
+        match self.options.get(name) {
+            None => Ok(None),
+            Some(v) => v.parse::<f64>().map(Some).map_err(|_| {
+                CompressError::invalid(format!("--{} expects a number, got '{}'", name, v))
+            }),
+        }
+    }
+
+    // Option values that are serde enum names (--codec, --profile)
+    fn choice<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+        expected: &str,
+    ) -> Result<Option<T>> {
+        match self.options.get(name) {
+            None => Ok(None),
+            Some(v) => serde_json::from_value(Value::String(v.to_lowercase()))
+                .map(Some)
+                .map_err(|_| {
+                    CompressError::invalid(format!(
+                        "Unknown --{} '{}', expected {}",
+                        name, v, expected
+                    ))
+                }),
+        }
+    }
+
+    // --codec / --profile / --level, same names as in presets
+    fn video_options(&self) -> Result<VideoOptions> {
+        Ok(VideoOptions {
+            codec: self
+                .choice("codec", "auto, h264, hevc, av1 or vp9")?
+                .unwrap_or_default(),
+            h264_profile: self.choice("profile", "baseline, main or high")?,
+            h264_level: self.opt("level"),
+            max_width: self.number("max-width")?.map(|n| n as u32),
+            max_height: self.number("max-height")?.map(|n| n as u32),
+            max_fps: self.number("max-fps")?,
+            target_quality: self.quality_target()?,
+            edit: self.edit_list()?,
+            hdr: self.choice("hdr", "preserve or sdr")?.unwrap_or_default(),
+            parallel_segments: self.number("parallel")?.map(|n| n as u32),
+            subtitles: self.subtitle_options()?,
+            transform: self.transform_options()?,
+            loudness: self.loudness_target()?,
+        })
+    }
+
+    fn audio_options(&self) -> Result<AudioOptions> {
+        Ok(AudioOptions {
+            codec: self
+                .choice::<AudioCodecChoice>("codec", "auto, mp3, aac, opus, flac, alac or pcm")?
+                .unwrap_or_default(),
+            mode: self.choice("bitrate-mode", "cbr or vbr")?,
+            bitrate_kbps: self.number("bitrate")?.map(|n| n as u32),
+            sample_rate: self.number("sample-rate")?.map(|n| n as u32),
+            channels: self.choice("channels", "mono or stereo")?,
+            loudness: self.loudness_target()?,
+        })
+    }
+
+    // --loudness -16 normalizes in two passes, --true-peak only means something with it
+    fn loudness_target(&self) -> Result<Option<LoudnessTarget>> {
+        let true_peak = self.number("true-peak")?;
+        match self.number("loudness")? {
+            Some(integrated) => Ok(Some(LoudnessTarget {
+                integrated,
+                true_peak,
+            })),
+            None if true_peak.is_some() => {
+                Err(CompressError::invalid("--true-peak needs --loudness"))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // --crop W:H:X:Y like ffmpeg's crop filter, in the picture as players show it
+    fn transform_options(&self) -> Result<TransformOptions> {
+        let crop = match self.opt("crop") {
+            Some(rect) => {
+                let invalid = || {
+                    CompressError::invalid(format!(
+                        "--crop '{}' should look like WIDTH:HEIGHT:X:Y",
+                        rect
+                    ))
+                };
+                let values: Vec<u32> = rect
+                    .split(':')
+                    .map(|v| v.trim().parse().map_err(|_| invalid()))
+                    .collect::<Result<_>>()?;
+                match values.as_slice() {
+                    [width, height, x, y] => Some(CropRect {
+                        x: *x,
+                        y: *y,
+                        width: *width,
+                        height: *height,
+                    }),
+                    _ => return Err(invalid()),
+                }
+            }
+            None => None,
+        };
+        Ok(TransformOptions {
+            rotate: self.number("rotate")?.map(|n| n as u32).unwrap_or(0),
+            flip_horizontal: self.flag("hflip"),
+            flip_vertical: self.flag("vflip"),
+            crop,
+            auto_crop: self.flag("auto-crop"),
+        })
+    }
+
+    // --subtitles FILE or --subtitle-track N (probe index), soft unless --burn
+    fn subtitle_options(&self) -> Result<Option<SubtitleOptions>> {
+        let file = self.opt("subtitles");
+        let track = self.number("subtitle-track")?.map(|n| n as u32);
+        if file.is_none() && track.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(SubtitleOptions {
+            file,
+            track,
+            mode: if self.flag("burn") {
+                SubtitleMode::Burn
+            } else {
+                SubtitleMode::Soft
+            },
+            language: self.opt("sub-lang"),
+            title: self.opt("sub-title"),
+            default: self.flag("sub-default"),
+        }))
+    }
+
+    // --vmaf or --ssim; the CRF search aims at one metric
+    fn quality_target(&self) -> Result<Option<QualityTarget>> {
+        match (self.number("vmaf")?, self.number("ssim")?) {
+            (Some(_), Some(_)) => Err(CompressError::invalid(
+                "--vmaf and --ssim can't be used together, pick one metric",
+            )),
+            (Some(score), None) => Ok(Some(QualityTarget {
+                metric: QualityMetric::Vmaf,
+                score,
+            })),
+            (None, Some(score)) => Ok(Some(QualityTarget {
+                metric: QualityMetric::Ssim,
+                score,
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // --ladder 1080:5000,720:2800 is short side:video kbps per rendition
+    fn streaming_options(&self) -> Result<StreamingOptions> {
+        let mut options = StreamingOptions {
+            segment_seconds: self.number("segment")?,
+            dash: self.flag("dash"),
+            audio_kbps: self.number("audio-kbps")?.map(|n| n as u32),
+            ..Default::default()
+        };
+        if let Some(ladder) = self.opt("ladder") {
+            for rung in ladder.split(',') {
+                let invalid = || {
+                    CompressError::invalid(format!(
+                        "--ladder rendition '{}' should look like HEIGHT:KBPS",
+                        rung
+                    ))
+                };
+                let (height, kbps) = rung.split_once(':').ok_or_else(invalid)?;
+                options.renditions.push(Rendition {
+                    height: height.trim().parse().map_err(|_| invalid())?,
+                    video_kbps: kbps.trim().parse().map_err(|_| invalid())?,
+                });
+            }
+        }
+        Ok(options)
+    }
+
+    // --start/--end trim, or --keep for several ranges; --append joins more files after the input
+    fn edit_list(&self) -> Result<EditList> {
+        let mut edit = EditList::default();
+        match self.opt("keep") {
+            Some(ranges) => {
+                for range in ranges.split(',') {
+                    let (start, end) = range.split_once('-').ok_or_else(|| {
+                        CompressError::invalid(format!(
+                            "--keep range '{}' should look like START-END",
+                            range
+                        ))
+                    })?;
+                    let end = if end.trim().is_empty() {
+                        None
+                    } else {
+                        Some(parse_time(end)?)
+                    };
+                    edit.keep.push(KeepRange {
+                        start: parse_time(start)?,
+                        end,
+                    });
+                }
+            }
+            None => {
+                let end = self.opt("end").map(|t| parse_time(&t)).transpose()?;
+                if let Some(start) = self.opt("start").map(|t| parse_time(&t)).transpose()? {
+                    edit.keep.push(KeepRange { start, end });
+                } else if end.is_some() {
+                    edit.keep.push(KeepRange { start: 0.0, end });
+                }
+            }
+        }
+        if let Some(files) = self.opt("append") {
+            edit.append = std::env::split_paths(&files)
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+        }
+        Ok(edit)
+    }
+
+    // <input> <output>, both required
+    fn files(&self) -> Result<(String, String)> {
+        match self.positional.as_slice() {
+            [input, output] => Ok((input.clone(), output.clone())),
+            _ => Err(CompressError::invalid(format!(
+                "{} expects <input> <output>",
+                self.command
+            ))),
+        }
+    }
+}
+
+// "90", "1:30" or "00:01:30.5" -> seconds
+fn parse_time(value: &str) -> Result<f64> {
+    let invalid = || {
+        CompressError::invalid(format!(
+            "Invalid time '{}', expected seconds or [hh:]mm:ss",
+            value
+        ))
+    };
+    let mut seconds = 0.0;
+    for part in value.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().map_err(|_| invalid())?;
+    }
+    Ok(seconds)
+}
+
+fn exe_name(name: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(exe_name(name)))
+        .find(|p| p.is_file())
+}
+
+// Explicit flag first, then a binary sitting next to ffmpeg (static builds ship both), then PATH.
+// Falls back to the bare name so the spawn error names the missing tool.
+fn locate(name: &str, explicit: Option<String>, sibling_of: Option<&Path>) -> PathBuf {
+    if let Some(path) = explicit {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = sibling_of.and_then(|p| p.parent()) {
+        let candidate = dir.join(exe_name(name));
+        if candidate.is_file() {
+            return candidate;
+        }
+    }
+    find_on_path(name).unwrap_or_else(|| PathBuf::from(exe_name(name)))
+}
+
+// Without --ai-engine, look for the app's layout next to the CLI binary.
+fn default_ai_engine_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.parent()
+                .map(|dir| dir.join("binaries").join("ai_engine"))
+        })
+        .unwrap_or_else(|| PathBuf::from("ai_engine"))
+}
+
+fn build_engine(args: &Args) -> Engine {
+    let ffmpeg = locate(Tool::Ffmpeg.name(), args.opt("ffmpeg"), None);
+    let ffprobe = locate(Tool::Ffprobe.name(), args.opt("ffprobe"), Some(&ffmpeg));
+    let ai_engine_dir = args
+        .opt("ai-engine")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_ai_engine_dir);
+    let events = CliEvents {
+        progress: args.flag("progress"),
+        verbose: args.flag("verbose"),
+    };
+    let runner = NativeRunner {
+        ffmpeg,
+        ffprobe,
+        ai_engine: ai_engine_binary(&ai_engine_dir),
+    };
+    let engine = Engine::new(
+        Box::new(runner),
+        Box::new(events),
+        std::env::temp_dir(),
+        ai_engine_dir,
+    );
+    if let Some(file) = args.opt("presets") {
+        engine.presets.load(PathBuf::from(file));
+    }
+    engine
+}
+
+fn output_result(output: &str) -> Value {
+    let size = std::fs::metadata(output).map(|m| m.len()).ok();
+    json!({ "ok": true, "output": output, "outputSize": size })
+}
+
+async fn execute(engine: &Engine, args: &Args) -> Result<Value> {
+    // Each run is one job, registered up front so Ctrl+C reaches everything it starts
+    let scope = JobScope::new(engine, None);
+    match args.command.as_str() {
+        "video" => {
+            let (input, output) = args.files()?;
+            let preset = args.opt("preset");
+            let auto_gpu = args.flag("gpu");
+            let options = args.video_options()?;
+            let report = match args.number("target-size-kb")? {
+                Some(kb) => Some(
+                    pipeline::compress_video_target_size(
+                        &scope,
+                        input,
+                        output.clone(),
+                        kb,
+                        auto_gpu,
+                        preset,
+                        options,
+                    )
+                    .await?,
+                ),
+                None => {
+                    pipeline::compress_video(
+                        &scope,
+                        input,
+                        output.clone(),
+                        auto_gpu,
+                        preset,
+                        options,
+                    )
+                    .await?
+                }
+            };
+            let mut result = output_result(&output);
+            if let Some(size) = report.as_ref().and_then(|r| r.size.as_ref()) {
+                result["sizeReport"] = json!(size);
+            }
+            if let Some(loudness) = report.and_then(|r| r.loudness) {
+                result["loudness"] = json!(loudness);
+            }
+            Ok(result)
+        }
+        "stream" => {
+            let (input, output) = args.files()?;
+            let options = args.streaming_options()?;
+            let manifest = pipeline::compress_video_streaming(
+                &scope,
+                input,
+                output.clone(),
+                args.flag("gpu"),
+                args.opt("preset"),
+                options,
+            )
+            .await?;
+            Ok(json!({ "ok": true, "output": output, "streaming": manifest }))
+        }
+        "image" => {
+            let (input, output) = args.files()?;
+            let width = args.opt_or("width", "0");
+            let height = args.opt_or("height", "0");
+            let preset = args.opt("preset");
+            match args.number("target-size-kb")? {
+                Some(kb) => {
+                    pipeline::compress_image_target_size(
+                        &scope,
+                        input,
+                        output.clone(),
+                        kb,
+                        width,
+                        height,
+                        preset,
+                    )
+                    .await?
+                }
+                None => {
+                    pipeline::compress_image(&scope, input, output.clone(), width, height, preset)
+                        .await?
+                }
+            }
+            Ok(output_result(&output))
+        }
+        "audio" => {
+            let (input, output) = args.files()?;
+            let loudness = pipeline::compress_audio(
+                &scope,
+                input,
+                output.clone(),
+                args.opt("preset"),
+                args.audio_options()?,
+            )
+            .await?;
+            let mut result = output_result(&output);
+            if let Some(loudness) = loudness {
+                result["loudness"] = json!(loudness);
+            }
+            Ok(result)
+        }
+        "enhance-image" => {
+            let (input, output) = args.files()?;
+            let format = args.opt("format").unwrap_or_else(|| {
+                Path::new(&output)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("png")
+                    .to_lowercase()
+            });
+            pipeline::enhance_image(
+                &scope,
+                input,
+                output.clone(),
+                args.opt_or("scale", "4"),
+                format,
+                args.opt_or("model", "general"),
+                false,
+                args.flag("hyper-detail"),
+                args.opt_or("tile", "0"),
+            )
+            .await?;
+            Ok(output_result(&output))
+        }
+        "enhance-video" => {
+            let (input, output) = args.files()?;
+            pipeline::enhance_video(
+                &scope,
+                input,
+                output.clone(),
+                args.opt_or("scale", "2"),
+                args.opt_or("model", "general"),
+                false,
+                args.opt_or("fps", "0"),
+                args.flag("denoise"),
+                args.flag("stabilize"),
+                args.flag("hyper-detail"),
+                args.opt_or("tile", "0"),
+                args.flag("gpu"),
+            )
+            .await?;
+            Ok(output_result(&output))
+        }
+        "probe" => {
+            let input = args
+                .positional
+                .first()
+                .ok_or_else(|| CompressError::invalid("probe expects <input>"))?;
+            if !Path::new(input).exists() {
+                return Err(CompressError::input_not_found(input));
+            }
+            let info = probe::probe(&scope, input).await?;
+            Ok(json!({ "ok": true, "media": info }))
+        }
+        "hardware" => {
+            let report = hardware::report(engine, args.flag("refresh")).await?;
+            Ok(json!({ "ok": true, "hardware": report }))
+        }
+        "presets" => Ok(json!({ "ok": true, "presets": engine.presets.all() })),
+        "" => Err(CompressError::invalid("No command given, see --help")),
+        other => Err(CompressError::invalid(format!(
+            "Unknown command '{}', see --help",
+            other
+        ))),
+    }
+}
+
+// Ctrl+C kills the running ffmpeg/AI engine processes and drops the job, which removes its
+// temp files and partial output, then exits with 130
+async fn interruptible(engine: &Engine, args: &Args) -> Result<Value> {
+    tokio::select! {
+        result = execute(engine, args) => result,
+        Ok(()) = tokio::signal::ctrl_c() => {
+            engine.registry.cancel_all();
+            Err(CompressError::Cancelled)
+        }
+    }
+}
+
+fn exit_code(error: &CompressError) -> i32 {
+    match error {
+        CompressError::InvalidRequest { .. } | CompressError::JobNotFound { .. } => 2,
+        CompressError::InputNotFound { .. } => 3,
+        CompressError::UnsupportedFormat { .. }
+        | CompressError::MissingStream { .. }
+        | CompressError::TargetTooSmall { .. }
+        | CompressError::PresetNotFound { .. } => 4,
+        CompressError::EncoderUnavailable { .. } | CompressError::AiEngineMissing { .. } => 5,
+        CompressError::FfmpegFailed { .. }
+        | CompressError::ProbeFailed { .. }
+        | CompressError::AiEngineFailed { .. } => 6,
+        CompressError::DiskFull | CompressError::Io { .. } => 7,
+        CompressError::Cancelled => 130,
+        CompressError::Internal { .. } => 1,
+    }
+}
+
+fn fail(error: CompressError) -> i32 {
+    println!("{}", json!({ "ok": false, "error": error }));
+    exit_code(&error)
+}
+
+// Entry point of the compress-io-cli binary; returns the process exit code.
+pub fn main() -> i32 {
+    let args = match Args::parse(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => return fail(e),
+    };
+    if args.flag("help") || args.command == "help" {
+        let sep = if cfg!(target_os = "windows") {
+            ";"
+        } else {
+            ":"
+        };
+        println!("{}", USAGE.replace("{sep}", sep));
+        return 0;
+    }
+    let engine = build_engine(&args);
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => return fail(e.into()),
+    };
+    match runtime.block_on(interruptible(&engine, &args)) {
+        Ok(result) => {
+            println!("{}", result);
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::ChannelLayout;
+    use crate::encoders::CodecChoice;
+
+    fn args(raw: &[&str]) -> Result<Args> {
+        Args::parse(raw.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn switches_take_no_value_and_valued_flags_take_the_next_argument() {
+        let args = args(&[
+            "video", "--gpu", "in.mov", "--preset", "web", "out.mp4", "--hflip",
+        ])
+        .unwrap();
+
+        assert_eq!(args.command, "video");
+        assert_eq!(
+            args.files().unwrap(),
+            ("in.mov".to_string(), "out.mp4".to_string())
+        );
+        assert!(args.flag("gpu") && args.flag("hflip") && !args.flag("vflip"));
+        assert_eq!(args.opt("preset").as_deref(), Some("web"));
+    }
+
+    #[test]
+    fn flag_equals_value_works_for_both_kinds() {
+        let args = args(&[
+            "video",
+            "in.mov",
+            "out.mp4",
+            "--max-width=1280",
+            "--gpu=false",
+            "--sub-title=Director's cut: v2",
+        ])
+        .unwrap();
+
+        assert_eq!(args.number("max-width").unwrap(), Some(1280.0));
+        assert!(!args.flag("gpu"));
+        assert_eq!(args.opt("sub-title").as_deref(), Some("Director's cut: v2"));
+    }
+
+    #[test]
+    fn negative_values_are_not_mistaken_for_flags() {
+        let args = args(&[
+            "audio",
+            "in.wav",
+            "out.mp3",
+            "--loudness",
+            "-16",
+            "--true-peak",
+            "-1.5",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.loudness_target().unwrap(),
+            Some(LoudnessTarget {
+                integrated: -16.0,
+                true_peak: Some(-1.5)
+            })
+        );
+        assert_eq!(args.positional, vec!["in.wav", "out.mp3"]);
+    }
+
+    #[test]
+    fn valued_flag_at_the_end_needs_its_value() {
+        assert!(matches!(
+            args(&["video", "in.mov", "out.mp4", "--preset"]),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn choice_reads_serde_names_case_insensitively() {
+        let args = args(&[
+            "audio",
+            "in.wav",
+            "out.ogg",
+            "--codec",
+            "OPUS",
+            "--channels",
+            "mono",
+            "--bitrate-mode",
+            "abr",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.audio_options().map(|o| o.codec),
+            Err(CompressError::invalid(
+                "Unknown --bitrate-mode 'abr', expected cbr or vbr"
+            ))
+        );
+        assert_eq!(
+            args.choice::<AudioCodecChoice>("codec", "").unwrap(),
+            Some(AudioCodecChoice::Opus)
+        );
+        assert_eq!(
+            args.choice::<ChannelLayout>("channels", "").unwrap(),
+            Some(ChannelLayout::Mono)
+        );
+        assert_eq!(args.choice::<CodecChoice>("level", "").unwrap(), None);
+        // Opus is an audio codec only
+        assert!(args.video_options().is_err());
+    }
+
+    #[test]
+    fn transform_options_parse_the_crop_rectangle() {
+        let args = args(&[
+            "video",
+            "in.mov",
+            "out.mp4",
+            "--crop",
+            "1440:1080:240:0",
+            "--rotate",
+            "90",
+            "--vflip",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.transform_options().unwrap(),
+            TransformOptions {
+                rotate: 90,
+                flip_vertical: true,
+                crop: Some(CropRect {
+                    x: 240,
+                    y: 0,
+                    width: 1440,
+                    height: 1080
+                }),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_crop_is_a_bad_request() {
+        for crop in [
+            "1440:1080:240",
+            "1440x1080",
+            "1440:1080:-2:0",
+            "1440:1080:240:0:1",
+            "",
+        ] {
+            let args = args(&["video", "in.mov", "out.mp4", "--crop", crop]).unwrap();
+            assert!(
+                matches!(
+                    args.transform_options(),
+                    Err(CompressError::InvalidRequest { .. })
+                ),
+                "--crop {}",
+                crop
+            );
+        }
+    }
+
+    #[test]
+    fn quality_target_takes_one_metric() {
+        let ssim = args(&["video", "in.mov", "out.mp4", "--ssim", "0.98"]).unwrap();
+        assert_eq!(
+            ssim.quality_target().unwrap(),
+            Some(QualityTarget {
+                metric: QualityMetric::Ssim,
+                score: 0.98
+            })
+        );
+
+        let both = args(&[
+            "video", "in.mov", "out.mp4", "--vmaf", "93", "--ssim", "0.98",
+        ])
+        .unwrap();
+        assert!(matches!(
+            both.quality_target(),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn exit_codes_follow_the_usage_text() {
+        let codes: Vec<i32> = [
+            CompressError::internal("bug"),
+            CompressError::invalid("bad"),
+            CompressError::JobNotFound { id: 1 },
+            CompressError::input_not_found("in.mov"),
+            CompressError::unsupported("no"),
+            CompressError::MissingStream {
+                kind: "video".to_string(),
+            },
+            CompressError::TargetTooSmall {
+                detail: String::new(),
+            },
+            CompressError::PresetNotFound {
+                id: "x".to_string(),
+            },
+            CompressError::EncoderUnavailable {
+                encoder: "h264_nvenc".to_string(),
+            },
+            CompressError::AiEngineMissing {
+                path: String::new(),
+            },
+            CompressError::from_ffmpeg(Some(1), vec![]),
+            CompressError::ProbeFailed {
+                detail: String::new(),
+            },
+            CompressError::AiEngineFailed { exit_code: None },
+            CompressError::DiskFull,
+            CompressError::Io {
+                detail: String::new(),
+            },
+            CompressError::Cancelled,
+        ]
+        .iter()
+        .map(exit_code)
+        .collect();
+
+        assert_eq!(
+            codes,
+            vec![1, 2, 2, 3, 4, 4, 4, 4, 5, 5, 6, 6, 6, 7, 7, 130]
+        );
+    }
+}
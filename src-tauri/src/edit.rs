@@ -0,0 +1,406 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::{CompressError, Result};
+use crate::probe::{self, MediaInfo};
+use crate::process::JobScope;
+
+// --- Edit lists: trim, cut and join ---
+// A single trim is done with input seeking (-ss/-t before -i), which is frame accurate when
+// re-encoding and leaves stream mapping alone. Several keep-ranges or appended files go through a
+// trim/atrim + concat filter graph instead, so audio is cut at the same timestamps as the picture.
+
+// Seconds into the main input. No end keeps everything after `start`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeepRange {
+    pub start: f64,
+    #[serde(default)]
+    pub end: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EditList {
+    // Parts of the main input to keep, joined in this order. Empty keeps the whole file.
+    pub keep: Vec<KeepRange>,
+    // Files joined after the main input, in full
+    pub append: Vec<String>,
+}
+
+// Joined audio is resampled to one format, concat needs every segment to match
+const JOIN_SAMPLE_RATE: u32 = 48000;
+
+struct Segment {
+    input: usize,
+    video: u32,
+    audio: Option<u32>,
+    // Trim window inside the input, None for the whole file
+    range: Option<(f64, f64)>,
+    duration: f64,
+}
+
+pub struct EditPlan {
+    // Seeking options and -i for every input, in order
+    pub input_args: Vec<String>,
+    // The -ss/-t of a single trim, for other inputs that have to line up with the main one
+    pub seek_args: Vec<String>,
+    // Where a single trim starts in the main input
+    pub start: f64,
+    // Output duration after cutting and joining, what progress and target-size math work with
+    pub duration: f64,
+    pub total_frames: Option<u64>,
+    // Empty unless the edit needs the filter graph
+    segments: Vec<Segment>,
+    // Appended files are scaled and padded to the main input's frame so concat accepts them
+    normalize: bool,
+    width: u32,
+    height: u32,
+    fps: f64,
+}
+
+pub fn fmt_time(seconds: f64) -> String {
+    format!("{:.3}", seconds)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+impl EditPlan {
+    // True when the edit runs through the concat graph and `filter_args` must be used instead of -vf
+    pub fn is_filtered(&self) -> bool {
+        !self.segments.is_empty()
+    }
+
+    // Number of -i in `input_args`, the index the next input gets
+    pub fn inputs(&self) -> usize {
+        self.input_args.iter().filter(|a| *a == "-i").count()
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.segments.iter().any(|s| s.audio.is_some())
+    }
+
+    // -filter_complex with the cut/join graph and the maps for its outputs. `video_filter` and
+    // `audio_filter` are chained after the join (scaling, GIF palette, loudness...) since -vf/-af
+    // can't be combined with the graph.
+    pub fn filter_args(
+        &self,
+        video_filter: Option<&str>,
+        audio_filter: Option<&str>,
+        with_audio: bool,
+    ) -> Vec<String> {
+        let audio = with_audio && self.has_audio();
+        let mut chains = vec![];
+        let mut pads = String::new();
+        for (n, seg) in self.segments.iter().enumerate() {
+            let mut video = vec![];
+            if let Some((start, end)) = seg.range {
+                video.push(format!(
+                    "trim=start={}:end={}",
+                    fmt_time(start),
+                    fmt_time(end)
+                ));
+            }
+            video.push("setpts=PTS-STARTPTS".to_string());
+            if self.normalize {
+                video.push(format!(
+                    "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps}",
+                    w = self.width, h = self.height, fps = self.fps
+                ));
+            }
+            chains.push(format!(
+                "[{}:{}]{}[v{}]",
+                seg.input,
+                seg.video,
+                video.join(","),
+                n
+            ));
+            pads.push_str(&format!("[v{}]", n));
+            if audio {
+                let chain = match seg.audio {
+                    Some(index) => {
+                        let mut parts = vec![];
+                        if let Some((start, end)) = seg.range {
+                            parts.push(format!(
+                                "atrim=start={}:end={}",
+                                fmt_time(start),
+                                fmt_time(end)
+                            ));
+                        }
+                        parts.push("asetpts=PTS-STARTPTS".to_string());
+                        if self.normalize {
+                            parts.push(format!(
+                                "aformat=sample_rates={}:channel_layouts=stereo",
+                                JOIN_SAMPLE_RATE
+                            ));
+                        }
+                        format!("[{}:{}]{}", seg.input, index, parts.join(","))
+                    }
+                    // Silence for files without sound, so every segment has both pads
+                    None => format!(
+                        "anullsrc=r={}:cl=stereo,atrim=duration={}",
+                        JOIN_SAMPLE_RATE,
+                        fmt_time(seg.duration)
+                    ),
+                };
+                chains.push(format!("{}[a{}]", chain, n));
+                pads.push_str(&format!("[a{}]", n));
+            }
+        }
+        let joined = if video_filter.is_some() {
+            "[vj]"
+        } else {
+            "[v]"
+        };
+        let audio_out = match (audio, audio_filter) {
+            (false, _) => "",
+            (true, Some(_)) => "[aj]",
+            (true, None) => "[a]",
+        };
+        chains.push(format!(
+            "{}concat=n={}:v=1:a={}{}{}",
+            pads,
+            self.segments.len(),
+            audio as u8,
+            joined,
+            audio_out
+        ));
+        if let Some(filter) = video_filter {
+            chains.push(format!("[vj]{}[v]", filter));
+        }
+        if let Some(filter) = audio_filter.filter(|_| audio) {
+            chains.push(format!("[aj]{}[a]", filter));
+        }
+
+        let mut args = vec![
+            "-filter_complex".to_string(),
+            chains.join(";"),
+            "-map".to_string(),
+            "[v]".to_string(),
+        ];
+        if audio {
+            args.extend(["-map".to_string(), "[a]".to_string()]);
+        }
+        args
+    }
+}
+
+fn check_range(range: &KeepRange, duration: f64) -> Result<(f64, f64)> {
+    let end = match range.end.or((duration > 0.0).then_some(duration)) {
+        Some(end) => end,
+        None => {
+            return Err(CompressError::ProbeFailed {
+                detail: "Could not determine video duration".to_string(),
+            })
+        }
+    };
+    if range.start < 0.0 || (duration > 0.0 && range.start >= duration) {
+        return Err(CompressError::invalid(format!(
+            "Start {}s is outside the video ({}s long)",
+            range.start,
+            fmt_time(duration)
+        )));
+    }
+    if end <= range.start {
+        return Err(CompressError::invalid(format!(
+            "Range {}s-{}s is empty",
+            range.start, end
+        )));
+    }
+    let end = if duration > 0.0 {
+        end.min(duration)
+    } else {
+        end
+    };
+    Ok((range.start, end))
+}
+
+// Works out the inputs, the output duration and (when needed) the segments of the concat graph.
+// Appended files are probed here, so a missing or audio-only file fails before ffmpeg starts.
+pub async fn plan(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    edit: &EditList,
+) -> Result<EditPlan> {
+    let video = info
+        .primary_video()
+        .ok_or_else(|| CompressError::MissingStream {
+            kind: "video".to_string(),
+        })?;
+    let fps = info.fps().unwrap_or(30.0);
+    // Appended files are fitted to the main input as it is shown, after autorotation
+    let (width, height) = video.display_size();
+    let frames = |duration: f64| Some((duration * fps).round() as u64);
+    let mut plan = EditPlan {
+        input_args: vec![],
+        seek_args: vec![],
+        start: 0.0,
+        duration: info.duration,
+        total_frames: info.total_frames(),
+        segments: vec![],
+        normalize: !edit.append.is_empty(),
+        // 4:2:0 needs even dimensions
+        width: width & !1,
+        height: height & !1,
+        fps,
+    };
+
+    if edit.append.is_empty() && edit.keep.len() <= 1 {
+        if let Some(range) = edit.keep.first() {
+            let (start, end) = check_range(range, info.duration)?;
+            plan.seek_args.extend(["-ss".to_string(), fmt_time(start)]);
+            if range.end.is_some() {
+                plan.seek_args
+                    .extend(["-t".to_string(), fmt_time(end - start)]);
+            }
+            plan.input_args.extend(plan.seek_args.clone());
+            plan.start = start;
+            plan.duration = end - start;
+            plan.total_frames = frames(plan.duration);
+        }
+        plan.input_args
+            .extend(["-i".to_string(), input.to_string()]);
+        return Ok(plan);
+    }
+
+    plan.input_args
+        .extend(["-i".to_string(), input.to_string()]);
+    let main_audio = info.audio.first().map(|a| a.index);
+    if edit.keep.is_empty() {
+        plan.segments.push(Segment {
+            input: 0,
+            video: video.index,
+            audio: main_audio,
+            range: None,
+            duration: info.duration,
+        });
+    }
+    for range in &edit.keep {
+        let (start, end) = check_range(range, info.duration)?;
+        plan.segments.push(Segment {
+            input: 0,
+            video: video.index,
+            audio: main_audio,
+            range: Some((start, end)),
+            duration: end - start,
+        });
+    }
+    for (n, path) in edit.append.iter().enumerate() {
+        if !Path::new(path).exists() {
+            return Err(CompressError::input_not_found(path));
+        }
+        let extra = probe::probe(scope, path).await?;
+        let extra_video = extra
+            .primary_video()
+            .ok_or_else(|| CompressError::MissingStream {
+                kind: "video".to_string(),
+            })?;
+        plan.input_args.extend(["-i".to_string(), path.clone()]);
+        plan.segments.push(Segment {
+            input: n + 1,
+            video: extra_video.index,
+            audio: extra.audio.first().map(|a| a.index),
+            range: None,
+            duration: extra.duration,
+        });
+    }
+    plan.duration = plan.segments.iter().map(|s| s.duration).sum();
+    plan.total_frames = frames(plan.duration);
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        block_on, planning, strings, Script, ScriptedRunner, TempDir, PROBE_1080P,
+    };
+
+    // Appended files probe as the same 1080p clip
+    fn edit_plan(edit: &EditList) -> Result<EditPlan> {
+        let runner = ScriptedRunner::new(|_, _| Script::stdout(PROBE_1080P));
+        planning(PROBE_1080P, runner, |scope, info| {
+            block_on(plan(scope, "in.mov", info, edit))
+        })
+    }
+
+    #[test]
+    fn single_trim_seeks_the_input() {
+        let plan = edit_plan(&EditList {
+            keep: vec![KeepRange {
+                start: 2.5,
+                end: Some(7.0),
+            }],
+            append: vec![],
+        })
+        .unwrap();
+
+        assert_eq!(
+            plan.input_args,
+            strings(&["-ss", "2.5", "-t", "4.5", "-i", "in.mov"])
+        );
+        assert_eq!(
+            (plan.start, plan.duration, plan.total_frames),
+            (2.5, 4.5, Some(135))
+        );
+        assert!(!plan.is_filtered());
+    }
+
+    #[test]
+    fn cut_and_append_joins_through_concat() {
+        let dir = TempDir::new("edit-concat");
+        let outro = dir.file("outro.mov", b"");
+        let edit = EditList {
+            keep: vec![
+                KeepRange {
+                    start: 0.0,
+                    end: Some(2.0),
+                },
+                KeepRange {
+                    start: 5.0,
+                    end: None,
+                },
+            ],
+            append: vec![outro.clone()],
+        };
+
+        let plan = edit_plan(&edit).unwrap();
+
+        assert_eq!(plan.input_args, strings(&["-i", "in.mov", "-i", &outro]));
+        assert_eq!(plan.duration, 17.0);
+        let norm = "scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30";
+        let graph = [
+            format!("[0:0]trim=start=0:end=2,setpts=PTS-STARTPTS,{}[v0]", norm),
+            "[0:1]atrim=start=0:end=2,asetpts=PTS-STARTPTS,aformat=sample_rates=48000:channel_layouts=stereo[a0]".to_string(),
+            format!("[0:0]trim=start=5:end=10,setpts=PTS-STARTPTS,{}[v1]", norm),
+            "[0:1]atrim=start=5:end=10,asetpts=PTS-STARTPTS,aformat=sample_rates=48000:channel_layouts=stereo[a1]".to_string(),
+            format!("[1:0]setpts=PTS-STARTPTS,{}[v2]", norm),
+            "[1:1]asetpts=PTS-STARTPTS,aformat=sample_rates=48000:channel_layouts=stereo[a2]".to_string(),
+            "[v0][a0][v1][a1][v2][a2]concat=n=3:v=1:a=1[v][a]".to_string(),
+        ]
+        .join(";");
+        assert_eq!(
+            plan.filter_args(None, None, true),
+            strings(&["-filter_complex", &graph, "-map", "[v]", "-map", "[a]"])
+        );
+    }
+
+    #[test]
+    fn empty_or_out_of_range_keeps_are_rejected() {
+        let keep = |start, end| EditList {
+            keep: vec![KeepRange { start, end }],
+            append: vec![],
+        };
+
+        assert!(matches!(
+            edit_plan(&keep(12.0, None)),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            edit_plan(&keep(4.0, Some(4.0))),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+}
@@ -0,0 +1,488 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// --- Encoder capability matrix ---
+// Every video encoder we can drive is described once here. Callers speak in one quality scale
+// (CRF on the x265 scale, lower is better) and each backend translates it to its own flag.
+
+// CRF 24 on the x265 scale, the quality every command used before encoders were configurable.
+pub const DEFAULT_QUALITY: u32 = 24;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+// Codec a job asks for. `Auto` keeps the preset's codec, which is H.264 for the platform presets
+// (web, email, Discord, WhatsApp) that need it to play everywhere and HEVC otherwise.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecChoice {
+    #[default]
+    Auto,
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+impl CodecChoice {
+    pub fn resolve(self, preset_codec: VideoCodec) -> VideoCodec {
+        match self {
+            CodecChoice::Auto => preset_codec,
+            CodecChoice::H264 => VideoCodec::H264,
+            CodecChoice::Hevc => VideoCodec::Hevc,
+            CodecChoice::Av1 => VideoCodec::Av1,
+            CodecChoice::Vp9 => VideoCodec::Vp9,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum H264Profile {
+    // Old phones and set-top boxes
+    Baseline,
+    Main,
+    High,
+}
+
+// Levels worth offering; below 3.0 nothing is bigger than a thumbnail.
+pub const H264_LEVELS: &[&str] = &[
+    "3.0", "3.1", "3.2", "4.0", "4.1", "4.2", "5.0", "5.1", "5.2",
+];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Vendor {
+    Software,
+    Nvidia,
+    Intel,
+    Amd,
+    Apple,
+}
+
+impl Vendor {
+    // NVENC/QSV/AMF don't exist on macOS and VideoToolbox only exists there,
+    // so there is no point probing them elsewhere.
+    pub fn available_on_this_platform(&self) -> bool {
+        match self {
+            Vendor::Software => true,
+            Vendor::Apple => cfg!(target_os = "macos"),
+            Vendor::Nvidia | Vendor::Intel | Vendor::Amd => !cfg!(target_os = "macos"),
+        }
+    }
+}
+
+// How an encoder is told "constant quality".
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityMode {
+    // -crf (x264, x265, libvpx, SVT-AV1, aom)
+    Crf,
+    // -cq with -b:v 0 (NVENC)
+    Cq,
+    // -global_quality (QSV)
+    GlobalQuality,
+    // -q:v, higher is better (VideoToolbox)
+    QScale,
+    // -rc cqp with fixed QPs (AMF)
+    Qp,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EncoderCaps {
+    // ffmpeg encoder name, as passed to -c:v
+    pub name: &'static str,
+    pub codec: VideoCodec,
+    pub vendor: Vendor,
+    pub quality_mode: QualityMode,
+    // native value = offset + scale * crf, clamped to quality_range
+    pub quality_scale: i32,
+    pub quality_offset: i32,
+    pub quality_range: (i32, i32),
+    // Flag that picks the speed/quality trade-off, if the encoder has one
+    pub preset_flag: Option<&'static str>,
+    pub presets: &'static [&'static str],
+    pub default_preset: Option<&'static str>,
+    pub two_pass: bool,
+    // Pixel format to request for 10-bit output, None when the encoder is 8-bit only
+    pub ten_bit_pix_fmt: Option<&'static str>,
+}
+
+const X26X_PRESETS: &[&str] = &[
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+];
+const NVENC_PRESETS: &[&str] = &["p1", "p2", "p3", "p4", "p5", "p6", "p7"];
+const QSV_PRESETS: &[&str] = &[
+    "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow",
+];
+const AMF_PRESETS: &[&str] = &["speed", "balanced", "quality"];
+const SVTAV1_PRESETS: &[&str] = &[
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13",
+];
+const AOM_PRESETS: &[&str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8"];
+
+pub trait EncoderBackend: Send + Sync {
+    fn caps(&self) -> &EncoderCaps;
+
+    fn name(&self) -> &'static str {
+        self.caps().name
+    }
+
+    fn codec(&self) -> VideoCodec {
+        self.caps().codec
+    }
+
+    fn is_hardware(&self) -> bool {
+        self.caps().vendor != Vendor::Software
+    }
+
+    fn supports_two_pass(&self) -> bool {
+        self.caps().two_pass
+    }
+
+    // Translates an x265-scale CRF into this encoder's own quality value.
+    fn native_quality(&self, crf: u32) -> i32 {
+        let caps = self.caps();
+        let value = caps.quality_offset + caps.quality_scale * crf as i32;
+        value.clamp(caps.quality_range.0, caps.quality_range.1)
+    }
+
+    fn codec_args(&self) -> Vec<String> {
+        vec!["-c:v".to_string(), self.name().to_string()]
+    }
+
+    // Unknown preset names fall back to the encoder's default instead of failing the job.
+    fn preset_args(&self, preset: Option<&str>) -> Vec<String> {
+        let caps = self.caps();
+        let flag = match caps.preset_flag {
+            Some(flag) => flag,
+            None => return vec![],
+        };
+        let chosen = preset
+            .filter(|p| caps.presets.contains(p))
+            .or(caps.default_preset);
+        match chosen {
+            Some(p) => vec![flag.to_string(), p.to_string()],
+            None => vec![],
+        }
+    }
+
+    fn quality_args(&self, crf: u32) -> Vec<String> {
+        let q = self.native_quality(crf).to_string();
+        match self.caps().quality_mode {
+            QualityMode::Crf => {
+                let mut args = vec!["-crf".to_string(), q];
+                // libvpx only runs in constant quality mode when the bitrate is unset
+                if self.codec() == VideoCodec::Vp9 {
+                    args.extend(["-b:v".to_string(), "0".to_string()]);
+                }
+                args
+            }
+            QualityMode::Cq => vec!["-cq".to_string(), q, "-b:v".to_string(), "0".to_string()],
+            QualityMode::GlobalQuality => vec!["-global_quality".to_string(), q],
+            QualityMode::QScale => vec!["-q:v".to_string(), q],
+            QualityMode::Qp => vec![
+                "-rc".to_string(),
+                "cqp".to_string(),
+                "-qp_i".to_string(),
+                q.clone(),
+                "-qp_p".to_string(),
+                q,
+            ],
+        }
+    }
+
+    // Average bitrate for target-size encodes. Single pass encoders get a VBV cap so they
+    // can't overshoot the size; two-pass ones distribute the bits themselves.
+    fn bitrate_args(&self, kbps: u64, capped: bool) -> Vec<String> {
+        let mut args = vec!["-b:v".to_string(), format!("{}k", kbps)];
+        if capped {
+            args.extend([
+                "-maxrate".to_string(),
+                format!("{}k", kbps),
+                "-bufsize".to_string(),
+                format!("{}k", kbps * 2),
+            ]);
+        }
+        args
+    }
+
+    // `log` is the stats file prefix, ffmpeg appends "-0.log" (and x264/x265 their .mbtree/.cutree).
+    // libvpx only gathers stats in the first pass, so it runs that one at a faster speed.
+    fn pass_args(&self, pass: u32, log: &Path) -> Vec<String> {
+        let mut args = vec![
+            "-pass".to_string(),
+            pass.to_string(),
+            "-passlogfile".to_string(),
+            log.to_string_lossy().to_string(),
+        ];
+        if pass == 1 && self.codec() == VideoCodec::Vp9 && !self.is_hardware() {
+            args.extend(["-speed".to_string(), "4".to_string()]);
+        }
+        args
+    }
+
+    fn pix_fmt_args(&self, ten_bit: bool) -> Vec<String> {
+        let pix_fmt = match self.caps().ten_bit_pix_fmt {
+            Some(fmt) if ten_bit => fmt,
+            _ => "yuv420p",
+        };
+        vec!["-pix_fmt".to_string(), pix_fmt.to_string()]
+    }
+
+    // Apple players only accept HEVC in MP4/MOV when it is tagged hvc1 instead of hev1.
+    fn tag_args(&self) -> Vec<String> {
+        match self.codec() {
+            VideoCodec::Hevc => vec!["-tag:v".to_string(), "hvc1".to_string()],
+            _ => vec![],
+        }
+    }
+
+    // -profile:v / -level:v for H.264 output, nothing for other codecs. AMF only knows the
+    // constrained baseline profile and QSV wants the level as an integer (4.1 -> 41).
+    fn profile_args(&self, profile: Option<H264Profile>, level: Option<&str>) -> Vec<String> {
+        if self.codec() != VideoCodec::H264 {
+            return vec![];
+        }
+        let vendor = self.caps().vendor;
+        let mut args = vec![];
+        if let Some(profile) = profile {
+            let name = match profile {
+                H264Profile::Baseline if vendor == Vendor::Amd => "constrained_baseline",
+                H264Profile::Baseline => "baseline",
+                H264Profile::Main => "main",
+                H264Profile::High => "high",
+            };
+            args.extend(["-profile:v".to_string(), name.to_string()]);
+        }
+        if let Some(level) = level {
+            let level = if vendor == Vendor::Intel {
+                level.replace('.', "")
+            } else {
+                level.to_string()
+            };
+            args.extend(["-level:v".to_string(), level]);
+        }
+        args
+    }
+
+    // Everything needed for a constant quality encode: codec, preset and quality.
+    fn constant_quality_args(&self, crf: u32, preset: Option<&str>) -> Vec<String> {
+        let mut args = self.codec_args();
+        args.extend(self.preset_args(preset));
+        args.extend(self.quality_args(crf));
+        args
+    }
+}
+
+impl EncoderBackend for EncoderCaps {
+    fn caps(&self) -> &EncoderCaps {
+        self
+    }
+}
+
+const fn software(
+    name: &'static str,
+    codec: VideoCodec,
+    quality_offset: i32,
+    quality_max: i32,
+    preset_flag: Option<&'static str>,
+    presets: &'static [&'static str],
+    default_preset: Option<&'static str>,
+) -> EncoderCaps {
+    EncoderCaps {
+        name,
+        codec,
+        vendor: Vendor::Software,
+        quality_mode: QualityMode::Crf,
+        quality_scale: 1,
+        quality_offset,
+        quality_range: (0, quality_max),
+        preset_flag,
+        presets,
+        default_preset,
+        two_pass: true,
+        ten_bit_pix_fmt: Some("yuv420p10le"),
+    }
+}
+
+const fn nvenc(
+    name: &'static str,
+    codec: VideoCodec,
+    quality_offset: i32,
+    ten_bit: bool,
+) -> EncoderCaps {
+    EncoderCaps {
+        name,
+        codec,
+        vendor: Vendor::Nvidia,
+        quality_mode: QualityMode::Cq,
+        quality_scale: 1,
+        quality_offset,
+        quality_range: (0, 51),
+        preset_flag: Some("-preset"),
+        presets: NVENC_PRESETS,
+        default_preset: Some("p4"),
+        two_pass: false,
+        ten_bit_pix_fmt: if ten_bit { Some("p010le") } else { None },
+    }
+}
+
+const fn qsv(
+    name: &'static str,
+    codec: VideoCodec,
+    quality_offset: i32,
+    ten_bit: bool,
+) -> EncoderCaps {
+    EncoderCaps {
+        name,
+        codec,
+        vendor: Vendor::Intel,
+        quality_mode: QualityMode::GlobalQuality,
+        quality_scale: 1,
+        quality_offset,
+        quality_range: (1, 51),
+        preset_flag: Some("-preset"),
+        presets: QSV_PRESETS,
+        default_preset: Some("faster"),
+        two_pass: false,
+        ten_bit_pix_fmt: if ten_bit { Some("p010le") } else { None },
+    }
+}
+
+// AV1 AMF takes QPs on a 0-255 scale, H.264/HEVC on 0-51.
+const fn amf(
+    name: &'static str,
+    codec: VideoCodec,
+    quality_scale: i32,
+    quality_offset: i32,
+    quality_max: i32,
+    ten_bit: bool,
+) -> EncoderCaps {
+    EncoderCaps {
+        name,
+        codec,
+        vendor: Vendor::Amd,
+        quality_mode: QualityMode::Qp,
+        quality_scale,
+        quality_offset,
+        quality_range: (0, quality_max),
+        preset_flag: Some("-quality"),
+        presets: AMF_PRESETS,
+        default_preset: Some("quality"),
+        two_pass: false,
+        ten_bit_pix_fmt: if ten_bit { Some("p010le") } else { None },
+    }
+}
+
+// VideoToolbox's -q:v runs 1-100 upwards; CRF 24 lands on the 55 we always used.
+const fn videotoolbox(name: &'static str, codec: VideoCodec, ten_bit: bool) -> EncoderCaps {
+    EncoderCaps {
+        name,
+        codec,
+        vendor: Vendor::Apple,
+        quality_mode: QualityMode::QScale,
+        quality_scale: -2,
+        quality_offset: 103,
+        quality_range: (1, 100),
+        preset_flag: None,
+        presets: &[],
+        default_preset: None,
+        two_pass: false,
+        ten_bit_pix_fmt: if ten_bit { Some("p010le") } else { None },
+    }
+}
+
+// Offsets line the codecs up roughly by visual quality: x264 needs a few CRF points more than
+// x265 for the same result, VP9 and AV1 use a 0-63 scale.
+static ENCODERS: &[EncoderCaps] = &[
+    software(
+        "libx264",
+        VideoCodec::H264,
+        -4,
+        51,
+        Some("-preset"),
+        X26X_PRESETS,
+        Some("faster"),
+    ),
+    software(
+        "libx265",
+        VideoCodec::Hevc,
+        0,
+        51,
+        Some("-preset"),
+        X26X_PRESETS,
+        Some("faster"),
+    ),
+    software(
+        "libsvtav1",
+        VideoCodec::Av1,
+        8,
+        63,
+        Some("-preset"),
+        SVTAV1_PRESETS,
+        Some("8"),
+    ),
+    software(
+        "libaom-av1",
+        VideoCodec::Av1,
+        8,
+        63,
+        Some("-cpu-used"),
+        AOM_PRESETS,
+        Some("6"),
+    ),
+    software("libvpx-vp9", VideoCodec::Vp9, 6, 63, None, &[], None),
+    nvenc("h264_nvenc", VideoCodec::H264, -4, false),
+    nvenc("hevc_nvenc", VideoCodec::Hevc, 0, true),
+    nvenc("av1_nvenc", VideoCodec::Av1, 8, true),
+    qsv("h264_qsv", VideoCodec::H264, -4, false),
+    qsv("hevc_qsv", VideoCodec::Hevc, 0, true),
+    qsv("av1_qsv", VideoCodec::Av1, 8, true),
+    qsv("vp9_qsv", VideoCodec::Vp9, 6, true),
+    amf("h264_amf", VideoCodec::H264, 1, -4, 51, false),
+    amf("hevc_amf", VideoCodec::Hevc, 1, 0, 51, true),
+    amf("av1_amf", VideoCodec::Av1, 4, 0, 255, true),
+    videotoolbox("h264_videotoolbox", VideoCodec::H264, false),
+    videotoolbox("hevc_videotoolbox", VideoCodec::Hevc, true),
+];
+
+// Order hardware encoders are tried in when a job asks for GPU encoding.
+const VENDOR_PRIORITY: &[Vendor] = &[Vendor::Apple, Vendor::Nvidia, Vendor::Intel, Vendor::Amd];
+
+pub fn all() -> impl Iterator<Item = &'static dyn EncoderBackend> {
+    ENCODERS.iter().map(|e| e as &'static dyn EncoderBackend)
+}
+
+// The software encoder every codec falls back to. Listed first in ENCODERS for its codec.
+pub fn software_encoder(codec: VideoCodec) -> &'static dyn EncoderBackend {
+    all()
+        .find(|e| e.codec() == codec && !e.is_hardware())
+        .expect("every codec has a software encoder")
+}
+
+// Hardware encoders for the codec that could exist on this OS, best first.
+pub fn hardware_candidates(codec: VideoCodec) -> Vec<&'static dyn EncoderBackend> {
+    let mut candidates: Vec<&'static dyn EncoderBackend> = all()
+        .filter(|e| {
+            e.codec() == codec && e.is_hardware() && e.caps().vendor.available_on_this_platform()
+        })
+        .collect();
+    candidates.sort_by_key(|e| VENDOR_PRIORITY.iter().position(|v| *v == e.caps().vendor));
+    candidates
+}
@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command as StdCommand, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::error::Result;
+use crate::hardware::EncoderCache;
+use crate::presets::PresetStore;
+use crate::process::{collect_output, ProcessRegistry, TrackedOutput};
+
+// --- The compression core ---
+// Everything the pipelines need from the outside world goes through `Engine`: how to start
+// ffmpeg/ffprobe, where progress events go, scratch space and shared caches. The desktop app
+// builds one around its AppHandle, the CLI builds one around binaries found on PATH.
+// Diagnostics from the core go to stderr (eprintln!) so the CLI's stdout stays pure JSON.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Ffmpeg,
+    Ffprobe,
+    // realesrgan-ncnn-vulkan, used by the enhancers
+    AiEngine,
+}
+
+impl Tool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Ffmpeg => "ffmpeg",
+            Tool::Ffprobe => "ffprobe",
+            Tool::AiEngine => "realesrgan-ncnn-vulkan",
+        }
+    }
+}
+
+// Output of a running tool, one line per Stdout/Stderr event, like the shell plugin's CommandEvent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessEvent {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exited(Option<i32>),
+}
+
+// Handle to a started process, kept by the registry so the job can be cancelled.
+pub trait ChildProcess: Send {
+    // Kills the process and anything it spawned
+    fn kill(self: Box<Self>);
+}
+
+pub struct SpawnedTool {
+    pub pid: u32,
+    // Every output line, then exactly one `Exited`
+    pub events: Receiver<ProcessEvent>,
+    pub handle: Box<dyn ChildProcess>,
+}
+
+// How the pipelines start external programs. Production uses `SidecarRunner` (desktop app, app.rs) or
+// `NativeRunner` (CLI); tests swap in a scripted fake so no ffmpeg is needed.
+pub trait ProcessRunner: Send + Sync {
+    fn spawn(&self, tool: Tool, args: Vec<String>) -> Result<SpawnedTool>;
+}
+
+pub fn ai_engine_binary(dir: &Path) -> PathBuf {
+    #[cfg(target_os = "windows")]
+    let engine_path = dir.join("realesrgan-ncnn-vulkan.exe");
+    #[cfg(not(target_os = "windows"))]
+    let engine_path = dir.join("realesrgan-ncnn-vulkan");
+    engine_path
+}
+
+// Plain executables, e.g. found on PATH by the CLI
+pub struct NativeRunner {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    pub ai_engine: PathBuf,
+}
+
+impl ProcessRunner for NativeRunner {
+    fn spawn(&self, tool: Tool, args: Vec<String>) -> Result<SpawnedTool> {
+        let program = match tool {
+            Tool::Ffmpeg => &self.ffmpeg,
+            Tool::Ffprobe => &self.ffprobe,
+            Tool::AiEngine => &self.ai_engine,
+        };
+        spawn_native(program, args)
+    }
+}
+
+// Kills the process and anything it spawned. ffmpeg and realesrgan don't normally fork,
+// but hardware encoders and shells in between sometimes do.
+pub(crate) fn kill_tree(pid: u32) {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = std::process::Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .creation_flags(0x08000000)
+            .status();
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = std::process::Command::new("pkill")
+            .args(["-KILL", "-P", &pid.to_string()])
+            .status();
+    }
+}
+
+struct NativeChild(Arc<Mutex<Child>>);
+
+impl ChildProcess for NativeChild {
+    fn kill(self: Box<Self>) {
+        let mut c = self.0.lock().unwrap();
+        kill_tree(c.id());
+        let _ = c.kill();
+        let _ = c.wait();
+    }
+}
+
+// Splits on \r as well as \n: progress bars redraw their line with \r.
+fn pipe_lines<R: Read + Send + 'static>(
+    mut reader: R,
+    tx: Sender<ProcessEvent>,
+    wrap: fn(Vec<u8>) -> ProcessEvent,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut line = vec![];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            for &b in &buf[..n] {
+                if b == b'\n' || b == b'\r' {
+                    if !line.is_empty()
+                        && tx.blocking_send(wrap(std::mem::take(&mut line))).is_err()
+                    {
+                        return;
+                    }
+                } else {
+                    line.push(b);
+                }
+            }
+        }
+        if !line.is_empty() {
+            let _ = tx.blocking_send(wrap(line));
+        }
+    })
+}
+
+pub(crate) fn spawn_native(program: &Path, args: Vec<String>) -> Result<SpawnedTool> {
+    let mut command = StdCommand::new(program);
+    command
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(0x08000000);
+    }
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let child = Arc::new(Mutex::new(child));
+
+    let (tx, events) = channel(256);
+    let mut readers = vec![];
+    if let Some(stdout) = stdout {
+        readers.push(pipe_lines(stdout, tx.clone(), ProcessEvent::Stdout));
+    }
+    if let Some(stderr) = stderr {
+        readers.push(pipe_lines(stderr, tx.clone(), ProcessEvent::Stderr));
+    }
+    let waiter = child.clone();
+    std::thread::spawn(move || {
+        for reader in readers {
+            let _ = reader.join();
+        }
+        // Poll instead of wait() so a kill from another thread can still take the lock
+        let code = loop {
+            match waiter.lock().unwrap().try_wait() {
+                Ok(Some(status)) => break status.code(),
+                Ok(None) => {}
+                Err(_) => break None,
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        };
+        let _ = tx.blocking_send(ProcessEvent::Exited(code));
+    });
+    Ok(SpawnedTool {
+        pid,
+        events,
+        handle: Box::new(NativeChild(child)),
+    })
+}
+
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: serde_json::Value);
+}
+
+pub struct Engine {
+    pub runner: Box<dyn ProcessRunner>,
+    events: Box<dyn EventSink>,
+    pub registry: ProcessRegistry,
+    pub encoders: EncoderCache,
+    pub presets: PresetStore,
+    pub temp_dir: PathBuf,
+    // Folder holding the realesrgan-ncnn-vulkan binary and its models
+    pub ai_engine_dir: PathBuf,
+}
+
+impl Engine {
+    pub fn new(
+        runner: Box<dyn ProcessRunner>,
+        events: Box<dyn EventSink>,
+        temp_dir: PathBuf,
+        ai_engine_dir: PathBuf,
+    ) -> Self {
+        Engine {
+            runner,
+            events,
+            registry: ProcessRegistry::default(),
+            encoders: EncoderCache::default(),
+            presets: PresetStore::default(),
+            temp_dir,
+            ai_engine_dir,
+        }
+    }
+
+    pub fn emit<T: Serialize>(&self, event: &str, payload: T) {
+        if let Ok(value) = serde_json::to_value(payload) {
+            self.events.emit(event, value);
+        }
+    }
+
+    // A line for the user, on stderr and in the app's ffmpeg log
+    pub fn note(&self, note: impl Into<String>) {
+        let note = note.into();
+        eprintln!("{}", note);
+        self.emit("ffmpeg-progress", note);
+    }
+
+    pub fn ai_engine_path(&self) -> PathBuf {
+        ai_engine_binary(&self.ai_engine_dir)
+    }
+
+    // Runs a tool to completion outside of any job (ffprobe, version checks, encoder probes).
+    pub async fn run_tool(&self, tool: Tool, args: Vec<String>) -> Result<TrackedOutput> {
+        let spawned = self.runner.spawn(tool, args)?;
+        Ok(collect_output(spawned.events).await)
+    }
+}
@@ -0,0 +1,271 @@
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+// How many stderr lines an FfmpegFailed error carries back to the UI.
+pub const STDERR_TAIL_LINES: usize = 20;
+
+// ENOSPC on unix; ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+#[cfg(target_os = "windows")]
+const DISK_FULL_OS_ERRORS: &[i32] = &[39, 112];
+#[cfg(not(target_os = "windows"))]
+const DISK_FULL_OS_ERRORS: &[i32] = &[28];
+
+// Every command fails with one of these. On the wire it is an object with a stable `code`
+// (e.g. "FFMPEG_FAILED"), a human readable `message` and the variant's fields in camelCase,
+// so the UI and scripts can branch on `code` without parsing text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(
+    tag = "code",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum CompressError {
+    InputNotFound {
+        path: String,
+    },
+    UnsupportedFormat {
+        detail: String,
+    },
+    MissingStream {
+        kind: String,
+    },
+    EncoderUnavailable {
+        encoder: String,
+    },
+    TargetTooSmall {
+        detail: String,
+    },
+    DiskFull,
+    Cancelled,
+    JobNotFound {
+        id: u64,
+    },
+    PresetNotFound {
+        id: String,
+    },
+    InvalidRequest {
+        detail: String,
+    },
+    FfmpegFailed {
+        exit_code: Option<i32>,
+        stderr_tail: Vec<String>,
+    },
+    ProbeFailed {
+        detail: String,
+    },
+    AiEngineMissing {
+        path: String,
+    },
+    AiEngineFailed {
+        exit_code: Option<i32>,
+    },
+    Io {
+        detail: String,
+    },
+    Internal {
+        detail: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, CompressError>;
+
+impl CompressError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompressError::InputNotFound { .. } => "INPUT_NOT_FOUND",
+            CompressError::UnsupportedFormat { .. } => "UNSUPPORTED_FORMAT",
+            CompressError::MissingStream { .. } => "MISSING_STREAM",
+            CompressError::EncoderUnavailable { .. } => "ENCODER_UNAVAILABLE",
+            CompressError::TargetTooSmall { .. } => "TARGET_TOO_SMALL",
+            CompressError::DiskFull => "DISK_FULL",
+            CompressError::Cancelled => "CANCELLED",
+            CompressError::JobNotFound { .. } => "JOB_NOT_FOUND",
+            CompressError::PresetNotFound { .. } => "PRESET_NOT_FOUND",
+            CompressError::InvalidRequest { .. } => "INVALID_REQUEST",
+            CompressError::FfmpegFailed { .. } => "FFMPEG_FAILED",
+            CompressError::ProbeFailed { .. } => "PROBE_FAILED",
+            CompressError::AiEngineMissing { .. } => "AI_ENGINE_MISSING",
+            CompressError::AiEngineFailed { .. } => "AI_ENGINE_FAILED",
+            CompressError::Io { .. } => "IO",
+            CompressError::Internal { .. } => "INTERNAL",
+        }
+    }
+
+    pub fn input_not_found(path: &str) -> Self {
+        CompressError::InputNotFound {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn unsupported(detail: impl Into<String>) -> Self {
+        CompressError::UnsupportedFormat {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn invalid(detail: impl Into<String>) -> Self {
+        CompressError::InvalidRequest {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        CompressError::Internal {
+            detail: detail.into(),
+        }
+    }
+
+    // Turns a failed ffmpeg run into the most specific error its stderr allows.
+    pub fn from_ffmpeg(exit_code: Option<i32>, stderr_tail: Vec<String>) -> Self {
+        let log = stderr_tail.join("\n");
+        if log.contains("No space left on device")
+            || log.contains("There is not enough space on the disk")
+        {
+            return CompressError::DiskFull;
+        }
+        for line in stderr_tail.iter() {
+            // "Unknown encoder 'libsvtav1'"
+            if let Some(rest) = line.split("Unknown encoder").nth(1) {
+                let encoder = rest
+                    .trim()
+                    .trim_matches(|c| c == '\'' || c == '"')
+                    .to_string();
+                return CompressError::EncoderUnavailable { encoder };
+            }
+        }
+        CompressError::FfmpegFailed {
+            exit_code,
+            stderr_tail,
+        }
+    }
+
+    // Same as `from_ffmpeg` for runs whose whole stderr was collected at once.
+    pub fn from_ffmpeg_stderr(exit_code: Option<i32>, stderr: &[u8]) -> Self {
+        let stderr = String::from_utf8_lossy(stderr);
+        let lines: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        CompressError::from_ffmpeg(exit_code, tail)
+    }
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressError::InputNotFound { path } => write!(f, "Input file not found: {}", path),
+            CompressError::UnsupportedFormat { detail } => {
+                write!(f, "Unsupported format: {}", detail)
+            }
+            CompressError::MissingStream { kind } => write!(f, "Input has no {} stream", kind),
+            CompressError::EncoderUnavailable { encoder } => {
+                write!(f, "Encoder {} is not available on this machine", encoder)
+            }
+            CompressError::TargetTooSmall { detail } => write!(f, "{}", detail),
+            CompressError::DiskFull => write!(f, "Not enough disk space to write the output"),
+            CompressError::Cancelled => write!(f, "Job cancelled"),
+            CompressError::JobNotFound { id } => write!(f, "Job {} not found", id),
+            CompressError::PresetNotFound { id } => write!(f, "Preset '{}' not found", id),
+            CompressError::InvalidRequest { detail } => write!(f, "{}", detail),
+            CompressError::FfmpegFailed {
+                exit_code,
+                stderr_tail,
+            } => {
+                let last = stderr_tail
+                    .last()
+                    .map(|s| s.as_str())
+                    .unwrap_or("Unknown FFmpeg Error");
+                match exit_code {
+                    Some(code) => write!(f, "FFmpeg failed (Code {}): {}", code, last),
+                    None => write!(f, "FFmpeg was terminated: {}", last),
+                }
+            }
+            CompressError::ProbeFailed { detail } => {
+                write!(f, "Could not read media info: {}", detail)
+            }
+            CompressError::AiEngineMissing { path } => write!(f, "AI engine not found at {}", path),
+            CompressError::AiEngineFailed { exit_code } => write!(
+                f,
+                "AI Engine Error: Process exited with code {:?}",
+                exit_code
+            ),
+            CompressError::Io { detail } => write!(f, "{}", detail),
+            CompressError::Internal { detail } => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+impl Serialize for CompressError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            CompressError::InputNotFound { path } | CompressError::AiEngineMissing { path } => {
+                map.serialize_entry("path", path)?;
+            }
+            CompressError::UnsupportedFormat { detail }
+            | CompressError::TargetTooSmall { detail }
+            | CompressError::ProbeFailed { detail }
+            | CompressError::InvalidRequest { detail }
+            | CompressError::Io { detail }
+            | CompressError::Internal { detail } => {
+                map.serialize_entry("detail", detail)?;
+            }
+            CompressError::MissingStream { kind } => map.serialize_entry("kind", kind)?,
+            CompressError::JobNotFound { id } => map.serialize_entry("id", id)?,
+            CompressError::PresetNotFound { id } => map.serialize_entry("id", id)?,
+            CompressError::EncoderUnavailable { encoder } => {
+                map.serialize_entry("encoder", encoder)?
+            }
+            CompressError::FfmpegFailed {
+                exit_code,
+                stderr_tail,
+            } => {
+                map.serialize_entry("exitCode", exit_code)?;
+                map.serialize_entry("stderrTail", stderr_tail)?;
+            }
+            CompressError::AiEngineFailed { exit_code } => {
+                map.serialize_entry("exitCode", exit_code)?
+            }
+            CompressError::DiskFull | CompressError::Cancelled => {}
+        }
+        map.end()
+    }
+}
+
+impl From<std::io::Error> for CompressError {
+    fn from(e: std::io::Error) -> Self {
+        if e.raw_os_error()
+            .map(|c| DISK_FULL_OS_ERRORS.contains(&c))
+            .unwrap_or(false)
+        {
+            return CompressError::DiskFull;
+        }
+        CompressError::Io {
+            detail: e.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "app")]
+impl From<tauri::Error> for CompressError {
+    fn from(e: tauri::Error) -> Self {
+        CompressError::Internal {
+            detail: e.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "app")]
+impl From<tauri_plugin_shell::Error> for CompressError {
+    fn from(e: tauri_plugin_shell::Error) -> Self {
+        CompressError::Internal {
+            detail: format!("Failed to launch FFmpeg: {}", e),
+        }
+    }
+}
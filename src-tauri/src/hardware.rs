@@ -0,0 +1,414 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::encoders::{self, EncoderBackend, EncoderCaps, VideoCodec};
+use crate::engine::{Engine, Tool};
+use crate::error::{CompressError, Result};
+
+// Which encoders actually work on this machine. Filled lazily by `is_encoder_supported` or all at
+// once by `detect_hardware`, and persisted so a restart doesn't have to probe every GPU again.
+// The file is keyed by the ffmpeg version: a new ffmpeg build can add or drop encoders.
+#[derive(Default)]
+pub struct EncoderCache {
+    results: Mutex<HashMap<String, bool>>,
+    ffmpeg_version: Mutex<Option<String>>,
+    store_path: Mutex<Option<PathBuf>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredProbe {
+    ffmpeg_version: String,
+    detected_at: u64,
+    encoders: HashMap<String, bool>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EncoderStatus {
+    #[serde(flatten)]
+    pub caps: EncoderCaps,
+    pub supported: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HardwareReport {
+    pub ffmpeg_version: String,
+    pub os: String,
+    pub arch: String,
+    pub vram_mb: u64,
+    pub detected_at: u64,
+    // True when the result came from the stored probe instead of running ffmpeg again
+    pub from_cache: bool,
+    pub encoders: Vec<EncoderStatus>,
+    // Encoder a GPU job would use for each codec
+    pub recommended: HashMap<VideoCodec, String>,
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl EncoderCache {
+    fn cached(&self, encoder: &str) -> Option<bool> {
+        self.results.lock().unwrap().get(encoder).copied()
+    }
+
+    fn read_store(&self) -> Option<StoredProbe> {
+        let path = self.store_path.lock().unwrap().clone()?;
+        let text = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<StoredProbe>(&text) {
+            Ok(stored) => Some(stored),
+            Err(e) => {
+                eprintln!(
+                    "⚠️ HARDWARE: Ignoring unreadable probe file {:?}: {}",
+                    path, e
+                );
+                None
+            }
+        }
+    }
+
+    fn save(&self) {
+        let version = match self.ffmpeg_version.lock().unwrap().clone() {
+            Some(v) => v,
+            None => return,
+        };
+        let path = match self.store_path.lock().unwrap().clone() {
+            Some(p) => p,
+            None => return,
+        };
+        let stored = StoredProbe {
+            ffmpeg_version: version,
+            detected_at: now_ms(),
+            encoders: self.results.lock().unwrap().clone(),
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&stored) {
+            Ok(text) => {
+                if let Err(e) = std::fs::write(&path, text) {
+                    eprintln!("⚠️ HARDWARE: Failed to persist probe results: {}", e);
+                }
+            }
+            Err(e) => eprintln!("⚠️ HARDWARE: Failed to serialize probe results: {}", e),
+        }
+    }
+
+    fn insert(&self, encoder: &str, supported: bool) {
+        self.results
+            .lock()
+            .unwrap()
+            .insert(encoder.to_string(), supported);
+        self.save();
+    }
+}
+
+// "ffmpeg version 7.1-essentials_build-www.gyan.dev Copyright (c) ..." -> "7.1-essentials_build-www.gyan.dev"
+async fn ffmpeg_version(engine: &Engine) -> Result<String> {
+    let output = engine
+        .run_tool(
+            Tool::Ffmpeg,
+            vec!["-hide_banner".to_string(), "-version".to_string()],
+        )
+        .await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_line = stdout.lines().next().unwrap_or("");
+    first_line
+        .split_whitespace()
+        .skip_while(|w| *w != "version")
+        .nth(1)
+        .map(|v| v.to_string())
+        .ok_or_else(|| {
+            CompressError::internal(format!("Unexpected ffmpeg -version output: {}", first_line))
+        })
+}
+
+// Called from setup: remembers where the probe file lives and seeds the cache from it when it
+// was written by the same ffmpeg build. Jobs may already have probed encoders while the version
+// was being read; their results are fresher than the file's and are kept, then saved with it.
+#[cfg(feature = "app")]
+pub async fn restore(engine: &Engine, store_path: PathBuf) {
+    let cache = &engine.encoders;
+    *cache.store_path.lock().unwrap() = Some(store_path);
+    let version = match ffmpeg_version(engine).await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("⚠️ HARDWARE: Could not read ffmpeg version: {}", e);
+            return;
+        }
+    };
+    if let Some(stored) = cache.read_store() {
+        if stored.ffmpeg_version == version {
+            eprintln!(
+                "🔍 DIAGNOSTIC: Loaded {} stored encoder results for ffmpeg {}",
+                stored.encoders.len(),
+                version
+            );
+            let mut results = cache.results.lock().unwrap();
+            for (encoder, supported) in stored.encoders {
+                results.entry(encoder).or_insert(supported);
+            }
+        } else {
+            eprintln!(
+                "🔍 DIAGNOSTIC: ffmpeg changed ({} -> {}), encoders will be probed again",
+                stored.ffmpeg_version, version
+            );
+        }
+    }
+    *cache.ffmpeg_version.lock().unwrap() = Some(version);
+    // `insert` couldn't save without the version
+    cache.save();
+}
+
+pub fn get_system_vram_mb() -> u64 {
+    #[cfg(target_os = "windows")]
+    {
+        if let Ok(output) = std::process::Command::new("wmic")
+            .args(["path", "win32_VideoController", "get", "AdapterRAM"])
+            .output()
+        {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let mut max_vram: u64 = 0;
+            for line in stdout.lines() {
+                if let Ok(bytes) = line.trim().parse::<u64>() {
+                    let mb = bytes / (1024 * 1024);
+                    if mb > max_vram {
+                        max_vram = mb;
+                    }
+                }
+            }
+            if max_vram > 0 {
+                return max_vram;
+            }
+        }
+        return 4096; // Fallback to safe 4GB constraint if probe fails
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        return 8192; // Apple Silicon relies on unified memory, usually >8GB
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        4096 // Linux generic fallback
+    }
+}
+
+// Encodes a tenth of a second of test pattern; only a working driver + encoder pair succeeds.
+async fn probe_encoder(engine: &Engine, encoder: &str) -> bool {
+    eprintln!("🔍 DIAGNOSTIC: Checking support for encoder: {}", encoder);
+
+    let args = vec![
+        "-f",
+        "lavfi",
+        "-i",
+        "color=s=1280x720:d=0.1",
+        "-c:v",
+        encoder,
+        "-f",
+        "null",
+        "-",
+    ];
+
+    let output = engine
+        .run_tool(Tool::Ffmpeg, args.into_iter().map(String::from).collect())
+        .await;
+    let supported = match output {
+        Ok(o) => o.success(),
+        Err(e) => {
+            eprintln!("❌ DIAGNOSTIC: Could not start FFmpeg: {}", e);
+            false
+        }
+    };
+
+    if supported {
+        eprintln!("✅ DIAGNOSTIC: {} is WORKING!", encoder);
+    } else {
+        eprintln!("❌ DIAGNOSTIC: {} FAILED!", encoder);
+    }
+    supported
+}
+
+pub async fn is_encoder_supported(engine: &Engine, encoder: &str) -> bool {
+    if let Some(supported) = engine.encoders.cached(encoder) {
+        return supported;
+    }
+    let supported = probe_encoder(engine, encoder).await;
+    engine.encoders.insert(encoder, supported);
+    supported
+}
+
+// Best working encoder for the codec: the first hardware one that passes the probe when
+// GPU encoding is on, otherwise (or if none works) the software encoder.
+pub async fn pick_encoder(
+    engine: &Engine,
+    codec: VideoCodec,
+    auto_gpu: bool,
+) -> &'static dyn EncoderBackend {
+    if auto_gpu {
+        for encoder in encoders::hardware_candidates(codec) {
+            if is_encoder_supported(engine, encoder.name()).await {
+                return encoder;
+            }
+        }
+    }
+    encoders::software_encoder(codec)
+}
+
+// Probes every known encoder (stored results are reused unless `refresh` is set) and reports
+// what this machine can do, for the settings screen and support tickets.
+pub async fn report(engine: &Engine, refresh: bool) -> Result<HardwareReport> {
+    let cache = &engine.encoders;
+    let version = ffmpeg_version(engine).await?;
+    let version_changed = cache.ffmpeg_version.lock().unwrap().as_deref() != Some(version.as_str());
+    if refresh || version_changed {
+        cache.results.lock().unwrap().clear();
+    }
+    *cache.ffmpeg_version.lock().unwrap() = Some(version.clone());
+
+    let mut from_cache = true;
+    let mut statuses = vec![];
+    for encoder in encoders::all() {
+        let supported = match cache.cached(encoder.name()) {
+            Some(supported) => supported,
+            None => {
+                from_cache = false;
+                let supported = probe_encoder(engine, encoder.name()).await;
+                cache
+                    .results
+                    .lock()
+                    .unwrap()
+                    .insert(encoder.name().to_string(), supported);
+                supported
+            }
+        };
+        statuses.push(EncoderStatus {
+            caps: encoder.caps().clone(),
+            supported,
+        });
+    }
+    let detected_at = if from_cache {
+        cache
+            .read_store()
+            .filter(|s| s.ffmpeg_version == version)
+            .map(|s| s.detected_at)
+            .unwrap_or_else(now_ms)
+    } else {
+        cache.save();
+        now_ms()
+    };
+
+    let mut recommended = HashMap::new();
+    for codec in [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::Av1,
+        VideoCodec::Vp9,
+    ] {
+        recommended.insert(
+            codec,
+            pick_encoder(engine, codec, true).await.name().to_string(),
+        );
+    }
+
+    Ok(HardwareReport {
+        ffmpeg_version: version,
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        vram_mb: get_system_vram_mb(),
+        detected_at,
+        from_cache,
+        encoders: statuses,
+        recommended,
+    })
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub async fn detect_hardware(
+    engine: tauri::State<'_, Engine>,
+    refresh: Option<bool>,
+) -> Result<HardwareReport> {
+    report(&engine, refresh.unwrap_or(false)).await
+}
+
+#[cfg(all(test, feature = "app"))]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, engine_with, Script, ScriptedRunner, TempDir};
+
+    fn stored(path: &std::path::Path) -> StoredProbe {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn restore_keeps_results_probed_while_it_ran_and_saves_them() {
+        let dir = TempDir::new("hardware-restore");
+        let path = dir.0.join("encoders.json");
+        let old = StoredProbe {
+            ffmpeg_version: "7.1".to_string(),
+            detected_at: 0,
+            encoders: HashMap::from([
+                ("h264_nvenc".to_string(), true),
+                ("hevc_qsv".to_string(), false),
+            ]),
+        };
+        std::fs::write(&path, serde_json::to_string(&old).unwrap()).unwrap();
+        let engine = engine_with(
+            ScriptedRunner::new(|_, _| {
+                Script::stdout("ffmpeg version 7.1 Copyright (c) 2000-2024")
+            }),
+            &dir.0,
+        );
+        // A job found the driver gone before the version was known, which `insert` can't save yet
+        engine.encoders.insert("h264_nvenc", false);
+        assert!(stored(&path).encoders["h264_nvenc"]);
+
+        block_on(restore(&engine, path.clone()));
+
+        assert_eq!(engine.encoders.cached("h264_nvenc"), Some(false));
+        assert_eq!(engine.encoders.cached("hevc_qsv"), Some(false));
+        let saved = stored(&path);
+        assert_eq!(saved.ffmpeg_version, "7.1");
+        assert_eq!(
+            saved.encoders,
+            HashMap::from([
+                ("h264_nvenc".to_string(), false),
+                ("hevc_qsv".to_string(), false)
+            ])
+        );
+    }
+
+    #[test]
+    fn restore_ignores_results_of_another_ffmpeg_build() {
+        let dir = TempDir::new("hardware-upgrade");
+        let path = dir.0.join("encoders.json");
+        let old = StoredProbe {
+            ffmpeg_version: "6.0".to_string(),
+            detected_at: 0,
+            encoders: HashMap::from([("av1_nvenc".to_string(), true)]),
+        };
+        std::fs::write(&path, serde_json::to_string(&old).unwrap()).unwrap();
+        let engine = engine_with(
+            ScriptedRunner::new(|_, _| {
+                Script::stdout("ffmpeg version 7.1 Copyright (c) 2000-2024")
+            }),
+            &dir.0,
+        );
+
+        block_on(restore(&engine, path.clone()));
+
+        assert_eq!(engine.encoders.cached("av1_nvenc"), None);
+        assert_eq!(stored(&path).ffmpeg_version, "7.1");
+        assert!(stored(&path).encoders.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use serde::{Deserialize, Serialize};
+
+use crate::encoders::{EncoderBackend, VideoCodec};
+use crate::error::Result;
+use crate::probe::{self, ContentLight, DynamicRange, MasteringDisplay, MediaInfo, VideoStream};
+use crate::process::JobScope;
+
+// --- HDR and bit depth ---
+// A 10-bit HDR source squeezed into 8-bit yuv420p without tone mapping comes out washed out. HDR
+// (HDR10/HLG, and the compatible base layer of Dolby Vision phone clips) is kept as 10-bit with
+// its color tags and mastering metadata when the output codec and encoder can carry it; otherwise,
+// or when the job asks for SDR, it is tone-mapped to BT.709 with zscale/tonemap.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HdrMode {
+    // HDR stays HDR and 10-bit stays 10-bit where the encoder allows it
+    #[default]
+    Preserve,
+    // 8-bit BT.709 for players and sites that can't show HDR
+    Sdr,
+}
+
+// What an encode does about color: the tone mapping filter (if any) and the pixel format, tags
+// and HDR metadata options for the encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorPlan {
+    pub tonemap: Option<String>,
+    args: Vec<String>,
+}
+
+impl ColorPlan {
+    // 8-bit output and nothing else, what every encode got before
+    pub fn sdr(encoder: &dyn EncoderBackend) -> ColorPlan {
+        ColorPlan {
+            tonemap: None,
+            args: encoder.pix_fmt_args(false),
+        }
+    }
+
+    // Output options, after the encoder's own
+    pub fn args(&self) -> Vec<String> {
+        self.args.clone()
+    }
+
+    // -vf chain with the tone mapping ahead of `scale`, so scaling works on the SDR picture
+    pub fn video_filter(&self, scale: Option<String>) -> Option<String> {
+        match (&self.tonemap, scale) {
+            (Some(tonemap), Some(scale)) => Some(format!("{},{}", tonemap, scale)),
+            (Some(tonemap), None) => Some(tonemap.clone()),
+            (None, scale) => scale,
+        }
+    }
+}
+
+fn tags(primaries: &str, transfer: &str, matrix: &str) -> Vec<String> {
+    vec![
+        "-color_primaries".to_string(),
+        primaries.to_string(),
+        "-color_trc".to_string(),
+        transfer.to_string(),
+        "-colorspace".to_string(),
+        matrix.to_string(),
+    ]
+}
+
+// Linearize, map the highlights down with hable and convert to BT.709. The input side is spelled
+// out because some phone clips only tag the container, not the frames zscale looks at.
+pub fn tonemap_filter(video: &VideoStream) -> String {
+    format!(
+        "zscale=tin={}:pin={}:min={}:t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+        video.color_transfer.as_deref().unwrap_or("smpte2084"),
+        video.color_primaries.as_deref().unwrap_or("bt2020"),
+        video.color_space.as_deref().unwrap_or("bt2020nc"),
+    )
+}
+
+// The source's HDR10 metadata in the encoder's own syntax. Only x265 and SVT-AV1 take it; other
+// encoders get the color tags alone.
+fn metadata_args(
+    encoder: &dyn EncoderBackend,
+    mastering: Option<MasteringDisplay>,
+    light: Option<ContentLight>,
+) -> Vec<String> {
+    match encoder.name() {
+        "libx265" => {
+            let mut params = vec![];
+            if let Some(m) = mastering {
+                params.push(format!(
+                    "master-display=G({},{})B({},{})R({},{})WP({},{})L({},{})",
+                    m.green.0,
+                    m.green.1,
+                    m.blue.0,
+                    m.blue.1,
+                    m.red.0,
+                    m.red.1,
+                    m.white_point.0,
+                    m.white_point.1,
+                    m.max_luminance,
+                    m.min_luminance
+                ));
+            }
+            if let Some(l) = light {
+                params.push(format!("max-cll={},{}", l.max_content, l.max_average));
+            }
+            if params.is_empty() {
+                return vec![];
+            }
+            params.splice(
+                0..0,
+                ["hdr10=1".to_string(), "repeat-headers=1".to_string()],
+            );
+            vec!["-x265-params".to_string(), params.join(":")]
+        }
+        "libsvtav1" => {
+            let chroma = |v: u32| v as f64 / 50000.0;
+            let luminance = |v: u32| v as f64 / 10000.0;
+            let mut params = vec![];
+            if let Some(m) = mastering {
+                params.push(format!(
+                    "mastering-display=G({},{})B({},{})R({},{})WP({},{})L({},{})",
+                    chroma(m.green.0),
+                    chroma(m.green.1),
+                    chroma(m.blue.0),
+                    chroma(m.blue.1),
+                    chroma(m.red.0),
+                    chroma(m.red.1),
+                    chroma(m.white_point.0),
+                    chroma(m.white_point.1),
+                    luminance(m.max_luminance),
+                    luminance(m.min_luminance)
+                ));
+            }
+            if let Some(l) = light {
+                params.push(format!("content-light={},{}", l.max_content, l.max_average));
+            }
+            if params.is_empty() {
+                return vec![];
+            }
+            vec!["-svtav1-params".to_string(), params.join(":")]
+        }
+        _ => vec![],
+    }
+}
+
+// Decides between keeping HDR / 10-bit and tone mapping for this source and encoder. H.264 output
+// is always 8-bit SDR: it is picked for compatibility, and 10-bit H.264 hardly plays anywhere.
+pub async fn plan(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    encoder: &dyn EncoderBackend,
+    mode: HdrMode,
+) -> Result<ColorPlan> {
+    let video = match info.primary_video() {
+        Some(video) => video,
+        None => return Ok(ColorPlan::sdr(encoder)),
+    };
+    let ten_bit_ok = mode == HdrMode::Preserve
+        && encoder.codec() != VideoCodec::H264
+        && encoder.caps().ten_bit_pix_fmt.is_some();
+    let range = video.dynamic_range();
+    if range == DynamicRange::Sdr {
+        return Ok(ColorPlan {
+            tonemap: None,
+            args: encoder.pix_fmt_args(ten_bit_ok && video.bit_depth > 8),
+        });
+    }
+
+    if !ten_bit_ok {
+        if mode == HdrMode::Preserve {
+            scope.engine.note(format!(
+                "⚠️ HDR: {} can't carry HDR here, tone-mapping to SDR",
+                encoder.name()
+            ));
+        }
+        let mut args = encoder.pix_fmt_args(false);
+        args.extend(tags("bt709", "bt709", "bt709"));
+        return Ok(ColorPlan {
+            tonemap: Some(tonemap_filter(video)),
+            args,
+        });
+    }
+
+    if let Some(profile) = video.dolby_vision_profile {
+        // The RPU doesn't survive re-encoding; profile 8 falls back to its HDR10/HLG base layer
+        let base = if range == DynamicRange::Hlg {
+            "HLG"
+        } else {
+            "HDR10"
+        };
+        scope.engine.note(format!(
+            "⚠️ HDR: Dolby Vision (profile {}) is kept as its {} base layer",
+            profile, base
+        ));
+    }
+    let (mastering, light) = match (range, video.mastering_display) {
+        (DynamicRange::Hdr10, None) => {
+            let (mastering, light) = probe::hdr_metadata(scope, input, video.index)
+                .await
+                .unwrap_or_default();
+            (mastering, video.content_light.or(light))
+        }
+        _ => (video.mastering_display, video.content_light),
+    };
+    let transfer = if range == DynamicRange::Hlg {
+        "arib-std-b67"
+    } else {
+        "smpte2084"
+    };
+    let mut args = encoder.pix_fmt_args(true);
+    args.extend(tags(
+        video.color_primaries.as_deref().unwrap_or("bt2020"),
+        transfer,
+        video.color_space.as_deref().unwrap_or("bt2020nc"),
+    ));
+    args.extend(metadata_args(encoder, mastering, light));
+    Ok(ColorPlan {
+        tonemap: None,
+        args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoders::software_encoder;
+    use crate::testing::{block_on, planning, strings, Script, ScriptedRunner};
+
+    // 10-bit HEVC HDR10 with mastering display and light levels at stream level, like MKV remuxes have
+    const PROBE_HDR10: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "hevc", "width": 3840, "height": 2160, "pix_fmt": "yuv420p10le",
+          "color_space": "bt2020nc", "color_primaries": "bt2020", "color_transfer": "smpte2084", "avg_frame_rate": "24/1",
+          "side_data_list": [
+            { "side_data_type": "Mastering display metadata", "red_x": "34000/50000", "red_y": "16000/50000", "green_x": "13250/50000",
+              "green_y": "34500/50000", "blue_x": "7500/50000", "blue_y": "3000/50000", "white_point_x": "15635/50000",
+              "white_point_y": "16450/50000", "min_luminance": "50/10000", "max_luminance": "10000000/10000" },
+            { "side_data_type": "Content light level metadata", "max_content": 1000, "max_average": 400 }
+          ] },
+        { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000", "bit_rate": "128000" }
+      ],
+      "format": { "format_name": "matroska,webm", "duration": "10.000000", "size": "40000000" }
+    }"#;
+
+    fn color_plan(codec: VideoCodec, mode: HdrMode) -> ColorPlan {
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+        let calls = runner.calls();
+        let plan = planning(PROBE_HDR10, runner, |scope, info| {
+            block_on(plan(scope, "in.mkv", info, software_encoder(codec), mode))
+        })
+        .unwrap();
+        // Stream-level metadata needs no frame probe
+        assert!(calls.lock().unwrap().is_empty());
+        plan
+    }
+
+    #[test]
+    fn hdr10_is_kept_as_10_bit_with_metadata() {
+        let plan = color_plan(VideoCodec::Hevc, HdrMode::Preserve);
+
+        assert_eq!(plan.tonemap, None);
+        assert_eq!(plan.args(), strings(&[
+            "-pix_fmt", "yuv420p10le",
+            "-color_primaries", "bt2020", "-color_trc", "smpte2084", "-colorspace", "bt2020nc",
+            "-x265-params", "hdr10=1:repeat-headers=1:master-display=G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50):max-cll=1000,400",
+        ]));
+    }
+
+    #[test]
+    fn hdr_to_h264_is_tone_mapped_ahead_of_scaling() {
+        let plan = color_plan(VideoCodec::H264, HdrMode::Preserve);
+
+        let tonemap = "zscale=tin=smpte2084:pin=bt2020:min=bt2020nc:t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+        assert_eq!(
+            plan.video_filter(Some("scale=1920:-2".to_string())),
+            Some(format!("{},scale=1920:-2", tonemap))
+        );
+        assert_eq!(
+            plan.args(),
+            strings(&[
+                "-pix_fmt",
+                "yuv420p",
+                "-color_primaries",
+                "bt709",
+                "-color_trc",
+                "bt709",
+                "-colorspace",
+                "bt709"
+            ])
+        );
+    }
+
+    #[test]
+    fn sdr_mode_tone_maps_even_when_the_encoder_could_keep_hdr() {
+        let plan = color_plan(VideoCodec::Hevc, HdrMode::Sdr);
+
+        assert!(plan.tonemap.is_some());
+        assert_eq!(plan.args()[..2], strings(&["-pix_fmt", "yuv420p"]));
+    }
+
+    #[test]
+    fn hdr10_metadata_is_read_from_the_picture_not_the_cover_art() {
+        // HEVC HDR10 whose mastering display only lives in the bitstream, behind a cover image
+        const PROBE_COVER_HDR10: &str = r#"{
+          "streams": [
+            { "index": 0, "codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600, "disposition": { "attached_pic": 1 } },
+            { "index": 1, "codec_type": "video", "codec_name": "hevc", "width": 3840, "height": 2160, "pix_fmt": "yuv420p10le",
+              "color_space": "bt2020nc", "color_primaries": "bt2020", "color_transfer": "smpte2084", "avg_frame_rate": "24/1" }
+          ],
+          "format": { "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.000000" }
+        }"#;
+        const FRAME: &str = r#"{ "frames": [ { "side_data_list": [
+            { "side_data_type": "Mastering display metadata", "red_x": "34000/50000", "red_y": "16000/50000", "green_x": "13250/50000",
+              "green_y": "34500/50000", "blue_x": "7500/50000", "blue_y": "3000/50000", "white_point_x": "15635/50000",
+              "white_point_y": "16450/50000", "min_luminance": "50/10000", "max_luminance": "10000000/10000" },
+            { "side_data_type": "Content light level metadata", "max_content": 1000, "max_average": 400 }
+        ] } ] }"#;
+        let runner = ScriptedRunner::new(|_, _| Script::stdout(FRAME));
+        let calls = runner.calls();
+
+        let plan = planning(PROBE_COVER_HDR10, runner, |scope, info| {
+            let encoder = software_encoder(VideoCodec::Hevc);
+            block_on(plan(scope, "in.mp4", info, encoder, HdrMode::Preserve))
+        })
+        .unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert!(calls[0]
+            .args
+            .windows(2)
+            .any(|w| w[0] == "-select_streams" && w[1] == "1"));
+        assert!(plan.args().last().unwrap().contains(
+            "master-display=G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50):max-cll=1000,400"
+        ));
+    }
+}
@@ -0,0 +1,748 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::audio::AudioOptions;
+use crate::engine::Engine;
+use crate::error::{CompressError, Result};
+use crate::ladder::StreamingOptions;
+use crate::pipeline::{self, VideoOptions};
+use crate::process::JobScope;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+// One variant per compressor command, carrying exactly the arguments that command takes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(
+    tag = "kind",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum JobSpec {
+    CompressVideo {
+        input: String,
+        output: String,
+        auto_gpu: bool,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        options: VideoOptions,
+    },
+    CompressVideoStreaming {
+        input: String,
+        // Folder for the playlists, segments and renditions.json
+        output: String,
+        auto_gpu: bool,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        options: StreamingOptions,
+    },
+    CompressVideoTargetSize {
+        input: String,
+        output: String,
+        target_size_kb: f64,
+        auto_gpu: bool,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        options: VideoOptions,
+    },
+    CompressImage {
+        input: String,
+        output: String,
+        width: String,
+        height: String,
+        #[serde(default)]
+        preset: Option<String>,
+    },
+    CompressImageTargetSize {
+        input: String,
+        output: String,
+        target_size_kb: f64,
+        width: String,
+        height: String,
+        #[serde(default)]
+        preset: Option<String>,
+    },
+    CompressAudio {
+        input: String,
+        output: String,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        options: AudioOptions,
+    },
+    EnhanceImage {
+        input: String,
+        output: String,
+        scale: String,
+        format: String,
+        model_type: String,
+        face_restore: bool,
+        hyper_detail: bool,
+        tile_size: String,
+    },
+    EnhanceVideo {
+        input: String,
+        output: String,
+        ai_scale: String,
+        model_type: String,
+        face_restore: bool,
+        ai_fps: String,
+        denoise: bool,
+        stabilize: bool,
+        hyper_detail: bool,
+        tile_size: String,
+        auto_gpu: bool,
+    },
+}
+
+impl JobSpec {
+    pub fn input(&self) -> &str {
+        match self {
+            JobSpec::CompressVideo { input, .. }
+            | JobSpec::CompressVideoStreaming { input, .. }
+            | JobSpec::CompressVideoTargetSize { input, .. }
+            | JobSpec::CompressImage { input, .. }
+            | JobSpec::CompressImageTargetSize { input, .. }
+            | JobSpec::CompressAudio { input, .. }
+            | JobSpec::EnhanceImage { input, .. }
+            | JobSpec::EnhanceVideo { input, .. } => input,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: u64,
+    pub spec: JobSpec,
+    pub state: JobState,
+    pub error: Option<CompressError>,
+    // What the pipeline reported back (achieved size for target-size jobs...)
+    #[serde(default)]
+    pub result: Option<serde_json::Value>,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueueData {
+    jobs: Vec<Job>,
+    next_id: u64,
+    concurrency: usize,
+}
+
+pub struct JobQueue {
+    data: Mutex<QueueData>,
+    store_path: Mutex<Option<PathBuf>>,
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        JobQueue {
+            data: Mutex::new(QueueData {
+                jobs: vec![],
+                next_id: 1,
+                concurrency: 1,
+            }),
+            store_path: Mutex::new(None),
+        }
+    }
+}
+
+impl JobQueue {
+    // Loads the queue saved by a previous session. Jobs that were mid-flight when the app went
+    // away have no process behind them anymore, so they go back to the queue.
+    pub fn load(&self, path: PathBuf) {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            match serde_json::from_str::<QueueData>(&text) {
+                Ok(mut saved) => {
+                    for job in saved.jobs.iter_mut() {
+                        if job.state == JobState::Running {
+                            job.state = JobState::Queued;
+                            job.started_at = None;
+                        }
+                    }
+                    saved.concurrency = saved.concurrency.max(1);
+                    saved.next_id = saved
+                        .next_id
+                        .max(saved.jobs.iter().map(|j| j.id + 1).max().unwrap_or(1));
+                    *self.data.lock().unwrap() = saved;
+                }
+                Err(e) => eprintln!("⚠️ QUEUE: Ignoring unreadable queue file {:?}: {}", path, e),
+            }
+        }
+        *self.store_path.lock().unwrap() = Some(path);
+    }
+
+    fn save(&self, data: &QueueData) {
+        let store_path = self.store_path.lock().unwrap();
+        if let Some(path) = store_path.as_ref() {
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            match serde_json::to_string_pretty(data) {
+                Ok(text) => {
+                    if let Err(e) = std::fs::write(path, text) {
+                        eprintln!("⚠️ QUEUE: Failed to persist queue: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("⚠️ QUEUE: Failed to serialize queue: {}", e),
+            }
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<Job> {
+        self.data
+            .lock()
+            .unwrap()
+            .jobs
+            .iter()
+            .find(|j| j.id == id)
+            .cloned()
+    }
+
+    // Applies `f` to the job under the lock, persists the queue and returns the updated job.
+    fn update<F: FnOnce(&mut Job) -> Result<()>>(&self, id: u64, f: F) -> Result<Job> {
+        let mut data = self.data.lock().unwrap();
+        let job = data
+            .jobs
+            .iter_mut()
+            .find(|j| j.id == id)
+            .ok_or(CompressError::JobNotFound { id })?;
+        f(job)?;
+        let updated = job.clone();
+        self.save(&data);
+        Ok(updated)
+    }
+
+    // Picks as many queued jobs as the concurrency limit allows and marks them running.
+    fn take_runnable(&self) -> Vec<Job> {
+        let mut data = self.data.lock().unwrap();
+        let running = data
+            .jobs
+            .iter()
+            .filter(|j| j.state == JobState::Running)
+            .count();
+        let free = data.concurrency.saturating_sub(running);
+        let mut started = vec![];
+        for job in data
+            .jobs
+            .iter_mut()
+            .filter(|j| j.state == JobState::Queued)
+            .take(free)
+        {
+            job.state = JobState::Running;
+            job.started_at = Some(now_ms());
+            job.error = None;
+            job.result = None;
+            started.push(job.clone());
+        }
+        if !started.is_empty() {
+            self.save(&data);
+        }
+        started
+    }
+
+    fn add(&self, spec: JobSpec) -> Job {
+        let mut data = self.data.lock().unwrap();
+        let job = Job {
+            id: data.next_id,
+            spec,
+            state: JobState::Queued,
+            error: None,
+            result: None,
+            created_at: now_ms(),
+            started_at: None,
+            finished_at: None,
+        };
+        data.next_id += 1;
+        data.jobs.push(job.clone());
+        self.save(&data);
+        job
+    }
+
+    // Marks the job cancelled; true when it was running and its processes have to be stopped.
+    fn cancel(&self, id: u64) -> Result<(Job, bool)> {
+        let mut was_running = false;
+        let job = self.update(id, |job| {
+            if job.state.is_finished() {
+                return Err(CompressError::invalid(format!(
+                    "Job {} already finished",
+                    id
+                )));
+            }
+            was_running = job.state == JobState::Running;
+            job.state = JobState::Cancelled;
+            job.finished_at = Some(now_ms());
+            Ok(())
+        })?;
+        Ok((job, was_running))
+    }
+
+    fn pause(&self, id: u64) -> Result<Job> {
+        self.update(id, |job| {
+            if job.state != JobState::Queued {
+                return Err(CompressError::invalid(format!(
+                    "Only queued jobs can be paused (job {} is {:?})",
+                    id, job.state
+                )));
+            }
+            job.state = JobState::Paused;
+            Ok(())
+        })
+    }
+
+    fn resume(&self, id: u64) -> Result<Job> {
+        self.update(id, |job| {
+            if job.state != JobState::Paused {
+                return Err(CompressError::invalid(format!("Job {} is not paused", id)));
+            }
+            job.state = JobState::Queued;
+            Ok(())
+        })
+    }
+
+    fn set_concurrency(&self, concurrency: usize) -> Result<()> {
+        if concurrency == 0 {
+            return Err(CompressError::invalid("Concurrency must be at least 1"));
+        }
+        let mut data = self.data.lock().unwrap();
+        data.concurrency = concurrency;
+        self.save(&data);
+        Ok(())
+    }
+}
+
+fn notify(app: &AppHandle, job: &Job) {
+    let _ = app.emit("job-updated", job.clone());
+}
+
+// Starts queued jobs until the concurrency limit is reached. Called whenever a slot may have freed up.
+pub fn pump(app: &AppHandle) {
+    let queue = app.state::<JobQueue>();
+    for job in queue.take_runnable() {
+        notify(app, &job);
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            eprintln!("▶️ QUEUE: Starting job {}", job.id);
+            let result = run_spec(&app, job.id, job.spec.clone()).await;
+            finish(&app, job.id, result);
+        });
+    }
+}
+
+fn finish(app: &AppHandle, id: u64, result: Result<Option<serde_json::Value>>) {
+    let queue = app.state::<JobQueue>();
+    let updated = queue.update(id, |job| {
+        // A job cancelled through cancel_job is already marked; keep that state.
+        if job.state == JobState::Running {
+            match &result {
+                Ok(value) => {
+                    job.state = JobState::Done;
+                    job.result = value.clone();
+                }
+                Err(CompressError::Cancelled) => job.state = JobState::Cancelled,
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(e.clone());
+                }
+            }
+        }
+        job.finished_at = Some(now_ms());
+        Ok(())
+    });
+    match updated {
+        Ok(job) => {
+            eprintln!("⏹️ QUEUE: Job {} finished as {:?}", id, job.state);
+            notify(app, &job);
+        }
+        Err(e) => eprintln!("⚠️ QUEUE: {}", e),
+    }
+    pump(app);
+}
+
+// Pipelines that report something have it serialized into `Job::result`.
+fn report<T: Serialize>(value: T) -> Option<serde_json::Value> {
+    serde_json::to_value(value).ok().filter(|v| !v.is_null())
+}
+
+async fn run_spec(app: &AppHandle, id: u64, spec: JobSpec) -> Result<Option<serde_json::Value>> {
+    let engine = app.state::<Engine>();
+    let engine = engine.inner();
+    // Registered for the whole run and handed to the pipeline, so cancel_job reaches every
+    // process it starts, probes included
+    let scope = JobScope::new(engine, Some(id.to_string()));
+    match spec {
+        JobSpec::CompressVideo {
+            input,
+            output,
+            auto_gpu,
+            preset,
+            options,
+        } => pipeline::compress_video(&scope, input, output, auto_gpu, preset, options)
+            .await
+            .map(report),
+        JobSpec::CompressVideoStreaming {
+            input,
+            output,
+            auto_gpu,
+            preset,
+            options,
+        } => pipeline::compress_video_streaming(&scope, input, output, auto_gpu, preset, options)
+            .await
+            .map(report),
+        JobSpec::CompressVideoTargetSize {
+            input,
+            output,
+            target_size_kb,
+            auto_gpu,
+            preset,
+            options,
+        } => pipeline::compress_video_target_size(
+            &scope,
+            input,
+            output,
+            target_size_kb,
+            auto_gpu,
+            preset,
+            options,
+        )
+        .await
+        .map(report),
+        JobSpec::CompressImage {
+            input,
+            output,
+            width,
+            height,
+            preset,
+        } => pipeline::compress_image(&scope, input, output, width, height, preset)
+            .await
+            .map(|_| None),
+        JobSpec::CompressImageTargetSize {
+            input,
+            output,
+            target_size_kb,
+            width,
+            height,
+            preset,
+        } => pipeline::compress_image_target_size(
+            &scope,
+            input,
+            output,
+            target_size_kb,
+            width,
+            height,
+            preset,
+        )
+        .await
+        .map(|_| None),
+        JobSpec::CompressAudio {
+            input,
+            output,
+            preset,
+            options,
+        } => pipeline::compress_audio(&scope, input, output, preset, options)
+            .await
+            .map(report),
+        JobSpec::EnhanceImage {
+            input,
+            output,
+            scale,
+            format,
+            model_type,
+            face_restore,
+            hyper_detail,
+            tile_size,
+        } => pipeline::enhance_image(
+            &scope,
+            input,
+            output,
+            scale,
+            format,
+            model_type,
+            face_restore,
+            hyper_detail,
+            tile_size,
+        )
+        .await
+        .map(|_| None),
+        JobSpec::EnhanceVideo {
+            input,
+            output,
+            ai_scale,
+            model_type,
+            face_restore,
+            ai_fps,
+            denoise,
+            stabilize,
+            hyper_detail,
+            tile_size,
+            auto_gpu,
+        } => pipeline::enhance_video(
+            &scope,
+            input,
+            output,
+            ai_scale,
+            model_type,
+            face_restore,
+            ai_fps,
+            denoise,
+            stabilize,
+            hyper_detail,
+            tile_size,
+            auto_gpu,
+        )
+        .await
+        .map(|_| None),
+    }
+}
+
+#[tauri::command]
+pub fn enqueue_job(
+    app: AppHandle,
+    queue: tauri::State<'_, JobQueue>,
+    spec: JobSpec,
+) -> Result<Job> {
+    if !std::path::Path::new(spec.input()).exists() {
+        return Err(CompressError::input_not_found(spec.input()));
+    }
+    let job = queue.add(spec);
+    notify(&app, &job);
+    pump(&app);
+    Ok(queue.get(job.id).unwrap_or(job))
+}
+
+#[tauri::command]
+pub fn list_jobs(queue: tauri::State<'_, JobQueue>) -> Vec<Job> {
+    queue.data.lock().unwrap().jobs.clone()
+}
+
+#[tauri::command]
+pub fn cancel_job(
+    app: AppHandle,
+    queue: tauri::State<'_, JobQueue>,
+    engine: tauri::State<'_, Engine>,
+    id: u64,
+) -> Result<Job> {
+    let (job, was_running) = queue.cancel(id)?;
+    if was_running {
+        engine.registry.cancel(&id.to_string());
+    }
+    notify(&app, &job);
+    pump(&app);
+    Ok(job)
+}
+
+#[tauri::command]
+pub fn pause_job(app: AppHandle, queue: tauri::State<'_, JobQueue>, id: u64) -> Result<Job> {
+    let job = queue.pause(id)?;
+    notify(&app, &job);
+    Ok(job)
+}
+
+#[tauri::command]
+pub fn resume_job(app: AppHandle, queue: tauri::State<'_, JobQueue>, id: u64) -> Result<Job> {
+    let job = queue.resume(id)?;
+    notify(&app, &job);
+    pump(&app);
+    Ok(queue.get(id).unwrap_or(job))
+}
+
+#[tauri::command]
+pub fn set_queue_concurrency(
+    app: AppHandle,
+    queue: tauri::State<'_, JobQueue>,
+    concurrency: usize,
+) -> Result<()> {
+    queue.set_concurrency(concurrency)?;
+    pump(&app);
+    Ok(())
+}
+
+// Drops done/failed/cancelled jobs from the list so long sessions don't grow the queue file forever.
+#[tauri::command]
+pub fn clear_finished_jobs(queue: tauri::State<'_, JobQueue>) {
+    let mut data = queue.data.lock().unwrap();
+    data.jobs.retain(|j| !j.state.is_finished());
+    queue.save(&data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn image(name: &str) -> JobSpec {
+        JobSpec::CompressImage {
+            input: format!("{}.png", name),
+            output: format!("{}.jpg", name),
+            width: "0".to_string(),
+            height: "0".to_string(),
+            preset: None,
+        }
+    }
+
+    fn states(queue: &JobQueue) -> Vec<(u64, JobState)> {
+        queue
+            .data
+            .lock()
+            .unwrap()
+            .jobs
+            .iter()
+            .map(|j| (j.id, j.state))
+            .collect()
+    }
+
+    fn ids(jobs: &[Job]) -> Vec<u64> {
+        jobs.iter().map(|j| j.id).collect()
+    }
+
+    #[test]
+    fn load_puts_interrupted_jobs_back_in_the_queue() {
+        let dir = TempDir::new("queue-load");
+        let path = dir.0.join("queue.json");
+        let saved = JobQueue::default();
+        for name in ["a", "b", "c"] {
+            saved.add(image(name));
+        }
+        {
+            let mut data = saved.data.lock().unwrap();
+            data.jobs[0].state = JobState::Done;
+            data.jobs[1].state = JobState::Running;
+            data.jobs[1].started_at = Some(now_ms());
+            // Written by a build that allowed these
+            data.next_id = 1;
+            data.concurrency = 0;
+        }
+        std::fs::write(
+            &path,
+            serde_json::to_string(&*saved.data.lock().unwrap()).unwrap(),
+        )
+        .unwrap();
+
+        let queue = JobQueue::default();
+        queue.load(path.clone());
+
+        assert_eq!(
+            states(&queue),
+            vec![
+                (1, JobState::Done),
+                (2, JobState::Queued),
+                (3, JobState::Queued)
+            ]
+        );
+        assert_eq!(queue.get(2).unwrap().started_at, None);
+        assert_eq!(queue.data.lock().unwrap().concurrency, 1);
+        // Ids are never handed out twice
+        assert_eq!(queue.add(image("d")).id, 4);
+        let reloaded = JobQueue::default();
+        reloaded.load(path);
+        assert_eq!(states(&reloaded).len(), 4);
+    }
+
+    #[test]
+    fn load_starts_empty_from_a_missing_or_unreadable_file() {
+        let dir = TempDir::new("queue-garbage");
+        let missing = JobQueue::default();
+        missing.load(dir.0.join("nested").join("queue.json"));
+        let garbage = JobQueue::default();
+        garbage.load(PathBuf::from(dir.file("queue.json", b"{ not json")));
+
+        assert!(states(&missing).is_empty() && states(&garbage).is_empty());
+        // The path is kept for saving all the same
+        missing.add(image("a"));
+        assert!(dir.0.join("nested").join("queue.json").exists());
+    }
+
+    #[test]
+    fn take_runnable_stays_within_the_concurrency_limit() {
+        let queue = JobQueue::default();
+        for name in ["a", "b", "c", "d"] {
+            queue.add(image(name));
+        }
+        queue.set_concurrency(2).unwrap();
+        queue.pause(2).unwrap();
+
+        // Paused jobs are skipped
+        assert_eq!(ids(&queue.take_runnable()), vec![1, 3]);
+        assert!(queue.take_runnable().is_empty());
+        assert!(queue.get(1).unwrap().started_at.is_some());
+
+        queue.cancel(1).unwrap();
+        assert_eq!(ids(&queue.take_runnable()), vec![4]);
+        assert!(matches!(
+            queue.set_concurrency(0),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn pause_resume_and_cancel_only_apply_to_jobs_in_the_right_state() {
+        let queue = JobQueue::default();
+        for name in ["a", "b"] {
+            queue.add(image(name));
+        }
+        queue.take_runnable();
+
+        assert!(matches!(
+            queue.pause(1),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            queue.resume(2),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert_eq!(queue.pause(2).unwrap().state, JobState::Paused);
+        assert!(matches!(
+            queue.pause(2),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert_eq!(queue.resume(2).unwrap().state, JobState::Queued);
+
+        // Only a running job has processes to stop
+        let (running, was_running) = queue.cancel(1).unwrap();
+        assert_eq!((running.state, was_running), (JobState::Cancelled, true));
+        assert!(running.finished_at.is_some());
+        let (queued, was_running) = queue.cancel(2).unwrap();
+        assert_eq!((queued.state, was_running), (JobState::Cancelled, false));
+        assert!(matches!(
+            queue.cancel(2),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            queue.cancel(9),
+            Err(CompressError::JobNotFound { id: 9 })
+        ));
+    }
+}
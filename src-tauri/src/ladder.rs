@@ -0,0 +1,502 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::{CompressError, Result};
+use crate::probe::VideoStream;
+
+// --- Adaptive streaming ladders (HLS, DASH/CMAF) ---
+// One ffmpeg run produces every rung: the picture is split and scaled in a filter graph, keyframes
+// are forced on segment boundaries in all rungs so players can switch at any segment, and ffmpeg's
+// hls or dash muxer cuts the segments and writes the playlists. Audio is encoded once and shared by
+// all rungs (an HLS audio group / a DASH adaptation set). Rungs are H.264, which every HLS player decodes.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rendition {
+    // Short side in pixels: 720 is 1280x720 landscape or 720x1280 portrait
+    pub height: u32,
+    pub video_kbps: u32,
+}
+
+pub const DEFAULT_LADDER: &[Rendition] = &[
+    Rendition {
+        height: 1080,
+        video_kbps: 5000,
+    },
+    Rendition {
+        height: 720,
+        video_kbps: 2800,
+    },
+    Rendition {
+        height: 480,
+        video_kbps: 1400,
+    },
+    Rendition {
+        height: 360,
+        video_kbps: 800,
+    },
+];
+
+const DEFAULT_SEGMENT_SECONDS: f64 = 6.0;
+const DEFAULT_AUDIO_KBPS: u32 = 128;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StreamingOptions {
+    // Rungs in any order, DEFAULT_LADDER when empty. Rungs larger than the source are left out.
+    pub renditions: Vec<Rendition>,
+    // Target segment length, 6 s when unset
+    pub segment_seconds: Option<f64>,
+    // Also write a DASH manifest. Segments are then CMAF (fMP4) and shared by HLS and DASH.
+    pub dash: bool,
+    pub audio_kbps: Option<u32>,
+}
+
+// What ended up in the output folder, also written there as renditions.json. Paths are relative to it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamingManifest {
+    pub master_playlist: String,
+    pub dash_manifest: Option<String>,
+    pub segment_seconds: f64,
+    pub renditions: Vec<RenditionOutput>,
+    pub audio: Option<AudioOutput>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RenditionOutput {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub video_kbps: u32,
+    pub playlist: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioOutput {
+    pub kbps: u32,
+    pub playlist: String,
+}
+
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+pub const DASH_MANIFEST: &str = "manifest.mpd";
+pub const MANIFEST_FILE: &str = "renditions.json";
+// HLS variant folder of the shared audio
+const AUDIO_NAME: &str = "audio";
+
+struct Rung {
+    name: String,
+    width: u32,
+    height: u32,
+    video_kbps: u32,
+}
+
+pub struct Ladder {
+    rungs: Vec<Rung>,
+    segment_seconds: f64,
+    dash: bool,
+    audio_kbps: u32,
+}
+
+fn even(v: f64) -> u32 {
+    ((v / 2.0).round() as u32 * 2).max(2)
+}
+
+impl Ladder {
+    // Fits the requested rungs to the source: largest first, nothing above the source's short side.
+    // A source smaller than every rung gets one rung at its own size with the smallest bitrate.
+    pub fn new(options: &StreamingOptions, video: &VideoStream) -> Result<Ladder> {
+        let segment_seconds = options.segment_seconds.unwrap_or(DEFAULT_SEGMENT_SECONDS);
+        if segment_seconds <= 0.0 {
+            return Err(CompressError::invalid("Segment length must be positive"));
+        }
+        let mut wanted = if options.renditions.is_empty() {
+            DEFAULT_LADDER.to_vec()
+        } else {
+            options.renditions.clone()
+        };
+        if wanted.iter().any(|r| r.height < 2 || r.video_kbps == 0) {
+            return Err(CompressError::invalid(
+                "Every rendition needs a height of at least 2 pixels and a bitrate",
+            ));
+        }
+        wanted.sort_by_key(|r| std::cmp::Reverse(r.height));
+        wanted.dedup_by_key(|r| r.height);
+
+        // Players show the picture rotated, so the ladder works on the displayed size
+        let (width, height) = video.display_size();
+        if width == 0 || height == 0 {
+            return Err(CompressError::ProbeFailed {
+                detail: "Could not determine video size".to_string(),
+            });
+        }
+        let (short, long) = (width.min(height), width.max(height));
+        let mut fitting: Vec<Rendition> = wanted
+            .iter()
+            .copied()
+            .filter(|r| r.height <= short)
+            .collect();
+        if fitting.is_empty() {
+            let smallest = wanted.last().expect("ladder is never empty");
+            fitting.push(Rendition {
+                height: short & !1,
+                video_kbps: smallest.video_kbps,
+            });
+        }
+
+        let rungs = fitting
+            .into_iter()
+            .map(|r| {
+                let side = even(long as f64 * r.height as f64 / short as f64);
+                let (w, h) = if width >= height {
+                    (side, even(r.height as f64))
+                } else {
+                    (even(r.height as f64), side)
+                };
+                Rung {
+                    name: format!("{}p", r.height),
+                    width: w,
+                    height: h,
+                    video_kbps: r.video_kbps,
+                }
+            })
+            .collect();
+        Ok(Ladder {
+            rungs,
+            segment_seconds,
+            dash: options.dash,
+            audio_kbps: options.audio_kbps.unwrap_or(DEFAULT_AUDIO_KBPS),
+        })
+    }
+
+    // Variant folders inside the output folder; the hls muxer doesn't create them itself
+    pub fn variant_dirs(&self, has_audio: bool) -> Vec<String> {
+        if self.dash {
+            return vec![];
+        }
+        let mut dirs: Vec<String> = self.rungs.iter().map(|r| r.name.clone()).collect();
+        if has_audio {
+            dirs.push(AUDIO_NAME.to_string());
+        }
+        dirs
+    }
+
+    // Split, scale and map one output pad per rung. `pre` runs once before the split (tone mapping).
+    pub fn video_args(&self, video_index: u32, pre: Option<&str>) -> Vec<String> {
+        let pre = pre.map(|p| format!("{},", p)).unwrap_or_default();
+        let mut graph = format!("[0:{}]{}split={}", video_index, pre, self.rungs.len());
+        for n in 0..self.rungs.len() {
+            graph.push_str(&format!("[s{}]", n));
+        }
+        for (n, rung) in self.rungs.iter().enumerate() {
+            graph.push_str(&format!(
+                ";[s{}]scale={}:{}[v{}]",
+                n, rung.width, rung.height, n
+            ));
+        }
+        let mut args = vec!["-filter_complex".to_string(), graph];
+        for n in 0..self.rungs.len() {
+            args.extend(["-map".to_string(), format!("[v{}]", n)]);
+        }
+        args
+    }
+
+    // Per-rung bitrates with a VBV cap, so no segment runs far over its advertised bandwidth, and
+    // keyframes on every segment boundary.
+    pub fn rate_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for (n, rung) in self.rungs.iter().enumerate() {
+            args.extend([
+                format!("-b:v:{}", n),
+                format!("{}k", rung.video_kbps),
+                format!("-maxrate:v:{}", n),
+                format!("{}k", rung.video_kbps * 107 / 100),
+                format!("-bufsize:v:{}", n),
+                format!("{}k", rung.video_kbps * 3 / 2),
+            ]);
+        }
+        args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", self.segment_seconds),
+        ]);
+        args
+    }
+
+    // One shared AAC stereo rendition of the source's first audio stream
+    pub fn audio_args(&self, audio_index: u32) -> Vec<String> {
+        vec![
+            "-map".to_string(),
+            format!("0:{}", audio_index),
+            "-c:a".to_string(),
+            "aac".to_string(),
+            "-b:a".to_string(),
+            format!("{}k", self.audio_kbps),
+            "-ac".to_string(),
+            "2".to_string(),
+        ]
+    }
+
+    // Muxer options and the output path inside `dir`
+    pub fn muxer_args(&self, dir: &Path, has_audio: bool) -> Vec<String> {
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let segment = self.segment_seconds.to_string();
+        if self.dash {
+            let sets = if has_audio {
+                "id=0,streams=v id=1,streams=a"
+            } else {
+                "id=0,streams=v"
+            };
+            return vec![
+                "-f".to_string(),
+                "dash".to_string(),
+                "-seg_duration".to_string(),
+                segment,
+                "-use_template".to_string(),
+                "1".to_string(),
+                "-use_timeline".to_string(),
+                "1".to_string(),
+                "-adaptation_sets".to_string(),
+                sets.to_string(),
+                "-init_seg_name".to_string(),
+                "init-$RepresentationID$.m4s".to_string(),
+                "-media_seg_name".to_string(),
+                "chunk-$RepresentationID$-$Number%05d$.m4s".to_string(),
+                "-hls_playlist".to_string(),
+                "1".to_string(),
+                "-hls_master_name".to_string(),
+                MASTER_PLAYLIST.to_string(),
+                path(DASH_MANIFEST),
+            ];
+        }
+        let group = if has_audio {
+            format!(",agroup:{}", AUDIO_NAME)
+        } else {
+            String::new()
+        };
+        let mut streams: Vec<String> = self
+            .rungs
+            .iter()
+            .enumerate()
+            .map(|(n, r)| format!("v:{}{},name:{}", n, group, r.name))
+            .collect();
+        if has_audio {
+            streams.push(format!("a:0{},name:{}", group, AUDIO_NAME));
+        }
+        vec![
+            "-f".to_string(),
+            "hls".to_string(),
+            "-hls_time".to_string(),
+            segment,
+            "-hls_playlist_type".to_string(),
+            "vod".to_string(),
+            "-hls_flags".to_string(),
+            "independent_segments".to_string(),
+            "-hls_segment_filename".to_string(),
+            path("%v/segment_%05d.ts"),
+            "-master_pl_name".to_string(),
+            MASTER_PLAYLIST.to_string(),
+            "-var_stream_map".to_string(),
+            streams.join(" "),
+            path("%v/index.m3u8"),
+        ]
+    }
+
+    pub fn manifest(&self, has_audio: bool) -> StreamingManifest {
+        // The dash muxer names its HLS playlists after the representation index
+        let playlist = |n: usize, name: &str| {
+            if self.dash {
+                format!("media_{}.m3u8", n)
+            } else {
+                format!("{}/index.m3u8", name)
+            }
+        };
+        StreamingManifest {
+            master_playlist: MASTER_PLAYLIST.to_string(),
+            dash_manifest: self.dash.then(|| DASH_MANIFEST.to_string()),
+            segment_seconds: self.segment_seconds,
+            renditions: self
+                .rungs
+                .iter()
+                .enumerate()
+                .map(|(n, r)| RenditionOutput {
+                    name: r.name.clone(),
+                    width: r.width,
+                    height: r.height,
+                    video_kbps: r.video_kbps,
+                    playlist: playlist(n, &r.name),
+                })
+                .collect(),
+            audio: has_audio.then(|| AudioOutput {
+                kbps: self.audio_kbps,
+                playlist: playlist(self.rungs.len(), AUDIO_NAME),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::parse_ffprobe_json;
+
+    // Picture of `width`x`height` with an optional display matrix rotation, as ffprobe prints it
+    fn video(width: u32, height: u32, rotation: i32) -> VideoStream {
+        let json = format!(
+            r#"{{
+              "streams": [
+                {{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": {}, "height": {}, "avg_frame_rate": "30/1",
+                  "side_data_list": [ {{ "side_data_type": "Display Matrix", "rotation": {} }} ] }}
+              ],
+              "format": {{ "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.000000" }}
+            }}"#,
+            width, height, rotation
+        );
+        parse_ffprobe_json(&json).unwrap().video.remove(0)
+    }
+
+    fn rungs(ladder: &Ladder) -> Vec<(&str, u32, u32, u32)> {
+        ladder
+            .rungs
+            .iter()
+            .map(|r| (r.name.as_str(), r.width, r.height, r.video_kbps))
+            .collect()
+    }
+
+    fn hls(renditions: &[Rendition]) -> StreamingOptions {
+        StreamingOptions {
+            renditions: renditions.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn landscape_source_gets_every_rung_up_to_its_height() {
+        let ladder = Ladder::new(&StreamingOptions::default(), &video(1280, 720, 0)).unwrap();
+
+        assert_eq!(
+            rungs(&ladder),
+            vec![
+                ("720p", 1280, 720, 2800),
+                ("480p", 854, 480, 1400),
+                ("360p", 640, 360, 800)
+            ]
+        );
+    }
+
+    #[test]
+    fn portrait_source_keeps_the_short_side_as_the_rung_height() {
+        // A phone clip stored landscape and shown upright
+        let ladder = Ladder::new(&StreamingOptions::default(), &video(1920, 1080, -90)).unwrap();
+
+        assert_eq!(
+            rungs(&ladder),
+            vec![
+                ("1080p", 1080, 1920, 5000),
+                ("720p", 720, 1280, 2800),
+                ("480p", 480, 854, 1400),
+                ("360p", 360, 640, 800)
+            ]
+        );
+    }
+
+    #[test]
+    fn source_smaller_than_every_rung_gets_one_at_its_own_size() {
+        // The odd short side is rounded down to even
+        let ladder = Ladder::new(&StreamingOptions::default(), &video(426, 241, 0)).unwrap();
+
+        assert_eq!(rungs(&ladder), vec![("240p", 424, 240, 800)]);
+    }
+
+    #[test]
+    fn duplicate_heights_keep_the_first_one_asked_for() {
+        let options = hls(&[
+            Rendition {
+                height: 720,
+                video_kbps: 2800,
+            },
+            Rendition {
+                height: 1080,
+                video_kbps: 5000,
+            },
+            Rendition {
+                height: 720,
+                video_kbps: 2000,
+            },
+        ]);
+
+        let ladder = Ladder::new(&options, &video(1920, 1080, 0)).unwrap();
+
+        assert_eq!(
+            rungs(&ladder),
+            vec![("1080p", 1920, 1080, 5000), ("720p", 1280, 720, 2800)]
+        );
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let source = video(1920, 1080, 0);
+        let segments = |seconds| StreamingOptions {
+            segment_seconds: Some(seconds),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Ladder::new(&segments(0.0), &source),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            Ladder::new(&segments(-6.0), &source),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            Ladder::new(
+                &hls(&[Rendition {
+                    height: 720,
+                    video_kbps: 0
+                }]),
+                &source
+            ),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            Ladder::new(
+                &hls(&[Rendition {
+                    height: 1,
+                    video_kbps: 800
+                }]),
+                &source
+            ),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn var_stream_map_puts_every_rung_in_the_audio_group() {
+        let options = hls(&[
+            Rendition {
+                height: 1080,
+                video_kbps: 5000,
+            },
+            Rendition {
+                height: 720,
+                video_kbps: 2800,
+            },
+        ]);
+        let ladder = Ladder::new(&options, &video(1920, 1080, 0)).unwrap();
+        let stream_map = |has_audio| {
+            let args = ladder.muxer_args(Path::new("out"), has_audio);
+            let n = args.iter().position(|a| a == "-var_stream_map").unwrap();
+            args[n + 1].clone()
+        };
+
+        assert_eq!(
+            stream_map(true),
+            "v:0,agroup:audio,name:1080p v:1,agroup:audio,name:720p a:0,agroup:audio,name:audio"
+        );
+        assert_eq!(stream_map(false), "v:0,name:1080p v:1,name:720p");
+        assert_eq!(ladder.variant_dirs(true), vec!["1080p", "720p", "audio"]);
+        assert_eq!(ladder.variant_dirs(false), vec!["1080p", "720p"]);
+    }
+}
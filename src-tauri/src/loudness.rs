@@ -0,0 +1,351 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Tool;
+use crate::error::{CompressError, Result};
+use crate::process::JobScope;
+
+// --- Loudness normalization (EBU R128) ---
+// Two-pass loudnorm: a first run measures integrated loudness, true peak and loudness range, the
+// encode then applies one fixed gain (linear mode) worked out from those numbers, so the dynamics
+// of the recording stay as they are. loudnorm falls back to its dynamic mode when that gain would
+// push peaks over the ceiling. The written file is measured again for the job's report.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessTarget {
+    // Integrated loudness in LUFS: -14 for streaming sites, -16 for podcasts, -23 for EBU R128 broadcast
+    pub integrated: f64,
+    // True peak ceiling in dBTP, -1 when unset
+    #[serde(default)]
+    pub true_peak: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessStats {
+    // LUFS
+    pub integrated: f64,
+    // dBTP
+    pub true_peak: f64,
+    // LU
+    pub range: f64,
+}
+
+// Returned with the job result
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessReport {
+    pub target: f64,
+    pub before: LoudnessStats,
+    pub after: LoudnessStats,
+    // False when loudnorm had to compress the dynamics to stay under the peak ceiling
+    pub linear: bool,
+}
+
+// First pass result, input for the normalizing filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub stats: LoudnessStats,
+    threshold: f64,
+    offset: f64,
+}
+
+// What the encode needs: the -af filter and what to report afterwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalization {
+    pub filter: String,
+    target: LoudnessTarget,
+    before: LoudnessStats,
+    linear: bool,
+}
+
+const DEFAULT_TRUE_PEAK: f64 = -1.0;
+const DEFAULT_RANGE: f64 = 11.0;
+// Older loudnorm builds reject a range target above 20 LU
+const MAX_RANGE: f64 = 20.0;
+
+impl LoudnessTarget {
+    pub fn validate(&self) -> Result<()> {
+        if !(-70.0..=-5.0).contains(&self.integrated) {
+            return Err(CompressError::invalid(
+                "Loudness target must be between -70 and -5 LUFS",
+            ));
+        }
+        if self
+            .true_peak
+            .map(|tp| !(-9.0..=0.0).contains(&tp))
+            .unwrap_or(false)
+        {
+            return Err(CompressError::invalid(
+                "True peak ceiling must be between -9 and 0 dBTP",
+            ));
+        }
+        Ok(())
+    }
+
+    fn true_peak(&self) -> f64 {
+        self.true_peak.unwrap_or(DEFAULT_TRUE_PEAK)
+    }
+
+    // Filter for the measuring run, the numbers it prints don't depend on the targets
+    pub fn measure_filter(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            self.integrated,
+            self.true_peak(),
+            DEFAULT_RANGE
+        )
+    }
+
+    // Linear normalization from a measurement. loudnorm resamples to 192 kHz internally, aresample
+    // brings the source rate back. None for silence, there's no gain that makes it -16 LUFS.
+    pub fn normalize(&self, m: &Measurement, sample_rate: u32) -> Option<Normalization> {
+        if !m.stats.integrated.is_finite() || !m.stats.true_peak.is_finite() {
+            return None;
+        }
+        // A range target below the source's own would make loudnorm squeeze it dynamically
+        let range = m.stats.range.ceil().clamp(DEFAULT_RANGE, MAX_RANGE);
+        let linear = m.stats.true_peak + (self.integrated - m.stats.integrated) <= self.true_peak()
+            && m.stats.range <= MAX_RANGE;
+        let filter = format!(
+            "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true,aresample={}",
+            self.integrated, self.true_peak(), range, m.stats.integrated, m.stats.true_peak, m.stats.range, m.threshold, m.offset, sample_rate
+        );
+        Some(Normalization {
+            filter,
+            target: *self,
+            before: m.stats,
+            linear,
+        })
+    }
+}
+
+// loudnorm's print_format=json block, closing its run's stderr:
+//   "input_i" : "-27.61",
+//   "input_tp" : "-4.47", ...
+// Silence is reported as "-inf".
+pub fn parse(stderr: &str) -> Option<Measurement> {
+    let value = |key: &str| -> Option<f64> {
+        let needle = format!("\"{}\"", key);
+        stderr
+            .lines()
+            .rev()
+            .find(|l| l.contains(&needle))?
+            .split(':')
+            .nth(1)?
+            .trim()
+            .trim_end_matches(',')
+            .trim_matches('"')
+            .parse()
+            .ok()
+    };
+    Some(Measurement {
+        stats: LoudnessStats {
+            integrated: value("input_i")?,
+            true_peak: value("input_tp")?,
+            range: value("input_lra")?,
+        },
+        threshold: value("input_thresh")?,
+        offset: value("target_offset").unwrap_or(0.0),
+    })
+}
+
+// Runs `args` (inputs, stream selection and the measure filter) into the null muxer
+pub async fn measure(scope: &JobScope<'_>, mut args: Vec<String>) -> Result<Measurement> {
+    args.extend(["-f".to_string(), "null".to_string(), "-".to_string()]);
+    let out = scope.output(Tool::Ffmpeg, args).await?;
+    if !out.success() {
+        return Err(CompressError::from_ffmpeg_stderr(out.code, &out.stderr));
+    }
+    parse(&String::from_utf8_lossy(&out.stderr))
+        .ok_or_else(|| CompressError::internal("ffmpeg printed no loudness measurement"))
+}
+
+// Measures the first pass and works out the normalization, None (with a note) for silent audio
+pub async fn first_pass(
+    scope: &JobScope<'_>,
+    target: LoudnessTarget,
+    args: Vec<String>,
+    sample_rate: u32,
+) -> Result<Option<Normalization>> {
+    target.validate()?;
+    let measured = measure(scope, args).await?;
+    let plan = target.normalize(&measured, sample_rate);
+    let note = match &plan {
+        Some(plan) if plan.linear => format!(
+            "🔊 Loudness: {:.1} LUFS, {:.1} dBTP, normalizing to {} LUFS",
+            measured.stats.integrated, measured.stats.true_peak, target.integrated
+        ),
+        Some(_) => format!(
+            "⚠️ Loudness: {:.1} LUFS, {:.1} dBTP; {} LUFS would clip at a fixed gain, loudnorm compresses dynamically",
+            measured.stats.integrated, measured.stats.true_peak, target.integrated
+        ),
+        None => "⚠️ Loudness: the audio is silent, leaving it as it is".to_string(),
+    };
+    scope.engine.note(note);
+    Ok(plan)
+}
+
+// Measures the written file's first audio track for the report
+pub async fn report(
+    scope: &JobScope<'_>,
+    output: &str,
+    normalization: &Normalization,
+) -> Result<LoudnessReport> {
+    let args = vec![
+        "-i".to_string(),
+        output.to_string(),
+        "-map".to_string(),
+        "0:a:0".to_string(),
+        "-af".to_string(),
+        normalization.target.measure_filter(),
+    ];
+    let after = measure(scope, args).await?.stats;
+    Ok(LoudnessReport {
+        target: normalization.target.integrated,
+        before: normalization.before,
+        after,
+        linear: normalization.linear,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What loudnorm prints at the end of a print_format=json run
+    const MEASURED: &str = r#"size=N/A time=00:00:10.00 bitrate=N/A speed= 312x
+[Parsed_loudnorm_0 @ 0x55d0f4c3a2c0] 
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-14.20",
+	"input_lra" : "6.20",
+	"input_thresh" : "-38.10",
+	"output_i" : "-16.05",
+	"output_tp" : "-2.55",
+	"output_lra" : "5.90",
+	"output_thresh" : "-26.50",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.05"
+}"#;
+
+    fn measurement(integrated: f64, true_peak: f64, range: f64) -> Measurement {
+        Measurement {
+            stats: LoudnessStats {
+                integrated,
+                true_peak,
+                range,
+            },
+            threshold: -38.1,
+            offset: 0.05,
+        }
+    }
+
+    fn target(integrated: f64, true_peak: Option<f64>) -> LoudnessTarget {
+        LoudnessTarget {
+            integrated,
+            true_peak,
+        }
+    }
+
+    #[test]
+    fn parse_reads_the_input_side_of_the_json_block() {
+        assert_eq!(parse(MEASURED), Some(measurement(-27.61, -14.2, 6.2)));
+    }
+
+    #[test]
+    fn parse_keeps_silence_as_negative_infinity() {
+        let silent = MEASURED
+            .replace("\"-27.61\"", "\"-inf\"")
+            .replace("\"-14.20\"", "\"-inf\"");
+
+        let m = parse(&silent).unwrap();
+
+        assert_eq!(
+            (m.stats.integrated, m.stats.true_peak),
+            (f64::NEG_INFINITY, f64::NEG_INFINITY)
+        );
+        assert_eq!(target(-16.0, None).normalize(&m, 48000), None);
+    }
+
+    #[test]
+    fn parse_without_the_json_block_is_none() {
+        assert_eq!(
+            parse("[Parsed_loudnorm_0 @ 0x55d0] \nsize=N/A time=00:00:10.00"),
+            None
+        );
+    }
+
+    #[test]
+    fn normalize_is_linear_while_the_gain_keeps_peaks_under_the_ceiling() {
+        let n = target(-16.0, None)
+            .normalize(&measurement(-27.61, -14.2, 6.2), 44100)
+            .unwrap();
+
+        assert!(n.linear);
+        assert_eq!(
+            n.filter,
+            "loudnorm=I=-16:TP=-1:LRA=11:measured_I=-27.61:measured_TP=-14.2:measured_LRA=6.2:measured_thresh=-38.1:offset=0.05:linear=true,aresample=44100"
+        );
+    }
+
+    #[test]
+    fn normalize_falls_back_to_dynamic_when_the_gain_would_clip() {
+        // +11.61 dB of gain on a -4 dBTP peak lands at +7.61, way over -1
+        let loud_peaks = target(-16.0, None)
+            .normalize(&measurement(-27.61, -4.0, 6.2), 48000)
+            .unwrap();
+        // Exactly at the ceiling is still linear
+        let at_ceiling = target(-16.0, Some(-2.0))
+            .normalize(&measurement(-20.0, -6.0, 6.2), 48000)
+            .unwrap();
+
+        assert!(!loud_peaks.linear);
+        assert!(at_ceiling.linear);
+    }
+
+    #[test]
+    fn normalize_clamps_the_range_target() {
+        let lra = |range: f64| {
+            let n = target(-16.0, None)
+                .normalize(&measurement(-27.61, -14.2, range), 48000)
+                .unwrap();
+            let lra = n
+                .filter
+                .split(':')
+                .find_map(|p| p.strip_prefix("LRA="))
+                .unwrap()
+                .to_string();
+            (lra, n.linear)
+        };
+
+        assert_eq!(lra(6.2), ("11".to_string(), true));
+        // Rounded up so loudnorm never targets less range than the source has
+        assert_eq!(lra(14.3), ("15".to_string(), true));
+        // Wider than loudnorm accepts: capped, and linear mode can't keep it
+        assert_eq!(lra(24.8), ("20".to_string(), false));
+    }
+
+    #[test]
+    fn validate_checks_loudness_and_peak_bounds() {
+        assert!(target(-16.0, None).validate().is_ok());
+        assert!(target(-70.0, Some(0.0)).validate().is_ok());
+        assert!(target(-5.0, Some(-9.0)).validate().is_ok());
+        assert!(matches!(
+            target(-4.0, None).validate(),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            target(-71.0, None).validate(),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            target(-16.0, Some(0.5)).validate(),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            target(-16.0, Some(-10.0)).validate(),
+            Err(CompressError::InvalidRequest { .. })
+        ));
+    }
+}
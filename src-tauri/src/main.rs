@@ -3,4 +3,4 @@
 fn main() {
     // This boots up the app by calling your lib.rs file
     universal_compressor_lib::run();
-}
\ No newline at end of file
+}
@@ -0,0 +1,3429 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::audio::{self, AudioOptions};
+use crate::edit::{self, EditList, EditPlan};
+use crate::encoders::{self, CodecChoice, EncoderBackend, H264Profile, VideoCodec};
+use crate::engine::{ProcessEvent, Tool};
+use crate::error::{CompressError, Result};
+use crate::hardware::{self, pick_encoder};
+use crate::hdr::{self, ColorPlan, HdrMode};
+use crate::ladder::{self, Ladder, StreamingManifest, StreamingOptions};
+use crate::loudness::{self, LoudnessReport, LoudnessTarget, Normalization};
+use crate::presets::{self, AudioCodec, Preset, StreamPolicy};
+use crate::probe::{self, DynamicRange, MediaInfo};
+use crate::process::JobScope;
+use crate::progress::{self, ProgressTracker};
+use crate::quality::{self, QualityTarget};
+use crate::segments;
+use crate::streams;
+use crate::subtitles::{self, SubtitleOptions, SubtitlePlan};
+use crate::transform::{self, TransformOptions, TransformPlan};
+
+// The compressor and enhancer pipelines. They only talk to the outside world through `Engine`,
+// so the Tauri commands and compress-io-cli run exactly the same code.
+
+// Per-job video settings on top of the preset. Everything left at its default keeps the preset's value.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VideoOptions {
+    pub codec: CodecChoice,
+    pub h264_profile: Option<H264Profile>,
+    pub h264_level: Option<String>,
+    // Resolution and frame rate caps; never upscale and keep the aspect ratio
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_fps: Option<f64>,
+    // Search the CRF that reaches this score instead of using the preset's quality. Constant
+    // quality encodes only; a target size takes precedence.
+    pub target_quality: Option<QualityTarget>,
+    // Trim, cut and join (see edit.rs)
+    pub edit: EditList,
+    // Keep HDR / 10-bit or tone-map to SDR (see hdr.rs)
+    pub hdr: HdrMode,
+    // Encode this many segments at once with a software encoder (see segments.rs). None or 1 runs
+    // a single ffmpeg; ignored for GPU encoders and edit lists.
+    pub parallel_segments: Option<u32>,
+    // Burn in or add a subtitle track (see subtitles.rs)
+    pub subtitles: Option<SubtitleOptions>,
+    // Rotate, flip and crop (see transform.rs)
+    pub transform: TransformOptions,
+    // Two-pass loudness normalization of the first audio track (see loudness.rs); GIFs have no sound
+    pub loudness: Option<LoudnessTarget>,
+}
+
+// What a video job reports back, None from compress_video when there is nothing to say
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoReport {
+    // Target-size encodes; flattened so the result keeps the SizeReport fields at the top
+    #[serde(flatten)]
+    pub size: Option<SizeReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<LoudnessReport>,
+}
+
+// First loudness pass over the audio the encode will get: the edit plan's inputs and graph (the
+// whole `input` without one), the first audio track with every track kept, ffmpeg's pick
+// otherwise. None without sound or for silence.
+async fn measure_loudness(
+    scope: &JobScope<'_>,
+    target: Option<LoudnessTarget>,
+    input: &str,
+    info: &MediaInfo,
+    plan: Option<&EditPlan>,
+    all_tracks: bool,
+) -> Result<Option<Normalization>> {
+    let graph = plan.filter(|p| p.is_filtered());
+    let (target, first) = match (target, info.audio.first()) {
+        (Some(target), Some(first)) if graph.map(|p| p.has_audio()).unwrap_or(true) => {
+            (target, first)
+        }
+        _ => return Ok(None),
+    };
+    let measure = target.measure_filter();
+    let mut args = plan
+        .map(|p| p.input_args.clone())
+        .unwrap_or_else(|| vec!["-i".to_string(), input.to_string()]);
+    if let Some(graph) = graph {
+        args.extend(graph.filter_args(None, Some(&measure), true));
+    } else if all_tracks {
+        args.extend([
+            "-map".to_string(),
+            format!("0:{}", first.index),
+            "-af".to_string(),
+            measure,
+        ]);
+    } else {
+        args.extend([
+            "-vn".to_string(),
+            "-sn".to_string(),
+            "-dn".to_string(),
+            "-af".to_string(),
+            measure,
+        ]);
+    }
+    let sample_rate = if first.sample_rate > 0 {
+        first.sample_rate
+    } else {
+        48000
+    };
+    loudness::first_pass(scope, target, args, sample_rate).await
+}
+
+// Second measurement, on the written file
+async fn loudness_report(
+    scope: &JobScope<'_>,
+    output: &str,
+    normalization: &Option<Normalization>,
+) -> Result<Option<LoudnessReport>> {
+    match normalization {
+        Some(n) => Ok(Some(loudness::report(scope, output, n).await?)),
+        None => Ok(None),
+    }
+}
+
+impl VideoOptions {
+    fn apply(&self, mut preset: Preset) -> Result<Preset> {
+        presets::validate_h264_level(self.h264_level.as_deref())?;
+        preset.video_codec = self.codec.resolve(preset.video_codec);
+        if self.h264_profile.is_some() {
+            preset.h264_profile = self.h264_profile;
+        }
+        if self.h264_level.is_some() {
+            preset.h264_level = self.h264_level.clone();
+        }
+        if [self.max_width, self.max_height]
+            .iter()
+            .flatten()
+            .any(|d| *d < 2)
+        {
+            return Err(CompressError::invalid(
+                "Resolution cap must be at least 2 pixels",
+            ));
+        }
+        if self.max_fps.map(|f| f <= 0.0).unwrap_or(false) {
+            return Err(CompressError::invalid("Frame rate cap must be positive"));
+        }
+        preset.max_width = self.max_width.or(preset.max_width);
+        preset.max_height = self.max_height.or(preset.max_height);
+        preset.max_fps = self.max_fps.or(preset.max_fps);
+        Ok(preset)
+    }
+}
+
+// 🟢 THE AI ENHANCER COMMAND (FIXED TO USE AVAILABLE MODELS)
+#[allow(clippy::too_many_arguments)]
+pub async fn enhance_image(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    scale: String,
+    format: String,
+    model_type: String,
+    _face_restore: bool,
+    hyper_detail: bool,
+    tile_size: String,
+) -> Result<()> {
+    let engine = scope.engine;
+    eprintln!("✨ DIAGNOSTIC: Enhance Image Function Called");
+
+    let engine_path = engine.ai_engine_path();
+
+    if !engine_path.exists() {
+        return Err(CompressError::AiEngineMissing {
+            path: engine_path.to_string_lossy().to_string(),
+        });
+    }
+
+    let model_name = match model_type.as_str() {
+        "anime" => "realesr-animevideov3-x4",
+        _ => "realesrgan-x4plus",
+    };
+
+    let mut args = vec![
+        "-i".to_string(),
+        input,
+        "-o".to_string(),
+        output.clone(),
+        "-s".to_string(),
+        scale,
+        "-f".to_string(),
+        format,
+        "-n".to_string(),
+        model_name.to_string(),
+        "-t".to_string(),
+        tile_size,
+    ];
+    if hyper_detail {
+        args.push("-x".to_string());
+    }
+
+    eprintln!(
+        "🚀 DIAGNOSTIC: Starting AI Process with model: {}",
+        model_name
+    );
+
+    let (mut rx, pid) = scope.spawn(Tool::AiEngine, args)?;
+    scope.track_output(output.into());
+
+    let mut exit_code = None;
+    while let Some(event) = rx.recv().await {
+        match event {
+            ProcessEvent::Stderr(bytes) => {
+                // Lines look like "12.34%", sometimes several glued together
+                let mut current_line = String::new();
+                for c in String::from_utf8_lossy(&bytes)
+                    .chars()
+                    .chain(std::iter::once('\n'))
+                {
+                    if c == '\r' || c == '\n' || c == '%' {
+                        if c == '%' {
+                            current_line.push(c);
+                        }
+                        if !current_line.trim().is_empty() {
+                            engine.emit("enhance-progress", current_line.clone());
+                        }
+                        current_line.clear();
+                    } else {
+                        current_line.push(c);
+                    }
+                }
+            }
+            ProcessEvent::Exited(code) => exit_code = code,
+            ProcessEvent::Stdout(_) => {}
+        }
+    }
+    scope.release(pid);
+    scope.check_cancelled()?;
+
+    if exit_code == Some(0) {
+        eprintln!("✅ DIAGNOSTIC: AI Enhancement Complete!");
+        Ok(())
+    } else {
+        Err(CompressError::AiEngineFailed { exit_code })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn enhance_video(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    ai_scale: String,
+    model_type: String,
+    _face_restore: bool,
+    ai_fps: String,
+    denoise: bool,
+    stabilize: bool,
+    hyper_detail: bool,
+    tile_size: String,
+    auto_gpu: bool,
+) -> Result<()> {
+    let engine = scope.engine;
+    eprintln!("✨ TURBO ENGINE: Starting Video Enhancement Phase");
+
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let temp_dir_path = engine.temp_dir.join(format!("ai_enhance_{}", ts));
+    std::fs::create_dir_all(&temp_dir_path)?;
+    scope.track_temp(temp_dir_path.clone());
+    scope.track_output(output.clone().into());
+
+    let input_frames_dir = temp_dir_path.join("input_frames");
+    let output_frames_dir = temp_dir_path.join("output_frames");
+    std::fs::create_dir_all(&input_frames_dir)?;
+    std::fs::create_dir_all(&output_frames_dir)?;
+    let audio_path = temp_dir_path.join("audio.aac");
+
+    engine.emit("enhance-progress", "Parsing metadata...");
+
+    engine.emit("enhance-progress", "Analyzing video stream...");
+
+    // 1. Probe Metadata (FPS & Estimating Total Frames)
+    let info = probe::probe(scope, &input).await?;
+    if !info.has_video() {
+        return Err(CompressError::MissingStream {
+            kind: "video".to_string(),
+        });
+    }
+    let fps = info.fps().unwrap_or(30.0);
+    let total_frames = info.total_frames().unwrap_or(0);
+
+    if total_frames > 0 {
+        engine.emit("enhance-progress", format!("Prep: Count:{}", total_frames));
+    }
+
+    // 2. Extract Audio
+    engine.emit("enhance-progress", "Separating audio track...");
+    let audio_args = vec![
+        "-y",
+        "-hwaccel",
+        "auto",
+        "-i",
+        &input,
+        "-vn",
+        "-c:a",
+        "copy",
+        audio_path.to_str().unwrap(),
+    ];
+    let audio_status = scope
+        .output(
+            Tool::Ffmpeg,
+            audio_args.into_iter().map(String::from).collect(),
+        )
+        .await?;
+    let has_audio = audio_path.exists() && audio_status.success();
+
+    // 3. Extract Frames with Live Progress
+    engine.emit("enhance-progress", "Extracting frames... (Hold tight)");
+    let frames_pattern = input_frames_dir.join("frame_%08d.jpg");
+
+    let mut vf_chain = "scale=-2:'min(1080,ih)'".to_string();
+    if stabilize {
+        let trf_path = temp_dir_path
+            .join("transform.trf")
+            .to_str()
+            .unwrap()
+            .replace("\\", "/");
+        let detect = format!(
+            "vidstabdetect=stepsize=32:shakiness=10:accuracy=10:result={}",
+            trf_path
+        );
+        let stab_args = vec![
+            "-y", "-hwaccel", "auto", "-i", &input, "-vf", &detect, "-f", "null", "-",
+        ];
+        scope
+            .output(
+                Tool::Ffmpeg,
+                stab_args.into_iter().map(String::from).collect(),
+            )
+            .await?;
+        vf_chain = format!(
+            "vidstabtransform=input={}:optzoom=1:zoomspeed=0.2:smoothing=10,{}",
+            trf_path, vf_chain
+        );
+    }
+    if denoise {
+        vf_chain = format!("hqdn3d=4.0:3.0:6.0:4.5,{}", vf_chain);
+    }
+
+    let frames_args = vec![
+        "-y",
+        "-hwaccel",
+        "auto",
+        "-i",
+        &input,
+        "-threads",
+        "4",
+        "-vf",
+        &vf_chain,
+        "-progress",
+        "pipe:1",
+        "-vsync",
+        "0",
+        frames_pattern.to_str().unwrap(),
+    ];
+
+    let (mut rx, frames_pid) = scope.spawn(
+        Tool::Ffmpeg,
+        frames_args.into_iter().map(String::from).collect(),
+    )?;
+    let mut tracker = ProgressTracker::new(&scope.id, info.duration, info.total_frames());
+    while let Some(event) = rx.recv().await {
+        if let ProcessEvent::Stdout(line_bytes) = event {
+            let line = String::from_utf8_lossy(&line_bytes);
+            if let Some(update) = tracker.feed_line(&line) {
+                engine.emit("job-progress", update);
+            }
+            if line.contains("frame=") {
+                let frame_str = line
+                    .split("frame=")
+                    .last()
+                    .unwrap_or("")
+                    .trim()
+                    .split(' ')
+                    .next()
+                    .unwrap_or("");
+                engine.emit(
+                    "enhance-progress",
+                    format!("Prep: Processing:{}", frame_str),
+                );
+            }
+        }
+    }
+    scope.release(frames_pid);
+    scope.check_cancelled()?;
+
+    // New: Physical Reconcile - Overwrite estimation with reality
+    let actual_files: Vec<_> = std::fs::read_dir(&input_frames_dir)?
+        .filter_map(|e| e.ok())
+        .collect();
+    let total_frames = actual_files.len();
+    engine.emit(
+        "enhance-progress",
+        format!("Prep: Finalized:{}", total_frames),
+    );
+
+    // 4. Turbo Parallel Enhancement (Hardware-Aware Throttling)
+    engine.emit("enhance-progress", "Analyzing GPU Core Capacity...");
+    let vram_mb = hardware::get_system_vram_mb();
+
+    // Scale lanes based on actual memory capacity to prevent vkQueueSubmit -4 (Device Lost)
+    let num_chunks = if vram_mb >= 10000 {
+        4 // High-end Desktop (3080/4080+)
+    } else if vram_mb >= 6000 {
+        2 // Mid-tier Desktop / High-end Laptop (3060/3070)
+    } else {
+        1 // Entry-level Laptop (RTX 2050/3050 - 4GB VRAM)
+    };
+
+    let auto_tile_size = if vram_mb >= 8000 { "400" } else { "200" };
+    let final_tile = if tile_size == "0" || tile_size.is_empty() {
+        auto_tile_size.to_string()
+    } else {
+        tile_size
+    };
+    let thread_map = if vram_mb >= 8000 { "1:4:4" } else { "1:2:2" };
+
+    engine.emit(
+        "enhance-progress",
+        format!(
+            "Turbo Engine Adjusted: {} Processing Lanes (VRAM: {}MB).",
+            num_chunks, vram_mb
+        ),
+    );
+
+    let engine_path = engine.ai_engine_path();
+    if !engine_path.exists() {
+        return Err(CompressError::AiEngineMissing {
+            path: engine_path.to_string_lossy().to_string(),
+        });
+    }
+
+    let model_name = match model_type.as_str() {
+        "anime" => "realesr-animevideov3-x4",
+        _ => "realesrgan-x4plus",
+    };
+
+    let mut frame_files: Vec<std::fs::DirEntry> = std::fs::read_dir(&input_frames_dir)?
+        .filter_map(|entry| entry.ok())
+        .collect();
+    frame_files.sort_by_key(|a| a.path());
+    // total_frames is already updated by the reconcile step above
+
+    let chunk_size = (total_frames as f64 / num_chunks as f64).ceil() as usize;
+    let mut lanes = vec![];
+    let lanes_exited = Arc::new(AtomicUsize::new(0));
+
+    for i in 0..num_chunks {
+        let chunk_in_dir = temp_dir_path.join(format!("chunk_in_{}", i));
+        let chunk_out_dir = temp_dir_path.join(format!("chunk_out_{}", i));
+        std::fs::create_dir_all(&chunk_in_dir)?;
+        std::fs::create_dir_all(&chunk_out_dir)?;
+
+        let start = i * chunk_size;
+        let end = std::cmp::min(start + chunk_size, total_frames);
+
+        for (j, frame) in frame_files.iter().enumerate().take(end).skip(start) {
+            if j % 10 == 0 {
+                engine.emit(
+                    "enhance-progress",
+                    format!("Prep: Active:Organizing Chunks ({}/{})...", j, total_frames),
+                );
+            }
+            let src = frame.path();
+            let dest = chunk_in_dir.join(src.file_name().unwrap());
+            std::fs::rename(src, dest)?;
+        }
+
+        let mut lane_args: Vec<String> = vec![
+            "-i",
+            chunk_in_dir.to_str().unwrap(),
+            "-o",
+            chunk_out_dir.to_str().unwrap(),
+            "-s",
+            &ai_scale,
+            "-f",
+            "jpg",
+            "-n",
+            model_name,
+            "-t",
+            &final_tile,
+            "-j",
+            thread_map,
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        if hyper_detail {
+            lane_args.push("-x".to_string());
+        }
+
+        engine.emit(
+            "enhance-progress",
+            format!("Prep: Active:Waking up GPU Lane {}...", i + 1),
+        );
+        let (mut rx, pid) = scope.spawn(Tool::AiEngine, lane_args)?;
+        // Drop individual lane logs to prevent "multiple percentages" noise; progress comes from counting frames
+        let exited = lanes_exited.clone();
+        tokio::spawn(async move {
+            while rx.recv().await.is_some() {}
+            exited.fetch_add(1, Ordering::SeqCst);
+        });
+        lanes.push(pid);
+    }
+
+    engine.emit(
+        "enhance-progress",
+        "Prep: Active:Loading AI Neural Weights...",
+    );
+
+    loop {
+        let mut total_done = 0;
+        for i in 0..num_chunks {
+            let chunk_out_dir = temp_dir_path.join(format!("chunk_out_{}", i));
+            if let Ok(paths) = std::fs::read_dir(chunk_out_dir) {
+                total_done += paths.count();
+            }
+        }
+
+        if total_done == 0 {
+            engine.emit(
+                "enhance-progress",
+                "Prep: Active:Waiting for first frame...",
+            );
+        } else {
+            let pct = (total_done as f64 / total_frames as f64) * 100.0;
+            engine.emit("enhance-progress", format!("{:.2}%", pct.min(99.9)));
+        }
+        if lanes_exited.load(Ordering::SeqCst) == lanes.len() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+    }
+    for pid in lanes {
+        scope.release(pid);
+    }
+    scope.check_cancelled()?;
+
+    // 5. Consolidate and Stitch
+    engine.emit("enhance-progress", "Multiplexing newly enhanced frames...");
+    for i in 0..num_chunks {
+        let chunk_out_dir = temp_dir_path.join(format!("chunk_out_{}", i));
+        for entry in std::fs::read_dir(chunk_out_dir)? {
+            let entry = entry?;
+            std::fs::rename(entry.path(), output_frames_dir.join(entry.file_name()))?;
+        }
+    }
+
+    let encoder = pick_encoder(engine, VideoCodec::Hevc, auto_gpu).await;
+
+    let out_pattern = output_frames_dir.join("frame_%08d.jpg");
+
+    let mut stitch_vf = vec![];
+    if ai_fps != "0" {
+        stitch_vf.push(format!("minterpolate=fps={}:mi_mode=mci", ai_fps));
+    }
+    if hyper_detail {
+        stitch_vf
+            .push("eq=contrast=1.1:saturation=1.2:gamma=0.95,unsharp=5:5:1.5:5:5:0.0".to_string());
+    }
+
+    // Final command construction
+    let mut final_args = vec![
+        "-y".to_string(),
+        "-hwaccel".to_string(),
+        "auto".to_string(),
+        "-r".to_string(),
+        fps.to_string(),
+        "-i".to_string(),
+        out_pattern.to_str().unwrap().to_string(),
+    ];
+    if has_audio {
+        final_args.extend(vec![
+            "-i".to_string(),
+            audio_path.to_str().unwrap().to_string(),
+        ]);
+    }
+    if !stitch_vf.is_empty() {
+        final_args.push("-vf".to_string());
+        final_args.push(stitch_vf.join(","));
+    }
+    final_args.extend(encoder.constant_quality_args(encoders::DEFAULT_QUALITY, None));
+    final_args.extend(encoder.pix_fmt_args(false));
+    final_args.extend(encoder.tag_args());
+    if has_audio {
+        final_args.extend(vec![
+            "-c:a".to_string(),
+            "aac".to_string(),
+            "-b:a".to_string(),
+            "192k".to_string(),
+            "-shortest".to_string(),
+        ]);
+    }
+    final_args.push("-movflags".to_string());
+    final_args.push("+faststart".to_string());
+    final_args.push(output.clone());
+
+    let stitch_status = scope.output(Tool::Ffmpeg, final_args).await?;
+    if !stitch_status.success() {
+        return Err(CompressError::from_ffmpeg_stderr(
+            stitch_status.code,
+            &stitch_status.stderr,
+        ));
+    }
+
+    engine.emit("enhance-progress", "100.00%");
+    Ok(())
+}
+
+// WebM only carries VP9 and AV1; H.264/HEVC requests become VP9 there.
+fn webm_codec(codec: VideoCodec) -> VideoCodec {
+    match codec {
+        VideoCodec::Av1 => VideoCodec::Av1,
+        _ => VideoCodec::Vp9,
+    }
+}
+
+// The preset's CRF, or the one the quality search settles on for this encoder.
+#[allow(clippy::too_many_arguments)]
+async fn pick_crf(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    encoder: &dyn EncoderBackend,
+    preset: &Preset,
+    transform: &TransformPlan,
+    color: &ColorPlan,
+    target: Option<QualityTarget>,
+) -> Result<u32> {
+    let target = match target {
+        Some(target) => target,
+        None => return Ok(preset.quality),
+    };
+    let result = quality::search(
+        scope,
+        input,
+        info,
+        encoder,
+        transform.video_filter(None).as_deref(),
+        preset.scale_filter(true).as_deref(),
+        color,
+        target,
+    )
+    .await?;
+    let note = if result.met {
+        format!(
+            "🎯 Quality search: CRF {} reaches {:.3} (target {})",
+            result.crf, result.score, target.score
+        )
+    } else {
+        format!(
+            "⚠️ Quality search: target {} not reached, using CRF {} ({:.3})",
+            target.score, result.crf, result.score
+        )
+    };
+    scope.engine.note(note);
+    Ok(result.crf)
+}
+
+// `options` overrides the preset's codec and H.264 profile/level for this job only.
+// Returns the size report when the preset has a target size, and the loudness report when
+// normalizing.
+#[allow(clippy::too_many_arguments)]
+pub async fn compress_video(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: VideoOptions,
+) -> Result<Option<VideoReport>> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let base = presets::resolve(engine, preset.as_deref())?;
+    if let Some(target_size_kb) = base.target_size_kb {
+        // Size-limited presets (Discord, WhatsApp...) go through the target-size pipeline
+        return compress_video_target_size(
+            scope,
+            input,
+            output,
+            target_size_kb,
+            auto_gpu,
+            Some(base.id),
+            options,
+        )
+        .await
+        .map(Some);
+    }
+    let preset = options.apply(base)?;
+    let info = probe::probe(scope, &input).await?;
+    if !info.has_video() {
+        return Err(CompressError::MissingStream {
+            kind: "video".to_string(),
+        });
+    }
+    let plan = edit::plan(scope, &input, &info, &options.edit).await?;
+    scope.track_output(output.clone().into());
+
+    let ext = Path::new(&output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let subtitles = match &options.subtitles {
+        Some(subs) => {
+            // With every track kept, the source's own subtitles are already mapped
+            let mapped = (preset.streams == StreamPolicy::All)
+                .then(|| streams::carried_subtitles(&info, &ext));
+            subtitles::plan(&input, &info, subs, &ext, &plan, mapped)?
+        }
+        None => SubtitlePlan::default(),
+    };
+    let transform = transform::plan(scope, &input, &info, &options.transform).await?;
+    let codec = preset.video_codec;
+    let mut audio_codec = preset.audio_codec;
+    let mut tag_args: Vec<String> = vec![];
+    let mut chosen: Option<&dyn EncoderBackend> = None;
+
+    match ext.as_str() {
+        "mp4" | "mkv" | "mov" | "avi" | "flv" | "ts" | "m4v" | "wmv" => {
+            let encoder = pick_encoder(engine, codec, auto_gpu).await;
+            tag_args = encoder.tag_args();
+            chosen = Some(encoder);
+        }
+        "webm" => {
+            audio_codec = AudioCodec::Opus;
+            if webm_codec(codec) == VideoCodec::Av1 {
+                chosen = Some(pick_encoder(engine, VideoCodec::Av1, auto_gpu).await);
+            } else {
+                chosen = Some(encoders::software_encoder(VideoCodec::Vp9));
+            }
+        }
+        "gif" => {
+            // GIFs are 8-bit by nature, HDR sources are always tone-mapped
+            let tonemap = match info.primary_video() {
+                Some(video) if video.dynamic_range() != DynamicRange::Sdr => {
+                    format!("{},", hdr::tonemap_filter(video))
+                }
+                _ => String::new(),
+            };
+            let burn = subtitles
+                .burn
+                .as_ref()
+                .map(|b| format!("{},", b))
+                .unwrap_or_default();
+            let geometry = transform
+                .video_filter(None)
+                .map(|f| format!("{},", f))
+                .unwrap_or_default();
+            let palette = format!("{}{}{}fps=15,scale=480:-1:flags=lanczos,split[s0][s1];[s0]palettegen[p];[s1][p]paletteuse", geometry, tonemap, burn);
+            let mut args = progress::progress_args();
+            args.extend(plan.input_args.clone());
+            if plan.is_filtered() {
+                args.extend(plan.filter_args(Some(&palette), None, false));
+            } else {
+                args.extend(["-filter_complex".to_string(), palette]);
+            }
+            args.extend(["-y".to_string(), output.clone()]);
+            let tracker = ProgressTracker::new(&scope.id, plan.duration, plan.total_frames);
+            progress::run_ffmpeg(scope, args, tracker, "").await?;
+            return Ok(None);
+        }
+        _ => {}
+    }
+
+    // Any other container gets software HEVC, like it always did
+    let encoder = chosen.unwrap_or_else(|| encoders::software_encoder(VideoCodec::Hevc));
+    let color = hdr::plan(scope, &input, &info, encoder, options.hdr).await?;
+    let crf = pick_crf(
+        scope,
+        &input,
+        &info,
+        encoder,
+        &preset,
+        &transform,
+        &color,
+        options.target_quality,
+    )
+    .await?;
+    let mut video_args = encoder.constant_quality_args(crf, None);
+    video_args.extend(encoder.profile_args(preset.h264_profile, preset.h264_level.as_deref()));
+    video_args.extend(color.args());
+    let video_filter = transform
+        .video_filter(color.video_filter(subtitles.video_filter(preset.scale_filter(true))));
+    let normalization = measure_loudness(
+        scope,
+        options.loudness,
+        &input,
+        &info,
+        Some(&plan),
+        preset.streams == StreamPolicy::All,
+    )
+    .await?;
+    let audio_filter = normalization.as_ref().map(|n| n.filter.as_str());
+
+    let workers = options.parallel_segments.unwrap_or(1) as usize;
+    if workers > 1 {
+        // Cutting and joining already happen in one graph, so only whole files are split. Segments
+        // restart at 0, which would throw burned-in subtitles off.
+        let video_index = info.primary_video().map(|v| v.index).unwrap_or(0);
+        let points = if !encoder.is_hardware()
+            && options.edit == EditList::default()
+            && options.subtitles.is_none()
+        {
+            segments::plan(scope, &input, &info, video_index, workers).await?
+        } else {
+            None
+        };
+        match points {
+            Some(points) => {
+                let mut encode_args = vec![];
+                if let Some(filter) = &video_filter {
+                    encode_args.extend(["-vf".to_string(), filter.clone()]);
+                }
+                encode_args.extend(preset.fps_args());
+                encode_args.extend(video_args);
+                let total = ProgressTracker::new(&scope.id, plan.duration, plan.total_frames);
+                let list = segments::encode(
+                    scope,
+                    &input,
+                    video_index,
+                    &points,
+                    &encode_args,
+                    workers,
+                    total,
+                )
+                .await?;
+
+                // Join without re-encoding and take everything else from the source
+                let mut args = vec![
+                    "-y".to_string(),
+                    "-f".to_string(),
+                    "concat".to_string(),
+                    "-safe".to_string(),
+                    "0".to_string(),
+                    "-i".to_string(),
+                    list,
+                    "-i".to_string(),
+                    input.clone(),
+                    "-c:v".to_string(),
+                    "copy".to_string(),
+                ];
+                args.extend(tag_args);
+                args.extend(streams::remux_args(
+                    &preset,
+                    &info,
+                    &ext,
+                    audio_codec,
+                    audio_filter,
+                    1,
+                ));
+                let metadata = preset.metadata_args();
+                if metadata.is_empty() {
+                    // The concat list has no tags of its own, keep the source's
+                    args.extend(["-map_metadata".to_string(), "1".to_string()]);
+                }
+                args.extend(metadata);
+                args.push(output.clone());
+                let joined = scope.output(Tool::Ffmpeg, args).await?;
+                if !joined.success() {
+                    return Err(CompressError::from_ffmpeg_stderr(
+                        joined.code,
+                        &joined.stderr,
+                    ));
+                }
+                let loudness = loudness_report(scope, &output, &normalization).await?;
+                return Ok(loudness.map(|l| VideoReport {
+                    size: None,
+                    loudness: Some(l),
+                }));
+            }
+            None => {
+                engine.note("⚠️ Segment-parallel encoding needs a software encoder, no edit list or subtitles and a long enough video; encoding in one process");
+            }
+        }
+    }
+
+    let mut args = progress::progress_args();
+    args.push("-hwaccel".to_string());
+    args.push("auto".to_string());
+    args.extend(plan.input_args.clone());
+    args.extend(subtitles.input_args.clone());
+    if plan.is_filtered() {
+        args.extend(plan.filter_args(video_filter.as_deref(), audio_filter, true));
+    } else if let Some(filter) = video_filter {
+        args.push("-vf".to_string());
+        args.push(filter);
+    }
+    args.extend(preset.fps_args());
+    args.extend(video_args);
+    args.extend(tag_args);
+    if plan.is_filtered() {
+        // Cut and joined audio comes out of the filter graph, it can't be copied. Chapters would
+        // point at the wrong times after cutting.
+        let audio_codec = if audio_codec == AudioCodec::Copy {
+            AudioCodec::Aac
+        } else {
+            audio_codec
+        };
+        args.extend(preset.audio_args(audio_codec));
+        args.extend(["-map_chapters".to_string(), "-1".to_string()]);
+    } else {
+        args.extend(streams::mapping_args(
+            &preset,
+            &info,
+            &ext,
+            audio_codec,
+            audio_filter,
+        ));
+    }
+    args.extend(subtitles.output_args);
+    args.extend(preset.metadata_args());
+    args.push("-y".to_string());
+    args.push(output.clone());
+
+    let tracker = ProgressTracker::new(&scope.id, plan.duration, plan.total_frames);
+    progress::run_ffmpeg(scope, args, tracker, "").await?;
+    let loudness = loudness_report(scope, &output, &normalization).await?;
+    Ok(loudness.map(|l| VideoReport {
+        size: None,
+        loudness: Some(l),
+    }))
+}
+
+// HLS (and optionally DASH) ladder of `input` in the folder `output` (see ladder.rs). The preset
+// contributes its H.264 profile/level, frame rate cap and metadata policy; rung sizes and bitrates
+// come from `options`. Returns what was written, also saved as renditions.json in the folder.
+pub async fn compress_video_streaming(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: StreamingOptions,
+) -> Result<StreamingManifest> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let preset = presets::resolve(engine, preset.as_deref())?;
+    let info = probe::probe(scope, &input).await?;
+    let video = info
+        .primary_video()
+        .ok_or_else(|| CompressError::MissingStream {
+            kind: "video".to_string(),
+        })?;
+    let ladder = Ladder::new(&options, video)?;
+    let audio = info.audio.first().map(|a| a.index);
+
+    let dir = Path::new(&output);
+    if dir.is_file() {
+        return Err(CompressError::invalid(format!(
+            "{} is a file, streaming output needs a folder",
+            output
+        )));
+    }
+    // A folder the user picked stays; only what this job created goes away on failure
+    if !dir.exists() {
+        scope.track_output(dir.to_path_buf());
+    }
+    std::fs::create_dir_all(dir)?;
+    for name in ladder.variant_dirs(audio.is_some()) {
+        std::fs::create_dir_all(dir.join(name))?;
+    }
+
+    // Every HLS player decodes H.264, so that's what the rungs are; HDR is tone-mapped
+    let encoder = pick_encoder(engine, VideoCodec::H264, auto_gpu).await;
+    let color = hdr::plan(scope, &input, &info, encoder, HdrMode::Preserve).await?;
+    let mut args = progress::progress_args();
+    args.extend(["-y".to_string(), "-i".to_string(), input.clone()]);
+    args.extend(ladder.video_args(video.index, color.tonemap.as_deref()));
+    if let Some(index) = audio {
+        args.extend(ladder.audio_args(index));
+    }
+    args.extend(preset.fps_args());
+    args.extend(encoder.codec_args());
+    args.extend(encoder.preset_args(None));
+    args.extend(ladder.rate_args());
+    args.extend(encoder.profile_args(preset.h264_profile, preset.h264_level.as_deref()));
+    args.extend(color.args());
+    args.extend(preset.metadata_args());
+    args.extend(ladder.muxer_args(dir, audio.is_some()));
+
+    let tracker = ProgressTracker::new(&scope.id, info.duration, info.total_frames());
+    progress::run_ffmpeg(scope, args, tracker, "").await?;
+
+    let manifest = ladder.manifest(audio.is_some());
+    let json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| CompressError::internal(e.to_string()))?;
+    std::fs::write(dir.join(ladder::MANIFEST_FILE), json)?;
+    Ok(manifest)
+}
+
+pub async fn compress_image(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    width: String,
+    height: String,
+    preset: Option<String>,
+) -> Result<()> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let preset = presets::resolve(engine, preset.as_deref())?;
+    scope.track_output(output.clone().into());
+    let mut args = progress::progress_args();
+    args.extend(vec![
+        "-hwaccel".to_string(),
+        "auto".to_string(),
+        "-i".to_string(),
+        input.clone(),
+    ]);
+    if width != "0" && !width.is_empty() {
+        let h = if height.is_empty() || height == "0" {
+            "-1"
+        } else {
+            &height
+        };
+        args.push("-vf".to_string());
+        args.push(format!("scale={}:{}", width, h));
+    } else if let Some(scale) = preset.scale_filter(false) {
+        args.push("-vf".to_string());
+        args.push(scale);
+    }
+
+    let ext = Path::new(&output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => {
+            args.push("-q:v".to_string());
+            args.push(preset.jpeg_qscale().unwrap_or(2).to_string());
+        }
+        "webp" => {
+            args.push("-q:v".to_string());
+            args.push(preset.image_quality.unwrap_or(75).to_string());
+        }
+        "png" => {
+            args.push("-compression_level".to_string());
+            args.push("4".to_string());
+        }
+        _ => {}
+    }
+    args.extend(preset.metadata_args());
+
+    args.push("-y".to_string());
+    args.push(output.clone());
+    let tracker = ProgressTracker::new(&scope.id, 0.0, Some(1));
+    progress::run_ffmpeg(scope, args, tracker, "").await
+}
+
+// Bits per pixel per frame below which each codec turns to mush
+fn min_bits_per_pixel(codec: VideoCodec) -> f64 {
+    match codec {
+        VideoCodec::H264 => 0.05,
+        VideoCodec::Hevc | VideoCodec::Vp9 => 0.035,
+        VideoCodec::Av1 => 0.025,
+    }
+}
+
+// Short side of the resolutions tried, largest first
+const SHORT_SIDE_STEPS: &[u32] = &[1440, 1080, 720, 540, 480, 360, 240];
+
+// Lowers the preset's caps when `video_kbps` is too thin for the output size: first the frame rate
+// to 30, then the resolution one step at a time. Returns what was changed, for the log.
+fn fit_caps_to_bitrate(
+    preset: &mut Preset,
+    info: &MediaInfo,
+    codec: VideoCodec,
+    video_kbps: u64,
+) -> Option<String> {
+    let video = info.primary_video()?;
+    let (w, h) = video.display_size();
+    let (w, h) = (w as f64, h as f64);
+    if w <= 0.0 || h <= 0.0 {
+        return None;
+    }
+    // What the existing caps already give
+    let fit = [
+        preset.max_width.map(|m| m as f64 / w),
+        preset.max_height.map(|m| m as f64 / h),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0_f64, f64::min);
+    let (w, h) = (w * fit, h * fit);
+    let mut fps = info
+        .fps()
+        .unwrap_or(30.0)
+        .min(preset.max_fps.unwrap_or(f64::MAX));
+    let enough = |w: f64, h: f64, fps: f64| {
+        video_kbps as f64 * 1000.0 / (w * h * fps) >= min_bits_per_pixel(codec)
+    };
+    if enough(w, h, fps) {
+        return None;
+    }
+    let source_fps = fps;
+    let mut changes = vec![];
+    if fps > 30.0 {
+        fps = 30.0;
+        preset.max_fps = Some(fps);
+        changes.push("30 fps".to_string());
+    }
+    if !enough(w, h, fps) {
+        let short = w.min(h);
+        let steps: Vec<f64> = SHORT_SIDE_STEPS
+            .iter()
+            .map(|s| *s as f64)
+            .filter(|s| *s < short)
+            .collect();
+        if let Some(side) = steps
+            .iter()
+            .copied()
+            .find(|side| enough(w * side / short, h * side / short, fps))
+            .or(steps.last().copied())
+        {
+            // Even, so the scale filter's rounding never goes over the cap
+            let even = |v: f64| ((v / 2.0).round() as u32 * 2).max(2);
+            let (new_w, new_h) = (even(w * side / short), even(h * side / short));
+            preset.max_width = Some(new_w);
+            preset.max_height = Some(new_h);
+            changes.push(format!("{}x{}", new_w, new_h));
+        }
+    }
+    if changes.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}k is too low for {}x{} at {} fps, encoding at {}",
+        video_kbps,
+        w.round(),
+        h.round(),
+        source_fps,
+        changes.join(", ")
+    ))
+}
+
+// What a target-size encode ended up with, returned to the caller and stored on queued jobs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeReport {
+    pub target_bytes: u64,
+    pub output_bytes: u64,
+    pub attempts: u32,
+    // Video bitrate of the final attempt
+    pub video_kbps: u64,
+    pub audio_kbps: u64,
+    // False when the last attempt still came out over the target
+    pub within_target: bool,
+}
+
+// Encodes are retried until the output lands between UNDERSHOOT and 100% of the target.
+const MAX_SIZE_ATTEMPTS: u32 = 3;
+const UNDERSHOOT: f64 = 0.90;
+// Retries aim this far below the target so a small overshoot doesn't need a fourth try
+const RETRY_HEADROOM: f64 = 0.98;
+
+// Bitrate for the next attempt, from what the last one actually produced. Audio and container
+// overhead come from probing the output, the video part is scaled by how far it was off.
+fn corrected_kbps(
+    video_kbps: u64,
+    target_bytes: u64,
+    output_bytes: u64,
+    out: Option<&MediaInfo>,
+    planned_audio_kbps: u64,
+    duration: f64,
+) -> f64 {
+    let audio_bytes = out
+        .map(|o| o.audio_bit_rate())
+        .filter(|bps| *bps > 0)
+        .map(|bps| bps as f64 * duration / 8.0)
+        .unwrap_or(planned_audio_kbps as f64 * 1000.0 * duration / 8.0);
+    let video_bytes = out
+        .and_then(|o| o.primary_video())
+        .and_then(|v| v.bit_rate)
+        .map(|bps| bps as f64 * duration / 8.0)
+        .unwrap_or(output_bytes as f64 - audio_bytes)
+        .max(1.0);
+    let overhead = (output_bytes as f64 - video_bytes - audio_bytes).max(0.0);
+    let available = target_bytes as f64 * RETRY_HEADROOM - audio_bytes - overhead;
+    video_kbps as f64 * available / video_bytes
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn compress_video_target_size(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    target_size_kb: f64,
+    auto_gpu: bool,
+    preset: Option<String>,
+    options: VideoOptions,
+) -> Result<VideoReport> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let mut preset = options.apply(presets::resolve(engine, preset.as_deref())?)?;
+    scope.track_output(output.clone().into());
+
+    // First probe the video to get duration and audio bitrate
+    let info = probe::probe(scope, &input).await?;
+    // Sizes are worked out on what is left after cutting, not the source length
+    let plan = edit::plan(scope, &input, &info, &options.edit).await?;
+    let duration = plan.duration;
+
+    if duration <= 0.0 {
+        return Err(CompressError::ProbeFailed {
+            detail: "Could not determine video duration".to_string(),
+        });
+    }
+
+    // Audio is re-encoded at the preset's bitrate, or the source's own when it is lower than 128k.
+    // Silent videos spend everything on the picture.
+    let audio_bitrate_kbps = match (preset.audio_bitrate_kbps, info.audio.first()) {
+        (_, None) => 0,
+        (Some(kbps), Some(_)) => kbps as u64,
+        (None, Some(track)) => track
+            .bit_rate
+            .map(|bps| (bps / 1000).clamp(32, 128))
+            .unwrap_or(128),
+    };
+    let mut target_video_bitrate_kbps =
+        ((target_size_kb * 8.0) / duration) - audio_bitrate_kbps as f64;
+
+    // 5% safety margin for container overhead, retries correct it with the measured one
+    target_video_bitrate_kbps *= 0.95;
+
+    if target_video_bitrate_kbps < 50.0 {
+        return Err(CompressError::TargetTooSmall {
+            detail: "Target size is too small for this video length".to_string(),
+        });
+    }
+
+    let is_webm = output.to_lowercase().ends_with(".webm");
+    let codec = if is_webm {
+        webm_codec(preset.video_codec)
+    } else {
+        preset.video_codec
+    };
+    let encoder = pick_encoder(engine, codec, auto_gpu).await;
+    // GPU encoders can't do two-pass here, they get a capped single pass instead
+    let use_two_pass = encoder.supports_two_pass();
+    // Stats live in the job's own directory, two jobs in the same cwd would clobber "ffmpeg2pass-0.log"
+    let passlog = if use_two_pass {
+        Some(scope.work_dir()?.join("ffmpeg2pass"))
+    } else {
+        None
+    };
+    let mut video_kbps = target_video_bitrate_kbps.floor() as u64;
+    if let Some(note) = fit_caps_to_bitrate(&mut preset, &info, codec, video_kbps) {
+        engine.note(note);
+    }
+    // The size math assumes re-encoded audio at a known bitrate, so "copy" becomes AAC here
+    let audio_codec = if is_webm {
+        AudioCodec::Opus
+    } else if preset.audio_codec == AudioCodec::Copy {
+        AudioCodec::Aac
+    } else {
+        preset.audio_codec
+    };
+    // Gain doesn't change the bitrate, so normalizing leaves the size math alone
+    let normalization = if audio_bitrate_kbps == 0 {
+        None
+    } else {
+        measure_loudness(scope, options.loudness, &input, &info, Some(&plan), false).await?
+    };
+    let audio_filter = normalization.as_ref().map(|n| n.filter.as_str());
+    let mut audio_args = if audio_bitrate_kbps == 0 {
+        vec!["-an".to_string()]
+    } else {
+        vec![
+            "-c:a".to_string(),
+            audio_codec.encoder().to_string(),
+            "-b:a".to_string(),
+            format!("{}k", audio_bitrate_kbps),
+        ]
+    };
+    if let Some(filter) = audio_filter.filter(|_| !plan.is_filtered()) {
+        audio_args.extend(["-af".to_string(), filter.to_string()]);
+    }
+    let subtitles = match &options.subtitles {
+        Some(subs) => {
+            let ext = Path::new(&output)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_lowercase();
+            subtitles::plan(&input, &info, subs, &ext, &plan, None)?
+        }
+        None => SubtitlePlan::default(),
+    };
+    let transform = transform::plan(scope, &input, &info, &options.transform).await?;
+    let color = hdr::plan(scope, &input, &info, encoder, options.hdr).await?;
+    let mut profile_args = encoder.profile_args(preset.h264_profile, preset.h264_level.as_deref());
+    profile_args.extend(color.args());
+    let video_filter = transform
+        .video_filter(color.video_filter(subtitles.video_filter(preset.scale_filter(true))));
+    // Pass 1 never writes audio, so the graph leaves it out there
+    let filter_args = |with_audio: bool| {
+        let mut args = vec![];
+        if plan.is_filtered() {
+            args.extend(plan.filter_args(
+                video_filter.as_deref(),
+                audio_filter.filter(|_| with_audio),
+                with_audio,
+            ));
+        } else if let Some(filter) = &video_filter {
+            args.extend(["-vf".to_string(), filter.clone()]);
+        }
+        args.extend(preset.fps_args());
+        args
+    };
+
+    let target_bytes = (target_size_kb * 1024.0) as u64;
+    let total_frames = plan.total_frames;
+    let mut attempt = 1;
+    let size = loop {
+        engine.note(format!(
+            "Target bitrate: {}k (attempt {}/{})",
+            video_kbps, attempt, MAX_SIZE_ATTEMPTS
+        ));
+        if let Some(passlog) = &passlog {
+            // Pass 1
+            let mut args1 = progress::progress_args();
+            args1.push("-y".to_string());
+            args1.extend(plan.input_args.clone());
+            args1.extend(filter_args(false));
+            args1.extend(encoder.codec_args());
+            args1.extend(encoder.preset_args(None));
+            args1.extend(encoder.bitrate_args(video_kbps, false));
+            args1.extend(profile_args.clone());
+            args1.extend(encoder.pass_args(1, passlog));
+            args1.extend(vec![
+                "-an".to_string(),
+                "-f".to_string(),
+                "null".to_string(),
+            ]);
+            #[cfg(target_os = "windows")]
+            args1.push("NUL".to_string());
+            #[cfg(not(target_os = "windows"))]
+            args1.push("/dev/null".to_string());
+
+            let tracker1 = ProgressTracker::new(&scope.id, duration, total_frames).with_pass(1, 2);
+            progress::run_ffmpeg(scope, args1, tracker1, "[Pass 1] ").await?;
+
+            // Pass 2
+            let mut args2 = progress::progress_args();
+            args2.push("-y".to_string());
+            args2.extend(plan.input_args.clone());
+            args2.extend(subtitles.input_args.clone());
+            args2.extend(filter_args(true));
+            args2.extend(encoder.codec_args());
+            args2.extend(encoder.preset_args(None));
+            args2.extend(encoder.bitrate_args(video_kbps, false));
+            args2.extend(profile_args.clone());
+            args2.extend(encoder.pass_args(2, passlog));
+            args2.extend(audio_args.clone());
+            args2.extend(subtitles.output_args.clone());
+            args2.extend(preset.metadata_args());
+            args2.push(output.clone());
+
+            let tracker2 = ProgressTracker::new(&scope.id, duration, total_frames).with_pass(2, 2);
+            progress::run_ffmpeg(scope, args2, tracker2, "[Pass 2] ").await?;
+        } else {
+            // Single pass with maxrate for GPU encoders
+            let mut args = progress::progress_args();
+            args.extend(vec![
+                "-y".to_string(),
+                "-hwaccel".to_string(),
+                "auto".to_string(),
+            ]);
+            args.extend(plan.input_args.clone());
+            args.extend(subtitles.input_args.clone());
+            args.extend(filter_args(true));
+            args.extend(encoder.codec_args());
+            args.extend(encoder.preset_args(None));
+            args.extend(encoder.bitrate_args(video_kbps, true));
+            args.extend(profile_args.clone());
+            args.extend(audio_args.clone());
+            args.extend(subtitles.output_args.clone());
+            args.extend(preset.metadata_args());
+            args.push(output.clone());
+
+            let tracker = ProgressTracker::new(&scope.id, duration, total_frames);
+            progress::run_ffmpeg(scope, args, tracker, "").await?;
+        }
+        scope.check_cancelled()?;
+
+        let output_bytes = std::fs::metadata(&output)?.len();
+        let within_target = output_bytes <= target_bytes;
+        let note = format!(
+            "📏 SIZE: attempt {} at {}k came out {} KB for a {} KB target",
+            attempt,
+            video_kbps,
+            output_bytes / 1024,
+            target_bytes / 1024
+        );
+        engine.note(note);
+        let report = SizeReport {
+            target_bytes,
+            output_bytes,
+            attempts: attempt,
+            video_kbps,
+            audio_kbps: audio_bitrate_kbps,
+            within_target,
+        };
+        if (within_target && output_bytes as f64 >= target_bytes as f64 * UNDERSHOOT)
+            || attempt == MAX_SIZE_ATTEMPTS
+        {
+            break report;
+        }
+
+        let out = probe::probe(scope, &output).await.ok();
+        let next = corrected_kbps(
+            video_kbps,
+            target_bytes,
+            output_bytes,
+            out.as_ref(),
+            audio_bitrate_kbps,
+            duration,
+        )
+        .floor();
+        if next < 50.0 || next as u64 == video_kbps {
+            // Nothing left to correct: keep what we have
+            break report;
+        }
+        video_kbps = next as u64;
+        attempt += 1;
+    };
+    let loudness = loudness_report(scope, &output, &normalization).await?;
+    Ok(VideoReport {
+        size: Some(size),
+        loudness,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn compress_image_target_size(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    target_size_kb: f64,
+    width: String,
+    height: String,
+    preset: Option<String>,
+) -> Result<()> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let preset = presets::resolve(engine, preset.as_deref())?;
+    scope.track_output(output.clone().into());
+
+    let ext = Path::new(&output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let is_jpg = ext == "jpg" || ext == "jpeg";
+    let is_webp = ext == "webp";
+
+    if !is_jpg && !is_webp {
+        // We only do binary search properly for lossy formats like JPG or WEBP.
+        // If PNG, BMP, TIFF, we fallback to standard compression or WebP.
+        // For simplicity, we just do a regular compress if they didn't switch to a lossy format.
+        // (The frontend should guide them to choose JPG/WEBP for target size).
+        return Err(CompressError::unsupported(
+            "Target size requires a lossy format like JPG or WebP. Please change output format.",
+        ));
+    }
+
+    let mut min_q: f32 = if is_jpg { 2.0 } else { 1.0 };
+    let mut max_q: f32 = if is_jpg { 31.0 } else { 100.0 }; // For JPG, lower is better. For WEBP, higher is better.
+    let target_bytes = (target_size_kb * 1024.0) as u64;
+
+    let mut best_q = min_q;
+    let mut best_size_diff = u64::MAX;
+
+    // We'll do binary search
+    for _i in 0..8 {
+        let mid_q = (min_q + max_q) / 2.0;
+
+        let mut args = vec![
+            "-hwaccel".to_string(),
+            "auto".to_string(),
+            "-i".to_string(),
+            input.clone(),
+        ];
+        if width != "0" && !width.is_empty() {
+            let h = if height.is_empty() || height == "0" {
+                "-1"
+            } else {
+                &height
+            };
+            args.push("-vf".to_string());
+            args.push(format!("scale={}:{}", width, h));
+        } else if let Some(scale) = preset.scale_filter(false) {
+            args.push("-vf".to_string());
+            args.push(scale);
+        }
+        args.extend(preset.metadata_args());
+
+        // Same flag for both, only the direction of the scale differs
+        args.push("-q:v".to_string());
+        args.push(mid_q.floor().to_string());
+
+        args.push("-y".to_string());
+        args.push(output.clone());
+
+        let output_cmd = scope.output(Tool::Ffmpeg, args).await?;
+
+        if !output_cmd.success() {
+            continue;
+        }
+
+        let current_size = std::fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+        let diff = current_size.abs_diff(target_bytes);
+
+        if current_size <= target_bytes && diff < best_size_diff {
+            best_q = mid_q;
+            best_size_diff = diff;
+        }
+
+        // Adjust search window
+        if current_size > target_bytes {
+            if is_jpg {
+                min_q = mid_q; // lower quality means higher q:v
+            } else {
+                max_q = mid_q; // lower quality means lower q:v
+            }
+        } else {
+            if is_jpg {
+                max_q = mid_q;
+            } else {
+                min_q = mid_q;
+            }
+        }
+
+        engine.emit(
+            "ffmpeg-progress",
+            format!("Testing quality index: {}", mid_q.floor()),
+        );
+    }
+
+    // Final encode with best_q if we didn't just leave it at the last iteration
+    let mut args = vec![
+        "-hwaccel".to_string(),
+        "auto".to_string(),
+        "-i".to_string(),
+        input.clone(),
+    ];
+    if width != "0" && !width.is_empty() {
+        let h = if height.is_empty() || height == "0" {
+            "-1"
+        } else {
+            &height
+        };
+        args.push("-vf".to_string());
+        args.push(format!("scale={}:{}", width, h));
+    } else if let Some(scale) = preset.scale_filter(false) {
+        args.push("-vf".to_string());
+        args.push(scale);
+    }
+    args.extend(preset.metadata_args());
+
+    args.push("-q:v".to_string());
+    args.push(best_q.floor().to_string());
+    args.push("-y".to_string());
+    args.push(output.clone());
+    scope.output(Tool::Ffmpeg, args).await?;
+
+    let final_size = std::fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+    if final_size > target_bytes + (target_bytes / 10) {
+        // 10% tolerance
+        return Err(CompressError::TargetTooSmall {
+            detail: "Cannot compress to this target size without reducing dimensions further."
+                .to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// Returns the loudness report when normalizing
+pub async fn compress_audio(
+    scope: &JobScope<'_>,
+    input: String,
+    output: String,
+    preset: Option<String>,
+    options: AudioOptions,
+) -> Result<Option<LoudnessReport>> {
+    let engine = scope.engine;
+    let input_path = Path::new(&input);
+    if !input_path.exists() {
+        return Err(CompressError::input_not_found(&input));
+    }
+    let preset = presets::resolve(engine, preset.as_deref())?;
+    let info = probe::probe(scope, &input).await?;
+    if !info.has_audio() {
+        return Err(CompressError::MissingStream {
+            kind: "audio".to_string(),
+        });
+    }
+    let ext = Path::new(&output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let plan = audio::plan(&ext, &info, &preset, &options)?;
+    if let Some(note) = plan.note {
+        engine.note(note);
+    }
+    scope.track_output(output.clone().into());
+
+    let normalization =
+        measure_loudness(scope, options.loudness, &input, &info, None, true).await?;
+    let mut args = progress::progress_args();
+    args.extend(vec![
+        "-hwaccel".to_string(),
+        "auto".to_string(),
+        "-i".to_string(),
+        input.clone(),
+    ]);
+    if let Some(n) = &normalization {
+        args.extend(vec!["-af".to_string(), n.filter.clone()]);
+    }
+    args.extend(plan.args);
+    args.extend(preset.metadata_args());
+
+    args.push("-y".to_string());
+    args.push(output.clone());
+
+    let tracker = ProgressTracker::new(&scope.id, info.duration, None);
+    progress::run_ffmpeg(scope, args, tracker, "").await?;
+    loudness_report(scope, &output, &normalization).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::KeepRange;
+    use crate::ladder::{AudioOutput, Rendition};
+    use crate::loudness::LoudnessStats;
+    use crate::testing::{
+        block_on, encoding, engine_with, ffmpeg_calls, probing, scoring, strings, Call, Script,
+        ScriptedRunner, TempDir, PROBE_1080P, PROBE_MULTI_TRACK,
+    };
+    use crate::transform::CropRect;
+
+    fn av1() -> VideoOptions {
+        VideoOptions {
+            codec: CodecChoice::Av1,
+            ..Default::default()
+        }
+    }
+
+    fn null_sink() -> &'static str {
+        if cfg!(target_os = "windows") {
+            "NUL"
+        } else {
+            "/dev/null"
+        }
+    }
+
+    // Stats prefix two-pass encodes use inside the first direct job's work dir
+    fn passlog(dir: &TempDir) -> String {
+        dir.0
+            .join("compress-io-direct-0")
+            .join("ffmpeg2pass")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn video_mp4_default_preset_uses_software_hevc() {
+        let dir = TempDir::new("video-mp4");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(
+            calls[0],
+            Call {
+                tool: Tool::Ffprobe,
+                args: probe::ffprobe_args(&input)
+            }
+        );
+        assert_eq!(
+            ffmpeg_calls(&calls),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "libx265",
+                "-preset",
+                "faster",
+                "-crf",
+                "24",
+                "-pix_fmt",
+                "yuv420p",
+                "-tag:v",
+                "hvc1",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_mp4_web_preset_scales_and_strips_metadata() {
+        let dir = TempDir::new("video-web");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            Some("web".to_string()),
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(ffmpeg_calls(&calls.lock().unwrap()), vec![strings(&[
+            "-progress", "pipe:1", "-hwaccel", "auto", "-i", &input,
+            "-vf", "scale=w='min(iw,1920)':h='min(ih,1080)':force_original_aspect_ratio=decrease:force_divisible_by=2",
+            "-c:v", "libx264", "-preset", "faster", "-crf", "22", "-profile:v", "high", "-pix_fmt", "yuv420p",
+            "-map", "0:0", "-map", "0:1", "-c:a:0", "copy", "-map_chapters", "0", "-map_metadata", "-1", "-y", &output,
+        ])]);
+    }
+
+    #[test]
+    fn video_h264_override_with_profile_and_level() {
+        let dir = TempDir::new("video-h264");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = VideoOptions {
+            codec: CodecChoice::H264,
+            h264_profile: Some(H264Profile::Main),
+            h264_level: Some("4.0".to_string()),
+            ..Default::default()
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "libx264",
+                "-preset",
+                "faster",
+                "-crf",
+                "20",
+                "-profile:v",
+                "main",
+                "-level:v",
+                "4.0",
+                "-pix_fmt",
+                "yuv420p",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_unknown_h264_level_is_rejected() {
+        let dir = TempDir::new("video-h264-level");
+        let input = dir.file("in.mov", b"");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = VideoOptions {
+            h264_level: Some("9.9".to_string()),
+            ..Default::default()
+        };
+
+        let result = block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input,
+            dir.path("out.mp4"),
+            false,
+            None,
+            options,
+        ));
+
+        assert!(matches!(result, Err(CompressError::InvalidRequest { .. })));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn video_soft_subtitle_file_is_added_after_the_source_tracks() {
+        let dir = TempDir::new("video-soft-subs");
+        let input = dir.file("in.mkv", b"");
+        let subs = dir.file("subs.srt", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_MULTI_TRACK));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let subtitles = SubtitleOptions {
+            file: Some(subs.clone()),
+            language: Some("DEU".to_string()),
+            default: true,
+            ..Default::default()
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            VideoOptions {
+                subtitles: Some(subtitles),
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-i",
+                &subs,
+                "-c:v",
+                "libx265",
+                "-preset",
+                "faster",
+                "-crf",
+                "24",
+                "-pix_fmt",
+                "yuv420p",
+                "-tag:v",
+                "hvc1",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map",
+                "0:2",
+                "-c:a:1",
+                "aac",
+                "-map",
+                "0:3",
+                "-c:s:0",
+                "mov_text",
+                "-map_chapters",
+                "0",
+                "-map",
+                "1:0",
+                "-c:s:1",
+                "mov_text",
+                "-metadata:s:s:1",
+                "language=deu",
+                "-disposition:s:1",
+                "default",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_crop_and_rotation_run_before_scaling() {
+        let dir = TempDir::new("video-rotate");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let transform = TransformOptions {
+            rotate: 90,
+            flip_horizontal: true,
+            crop: Some(CropRect {
+                x: 240,
+                y: 0,
+                width: 1441,
+                height: 1080,
+            }),
+            ..Default::default()
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            Some("web".to_string()),
+            VideoOptions {
+                transform,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg[0][6..8], strings(&[
+            "-vf", "crop=1440:1080:240:0,transpose=clock,hflip,scale=w='min(iw,1920)':h='min(ih,1080)':force_original_aspect_ratio=decrease:force_divisible_by=2",
+        ]));
+    }
+
+    #[test]
+    fn video_quality_target_picks_highest_passing_crf() {
+        let dir = TempDir::new("video-vmaf");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(scoring(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let target = QualityTarget {
+            metric: quality::QualityMetric::Vmaf,
+            score: 95.0,
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            VideoOptions {
+                target_quality: Some(target),
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        // The search itself is covered in quality.rs; the real encode uses the CRF it settled on
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg.len(), 9);
+        assert_eq!(
+            ffmpeg[8][6..17],
+            strings(&[
+                "-c:v", "libx265", "-preset", "faster", "-crf", "20", "-pix_fmt", "yuv420p",
+                "-tag:v", "hvc1", "-map"
+            ])
+        );
+    }
+
+    #[test]
+    fn video_webm_uses_vp9_and_opus() {
+        let dir = TempDir::new("video-webm");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.webm");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "libvpx-vp9",
+                "-crf",
+                "30",
+                "-b:v",
+                "0",
+                "-pix_fmt",
+                "yuv420p",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "libopus",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_av1_mp4_uses_svt_av1() {
+        let dir = TempDir::new("video-av1");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            av1(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "libsvtav1",
+                "-preset",
+                "8",
+                "-crf",
+                "32",
+                "-pix_fmt",
+                "yuv420p",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_av1_webm_keeps_av1_with_opus() {
+        let dir = TempDir::new("video-av1-webm");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.webm");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            av1(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "libsvtav1",
+                "-preset",
+                "8",
+                "-crf",
+                "32",
+                "-pix_fmt",
+                "yuv420p",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "libopus",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    // NVENC is the first AV1 candidate off macOS; the fake answers the encoder probe with success.
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn video_av1_gpu_uses_probed_hardware_encoder() {
+        let dir = TempDir::new("video-av1-gpu");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mkv");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            true,
+            None,
+            av1(),
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(
+            ffmpeg[0],
+            strings(&[
+                "-f",
+                "lavfi",
+                "-i",
+                "color=s=1280x720:d=0.1",
+                "-c:v",
+                "av1_nvenc",
+                "-f",
+                "null",
+                "-"
+            ])
+        );
+        assert_eq!(
+            ffmpeg[1],
+            strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-c:v",
+                "av1_nvenc",
+                "-preset",
+                "p4",
+                "-cq",
+                "32",
+                "-b:v",
+                "0",
+                "-pix_fmt",
+                "yuv420p",
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map",
+                "0:t?",
+                "-map_chapters",
+                "0",
+                "-y",
+                &output,
+            ])
+        );
+    }
+
+    #[test]
+    fn video_parallel_segments_split_at_keyframes_and_join() {
+        let dir = TempDir::new("video-segments");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(|tool, args: &[String]| match tool {
+            Tool::Ffprobe if args.contains(&"packet=pts_time,flags".to_string()) => Script::stdout(
+                "0.000000,K__\n1.001000,___\n2.502000,K__\n5.005000,K__\n7.507000,K__",
+            ),
+            Tool::Ffprobe => Script::stdout(PROBE_1080P),
+            _ => Script::ok(),
+        });
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = VideoOptions {
+            parallel_segments: Some(2),
+            ..Default::default()
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        let work = dir.0.join("compress-io-direct-0");
+        let file = |name: &str| work.join(name).to_string_lossy().to_string();
+        let encode = [
+            "-c:v", "libx265", "-preset", "faster", "-crf", "24", "-pix_fmt", "yuv420p", "-an",
+            "-sn", "-dn", "-f", "matroska",
+        ];
+        let segment = |seek: &[&str], name: &str| {
+            let mut args = strings(&["-progress", "pipe:1", "-y"]);
+            args.extend(strings(seek));
+            args.extend(strings(&["-i", &input, "-map", "0:0"]));
+            args.extend(strings(&encode));
+            args.push(file(name));
+            args
+        };
+        // 5.005 is the keyframe closest to the middle
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![
+                segment(&["-t", "5.005"], "segment-0.mkv"),
+                segment(&["-ss", "5.005"], "segment-1.mkv"),
+                strings(&[
+                    "-y",
+                    "-f",
+                    "concat",
+                    "-safe",
+                    "0",
+                    "-i",
+                    &file("segments.txt"),
+                    "-i",
+                    &input,
+                    "-c:v",
+                    "copy",
+                    "-tag:v",
+                    "hvc1",
+                    "-map",
+                    "0:v:0",
+                    "-map",
+                    "1:1",
+                    "-c:a:0",
+                    "copy",
+                    "-map_chapters",
+                    "1",
+                    "-map_metadata",
+                    "1",
+                    &output,
+                ]),
+            ]
+        );
+        assert!(!work.exists());
+    }
+
+    #[test]
+    fn video_gif_uses_palette_filter() {
+        let dir = TempDir::new("video-gif");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.gif");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(ffmpeg_calls(&calls.lock().unwrap()), vec![strings(&[
+            "-progress", "pipe:1", "-i", &input,
+            "-filter_complex", "fps=15,scale=480:-1:flags=lanczos,split[s0][s1];[s0]palettegen[p];[s1][p]paletteuse",
+            "-y", &output,
+        ])]);
+    }
+
+    #[test]
+    fn video_streaming_writes_hls_ladder_and_manifest() {
+        let dir = TempDir::new("video-hls");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("hls");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        // 1440p can't come out of a 1080p source and is left out
+        let options = StreamingOptions {
+            renditions: vec![
+                Rendition {
+                    height: 360,
+                    video_kbps: 800,
+                },
+                Rendition {
+                    height: 1440,
+                    video_kbps: 9000,
+                },
+                Rendition {
+                    height: 720,
+                    video_kbps: 2800,
+                },
+            ],
+            segment_seconds: Some(4.0),
+            ..Default::default()
+        };
+
+        let manifest = block_on(compress_video_streaming(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        let file = |name: &str| Path::new(&output).join(name).to_string_lossy().to_string();
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-y",
+                "-i",
+                &input,
+                "-filter_complex",
+                "[0:0]split=2[s0][s1];[s0]scale=1280:720[v0];[s1]scale=640:360[v1]",
+                "-map",
+                "[v0]",
+                "-map",
+                "[v1]",
+                "-map",
+                "0:1",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "128k",
+                "-ac",
+                "2",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "faster",
+                "-b:v:0",
+                "2800k",
+                "-maxrate:v:0",
+                "2996k",
+                "-bufsize:v:0",
+                "4200k",
+                "-b:v:1",
+                "800k",
+                "-maxrate:v:1",
+                "856k",
+                "-bufsize:v:1",
+                "1200k",
+                "-force_key_frames",
+                "expr:gte(t,n_forced*4)",
+                "-pix_fmt",
+                "yuv420p",
+                "-f",
+                "hls",
+                "-hls_time",
+                "4",
+                "-hls_playlist_type",
+                "vod",
+                "-hls_flags",
+                "independent_segments",
+                "-hls_segment_filename",
+                &file("%v/segment_%05d.ts"),
+                "-master_pl_name",
+                "master.m3u8",
+                "-var_stream_map",
+                "v:0,agroup:audio,name:720p v:1,agroup:audio,name:360p a:0,agroup:audio,name:audio",
+                &file("%v/index.m3u8"),
+            ])]
+        );
+        assert!(Path::new(&file("720p")).is_dir() && Path::new(&file("audio")).is_dir());
+
+        assert_eq!(manifest.master_playlist, "master.m3u8");
+        assert_eq!(manifest.dash_manifest, None);
+        let names: Vec<(&str, u32, u32, &str)> = manifest
+            .renditions
+            .iter()
+            .map(|r| (r.name.as_str(), r.width, r.height, r.playlist.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("720p", 1280, 720, "720p/index.m3u8"),
+                ("360p", 640, 360, "360p/index.m3u8")
+            ]
+        );
+        assert_eq!(
+            manifest.audio,
+            Some(AudioOutput {
+                kbps: 128,
+                playlist: "audio/index.m3u8".to_string()
+            })
+        );
+        let written: StreamingManifest =
+            serde_json::from_str(&std::fs::read_to_string(file("renditions.json")).unwrap())
+                .unwrap();
+        assert_eq!(written, manifest);
+    }
+
+    #[test]
+    fn video_streaming_dash_shares_cmaf_segments_with_hls() {
+        let dir = TempDir::new("video-dash");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("dash");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = StreamingOptions {
+            renditions: vec![Rendition {
+                height: 480,
+                video_kbps: 1400,
+            }],
+            dash: true,
+            ..Default::default()
+        };
+
+        let manifest = block_on(compress_video_streaming(
+            &JobScope::new(&engine, None),
+            input,
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        let muxer = ffmpeg[0].iter().position(|a| a == "dash").unwrap() - 1;
+        assert_eq!(
+            ffmpeg[0][muxer..],
+            strings(&[
+                "-f",
+                "dash",
+                "-seg_duration",
+                "6",
+                "-use_template",
+                "1",
+                "-use_timeline",
+                "1",
+                "-adaptation_sets",
+                "id=0,streams=v id=1,streams=a",
+                "-init_seg_name",
+                "init-$RepresentationID$.m4s",
+                "-media_seg_name",
+                "chunk-$RepresentationID$-$Number%05d$.m4s",
+                "-hls_playlist",
+                "1",
+                "-hls_master_name",
+                "master.m3u8",
+                &Path::new(&output).join("manifest.mpd").to_string_lossy(),
+            ])
+        );
+        assert_eq!(manifest.dash_manifest.as_deref(), Some("manifest.mpd"));
+        assert_eq!(
+            (manifest.renditions[0].width, manifest.renditions[0].height),
+            (854, 480)
+        );
+        assert_eq!(manifest.renditions[0].playlist, "media_0.m3u8");
+        assert_eq!(manifest.audio.unwrap().playlist, "media_1.m3u8");
+    }
+
+    #[test]
+    fn video_target_size_runs_two_passes_at_computed_bitrate() {
+        let dir = TempDir::new("video-target");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[4_900_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let log = passlog(&dir);
+
+        // 5000 KB over 10 s = 4000 kbps, minus 128k audio, minus 5% -> 3678k
+        block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            5000.0,
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![
+                strings(&[
+                    "-progress",
+                    "pipe:1",
+                    "-y",
+                    "-i",
+                    &input,
+                    "-c:v",
+                    "libx265",
+                    "-preset",
+                    "faster",
+                    "-b:v",
+                    "3678k",
+                    "-pix_fmt",
+                    "yuv420p",
+                    "-pass",
+                    "1",
+                    "-passlogfile",
+                    &log,
+                    "-an",
+                    "-f",
+                    "null",
+                    null_sink(),
+                ]),
+                strings(&[
+                    "-progress",
+                    "pipe:1",
+                    "-y",
+                    "-i",
+                    &input,
+                    "-c:v",
+                    "libx265",
+                    "-preset",
+                    "faster",
+                    "-b:v",
+                    "3678k",
+                    "-pix_fmt",
+                    "yuv420p",
+                    "-pass",
+                    "2",
+                    "-passlogfile",
+                    &log,
+                    "-c:a",
+                    "aac",
+                    "-b:a",
+                    "128k",
+                    &output,
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn video_target_size_webm_runs_vp9_two_pass_in_job_dir() {
+        let dir = TempDir::new("video-target-webm");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.webm");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[4_900_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let log = passlog(&dir);
+
+        block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            5000.0,
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(
+            ffmpeg[0][5..],
+            strings(&[
+                "-c:v",
+                "libvpx-vp9",
+                "-b:v",
+                "3678k",
+                "-pix_fmt",
+                "yuv420p",
+                "-pass",
+                "1",
+                "-passlogfile",
+                &log,
+                "-speed",
+                "4",
+                "-an",
+                "-f",
+                "null",
+                null_sink()
+            ])
+        );
+        assert_eq!(
+            ffmpeg[1][5..],
+            strings(&[
+                "-c:v",
+                "libvpx-vp9",
+                "-b:v",
+                "3678k",
+                "-pix_fmt",
+                "yuv420p",
+                "-pass",
+                "2",
+                "-passlogfile",
+                &log,
+                "-c:a",
+                "libopus",
+                "-b:a",
+                "128k",
+                &output
+            ])
+        );
+        // The stats go away with the job
+        assert!(!dir.0.join("compress-io-direct-0").exists());
+    }
+
+    #[test]
+    fn video_target_size_av1_runs_svt_av1_two_pass() {
+        let dir = TempDir::new("video-target-av1");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[4_900_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let log = passlog(&dir);
+
+        block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            5000.0,
+            false,
+            None,
+            av1(),
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(
+            ffmpeg[0][5..],
+            strings(&[
+                "-c:v",
+                "libsvtav1",
+                "-preset",
+                "8",
+                "-b:v",
+                "3678k",
+                "-pix_fmt",
+                "yuv420p",
+                "-pass",
+                "1",
+                "-passlogfile",
+                &log,
+                "-an",
+                "-f",
+                "null",
+                null_sink()
+            ])
+        );
+        assert_eq!(
+            ffmpeg[1][5..],
+            strings(&[
+                "-c:v",
+                "libsvtav1",
+                "-preset",
+                "8",
+                "-b:v",
+                "3678k",
+                "-pix_fmt",
+                "yuv420p",
+                "-pass",
+                "2",
+                "-passlogfile",
+                &log,
+                "-c:a",
+                "aac",
+                "-b:a",
+                "128k",
+                &output
+            ])
+        );
+    }
+
+    #[test]
+    fn video_cut_and_append_joins_through_concat() {
+        let dir = TempDir::new("video-concat");
+        let input = dir.file("in.mov", b"");
+        let outro = dir.file("outro.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let edit = EditList {
+            keep: vec![
+                KeepRange {
+                    start: 0.0,
+                    end: Some(2.0),
+                },
+                KeepRange {
+                    start: 5.0,
+                    end: None,
+                },
+            ],
+            append: vec![outro.clone()],
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            Some("web".to_string()),
+            VideoOptions {
+                edit,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        // The segment chains are covered in edit.rs; the preset's scale runs on the joined video
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        let graph = &ffmpeg[0][9];
+        assert!(graph.ends_with(
+            ";[v0][a0][v1][a1][v2][a2]concat=n=3:v=1:a=1[vj][a];[vj]scale=w='min(iw,1920)':h='min(ih,1080)':force_original_aspect_ratio=decrease:force_divisible_by=2[v]"
+        ));
+        assert_eq!(
+            ffmpeg,
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-i",
+                &outro,
+                "-filter_complex",
+                graph,
+                "-map",
+                "[v]",
+                "-map",
+                "[a]",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "faster",
+                "-crf",
+                "22",
+                "-profile:v",
+                "high",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "128k",
+                "-map_chapters",
+                "-1",
+                "-map_metadata",
+                "-1",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn video_target_size_uses_trimmed_duration() {
+        let dir = TempDir::new("video-target-trim");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[4_900_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let log = passlog(&dir);
+        let options = VideoOptions {
+            edit: EditList {
+                keep: vec![KeepRange {
+                    start: 0.0,
+                    end: Some(5.0),
+                }],
+                append: vec![],
+            },
+            ..Default::default()
+        };
+
+        // 5000 KB over 5 s = 8000 kbps, minus 128k audio, minus 5% -> 7478k
+        block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            5000.0,
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg.len(), 2);
+        assert_eq!(
+            ffmpeg[1],
+            strings(&[
+                "-progress",
+                "pipe:1",
+                "-y",
+                "-ss",
+                "0",
+                "-t",
+                "5",
+                "-i",
+                &input,
+                "-c:v",
+                "libx265",
+                "-preset",
+                "faster",
+                "-b:v",
+                "7478k",
+                "-pix_fmt",
+                "yuv420p",
+                "-pass",
+                "2",
+                "-passlogfile",
+                &log,
+                "-c:a",
+                "aac",
+                "-b:a",
+                "128k",
+                &output,
+            ])
+        );
+    }
+
+    #[test]
+    fn video_target_size_lowers_resolution_for_thin_bitrates() {
+        let dir = TempDir::new("video-target-fit");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[1_000_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let log = passlog(&dir);
+
+        // 1000 KB over 10 s leaves 638k: too little for 1080p or 720p HEVC, enough for 540p
+        block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            1000.0,
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg[1], strings(&[
+            "-progress", "pipe:1", "-y", "-i", &input,
+            "-vf", "scale=w='min(iw,960)':h='min(ih,540)':force_original_aspect_ratio=decrease:force_divisible_by=2",
+            "-c:v", "libx265", "-preset", "faster", "-b:v", "638k", "-pix_fmt", "yuv420p", "-pass", "2", "-passlogfile", &log, "-c:a", "aac", "-b:a", "128k", &output,
+        ]));
+    }
+
+    #[test]
+    fn video_caps_from_options_never_upscale() {
+        let dir = TempDir::new("video-caps");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mkv");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = VideoOptions {
+            max_height: Some(720),
+            max_fps: Some(24.0),
+            ..Default::default()
+        };
+
+        block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(
+            ffmpeg[0][6..10],
+            strings(&["-vf", "scale=w=-2:h='min(ih,720)'", "-fpsmax", "24"])
+        );
+    }
+
+    #[test]
+    fn video_target_size_retries_with_corrected_bitrate_when_over() {
+        let dir = TempDir::new("video-target-retry");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(encoding(PROBE_1080P, &[6_000_000, 5_000_000]));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        let report = block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input,
+            output,
+            5000.0,
+            false,
+            None,
+            VideoOptions::default(),
+        ))
+        .unwrap();
+
+        // 6 MB with 160 KB of audio: the video gets (5 MB * 0.98 - 160 KB) / 5.84 MB of its bitrate
+        let bitrates: Vec<String> = ffmpeg_calls(&calls.lock().unwrap())
+            .iter()
+            .filter(|args| args.contains(&"2".to_string()))
+            .map(|args| args[args.iter().position(|a| a == "-b:v").unwrap() + 1].clone())
+            .collect();
+        assert_eq!(bitrates, strings(&["3678k", "3059k"]));
+        assert_eq!(
+            report.size,
+            Some(SizeReport {
+                target_bytes: 5_120_000,
+                output_bytes: 5_000_000,
+                attempts: 2,
+                video_kbps: 3059,
+                audio_kbps: 128,
+                within_target: true
+            })
+        );
+    }
+
+    #[test]
+    fn video_target_size_too_small_never_starts_ffmpeg() {
+        let dir = TempDir::new("video-too-small");
+        let input = dir.file("in.mov", b"");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        let result = block_on(compress_video_target_size(
+            &JobScope::new(&engine, None),
+            input,
+            dir.path("out.mp4"),
+            100.0,
+            false,
+            None,
+            VideoOptions::default(),
+        ));
+
+        assert!(matches!(result, Err(CompressError::TargetTooSmall { .. })));
+        assert!(ffmpeg_calls(&calls.lock().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn image_jpg_with_width() {
+        let dir = TempDir::new("image-jpg");
+        let input = dir.file("in.png", b"");
+        let output = dir.path("out.jpg");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_image(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            "800".to_string(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-vf",
+                "scale=800:-1",
+                "-q:v",
+                "2",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    #[test]
+    fn audio_mp3_defaults_to_vbr() {
+        let dir = TempDir::new("audio-mp3");
+        let input = dir.file("in.wav", b"");
+        let output = dir.path("out.mp3");
+        let runner = ScriptedRunner::new(probing(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+
+        block_on(compress_audio(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            None,
+            AudioOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![strings(&[
+                "-progress",
+                "pipe:1",
+                "-hwaccel",
+                "auto",
+                "-i",
+                &input,
+                "-map",
+                "0:1",
+                "-c:a",
+                "libmp3lame",
+                "-q:a",
+                "4",
+                "-y",
+                &output,
+            ])]
+        );
+    }
+
+    // loudnorm's print_format=json block for a run measuring `i` LUFS, `tp` dBTP and `lra` LU
+    fn loudnorm(i: &str, tp: &str, lra: &str) -> Script {
+        let lines = [
+            "[Parsed_loudnorm_0 @ 0x55d0] ".to_string(),
+            "{".to_string(),
+            format!("\t\"input_i\" : \"{}\",", i),
+            format!("\t\"input_tp\" : \"{}\",", tp),
+            format!("\t\"input_lra\" : \"{}\",", lra),
+            "\t\"input_thresh\" : \"-38.10\",".to_string(),
+            "\t\"target_offset\" : \"0.35\"".to_string(),
+            "}".to_string(),
+        ];
+        Script {
+            stderr: lines.to_vec(),
+            code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    // The source measures -27.61 LUFS, the written file -16.02
+    fn measuring(output: String) -> impl Fn(Tool, &[String]) -> Script + Send + Sync + 'static {
+        move |tool, args| match tool {
+            Tool::Ffprobe => Script::stdout(PROBE_1080P),
+            _ if args.contains(&output) && args.contains(&"null".to_string()) => {
+                loudnorm("-16.02", "-2.61", "5.90")
+            }
+            _ if args.contains(&"null".to_string()) => loudnorm("-27.61", "-14.20", "6.20"),
+            _ => Script::ok(),
+        }
+    }
+
+    const NORMALIZE: &str = "loudnorm=I=-16:TP=-1:LRA=11:measured_I=-27.61:measured_TP=-14.2:measured_LRA=6.2:measured_thresh=-38.1:offset=0.35:linear=true,aresample=48000";
+
+    #[test]
+    fn audio_loudness_measures_normalizes_and_reports() {
+        let dir = TempDir::new("audio-loudnorm");
+        let input = dir.file("in.wav", b"");
+        let output = dir.path("out.mp3");
+        let runner = ScriptedRunner::new(measuring(output.clone()));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = AudioOptions {
+            loudness: Some(LoudnessTarget {
+                integrated: -16.0,
+                true_peak: None,
+            }),
+            ..Default::default()
+        };
+
+        let report = block_on(compress_audio(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            None,
+            options,
+        ))
+        .unwrap()
+        .unwrap();
+
+        let measure = "loudnorm=I=-16:TP=-1:LRA=11:print_format=json";
+        assert_eq!(
+            ffmpeg_calls(&calls.lock().unwrap()),
+            vec![
+                strings(&["-i", &input, "-map", "0:1", "-af", measure, "-f", "null", "-"]),
+                strings(&[
+                    "-progress",
+                    "pipe:1",
+                    "-hwaccel",
+                    "auto",
+                    "-i",
+                    &input,
+                    "-af",
+                    NORMALIZE,
+                    "-map",
+                    "0:1",
+                    "-c:a",
+                    "libmp3lame",
+                    "-q:a",
+                    "4",
+                    "-y",
+                    &output
+                ]),
+                strings(&["-i", &output, "-map", "0:a:0", "-af", measure, "-f", "null", "-"]),
+            ]
+        );
+        assert_eq!(
+            report,
+            LoudnessReport {
+                target: -16.0,
+                before: LoudnessStats {
+                    integrated: -27.61,
+                    true_peak: -14.2,
+                    range: 6.2
+                },
+                after: LoudnessStats {
+                    integrated: -16.02,
+                    true_peak: -2.61,
+                    range: 5.9
+                },
+                linear: true,
+            }
+        );
+    }
+
+    #[test]
+    fn video_loudness_filters_the_first_audio_track() {
+        let dir = TempDir::new("video-loudnorm");
+        let input = dir.file("in.mov", b"");
+        let output = dir.path("out.mp4");
+        let runner = ScriptedRunner::new(measuring(output.clone()));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = VideoOptions {
+            loudness: Some(LoudnessTarget {
+                integrated: -16.0,
+                true_peak: None,
+            }),
+            ..Default::default()
+        };
+
+        let report = block_on(compress_video(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            false,
+            None,
+            options,
+        ))
+        .unwrap()
+        .unwrap();
+
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg.len(), 3);
+        let filter = ffmpeg[1].iter().position(|a| a == "-filter:a:0").unwrap();
+        assert_eq!(ffmpeg[1][filter + 1], NORMALIZE);
+        // Normalized audio can't be copied
+        assert!(!ffmpeg[1]
+            .windows(2)
+            .any(|w| w[0].starts_with("-c:a") && w[1] == "copy"));
+        assert_eq!(report.size, None);
+        assert_eq!(report.loudness.map(|l| l.after.integrated), Some(-16.02));
+    }
+
+    #[test]
+    fn audio_loudness_leaves_silence_alone() {
+        let dir = TempDir::new("audio-silent");
+        let input = dir.file("in.wav", b"");
+        let output = dir.path("out.mp3");
+        let runner = ScriptedRunner::new(|tool, args: &[String]| match tool {
+            Tool::Ffprobe => Script::stdout(PROBE_1080P),
+            _ if args.contains(&"null".to_string()) => loudnorm("-inf", "-inf", "0.00"),
+            _ => Script::ok(),
+        });
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let options = AudioOptions {
+            loudness: Some(LoudnessTarget {
+                integrated: -23.0,
+                true_peak: Some(-2.0),
+            }),
+            ..Default::default()
+        };
+
+        let report = block_on(compress_audio(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            None,
+            options,
+        ))
+        .unwrap();
+
+        assert_eq!(report, None);
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg.len(), 2);
+        assert!(!ffmpeg[1].contains(&"-af".to_string()));
+    }
+
+    #[test]
+    fn ffmpeg_failure_reports_stderr_tail() {
+        let dir = TempDir::new("ffmpeg-fails");
+        let input = dir.file("in.png", b"");
+        let runner =
+            ScriptedRunner::new(|_, _| Script::failing(1, &["frame=    1", "Conversion failed!"]));
+        let engine = engine_with(runner, &dir.0);
+
+        let result = block_on(compress_image(
+            &JobScope::new(&engine, None),
+            input,
+            dir.path("out.jpg"),
+            "0".to_string(),
+            "0".to_string(),
+            None,
+        ));
+
+        assert_eq!(
+            result,
+            Err(CompressError::FfmpegFailed {
+                exit_code: Some(1),
+                stderr_tail: strings(&["frame=    1", "Conversion failed!"]),
+            })
+        );
+    }
+
+    #[test]
+    fn cancelled_job_does_not_spawn() {
+        let dir = TempDir::new("cancelled");
+        let input = dir.file("in.png", b"");
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        // Cancelled from the queue while it was still probing
+        let scope = JobScope::new(&engine, Some("7".to_string()));
+        engine.registry.cancel("7");
+
+        let result = block_on(compress_image(
+            &scope,
+            input,
+            dir.path("out.jpg"),
+            "0".to_string(),
+            "0".to_string(),
+            None,
+        ));
+
+        assert_eq!(result, Err(CompressError::Cancelled));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cancelled_job_does_not_probe() {
+        let dir = TempDir::new("cancelled-probe");
+        let input = dir.file("in.mp4", b"");
+        let runner = ScriptedRunner::new(|_, _| Script::stdout(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let scope = JobScope::new(&engine, Some("8".to_string()));
+        engine.registry.cancel("8");
+
+        let result = block_on(compress_video(
+            &scope,
+            input,
+            dir.path("out.mp4"),
+            false,
+            None,
+            VideoOptions::default(),
+        ));
+
+        // ffprobe runs under the job too, so the cancel stops it before anything is spawned
+        assert_eq!(result, Err(CompressError::Cancelled));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn enhance_image_runs_ai_engine() {
+        let dir = TempDir::new("enhance-image");
+        let input = dir.file("in.png", b"");
+        let output = dir.path("out.png");
+        let runner = ScriptedRunner::new(|_, _| Script {
+            stderr: strings(&["25.00%", "100.00%"]),
+            code: Some(0),
+            ..Default::default()
+        });
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        std::fs::create_dir_all(&engine.ai_engine_dir).unwrap();
+        std::fs::write(engine.ai_engine_path(), b"").unwrap();
+
+        block_on(enhance_image(
+            &JobScope::new(&engine, None),
+            input.clone(),
+            output.clone(),
+            "4".to_string(),
+            "png".to_string(),
+            "anime".to_string(),
+            false,
+            true,
+            "0".to_string(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![Call {
+                tool: Tool::AiEngine,
+                args: strings(&[
+                    "-i",
+                    &input,
+                    "-o",
+                    &output,
+                    "-s",
+                    "4",
+                    "-f",
+                    "png",
+                    "-n",
+                    "realesr-animevideov3-x4",
+                    "-t",
+                    "0",
+                    "-x"
+                ]),
+            }]
+        );
+    }
+
+    #[test]
+    fn enhance_image_without_engine_binary() {
+        let dir = TempDir::new("enhance-missing");
+        let input = dir.file("in.png", b"");
+        let engine = engine_with(ScriptedRunner::new(|_, _| Script::ok()), &dir.0);
+
+        let result = block_on(enhance_image(
+            &JobScope::new(&engine, None),
+            input,
+            dir.path("out.png"),
+            "4".to_string(),
+            "png".to_string(),
+            "general".to_string(),
+            false,
+            false,
+            "0".to_string(),
+        ));
+
+        assert!(matches!(result, Err(CompressError::AiEngineMissing { .. })));
+    }
+}
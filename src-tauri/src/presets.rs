@@ -0,0 +1,550 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::encoders::{H264Profile, VideoCodec, DEFAULT_QUALITY, H264_LEVELS};
+use crate::engine::Engine;
+use crate::error::{CompressError, Result};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+    Mp3,
+    // Keep the source audio untouched
+    Copy,
+}
+
+impl AudioCodec {
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Mp3 => "libmp3lame",
+            AudioCodec::Copy => "copy",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataPolicy {
+    Keep,
+    // Drops global tags (GPS, camera model, encoder names...) from the output
+    Strip,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamPolicy {
+    // Every audio and subtitle track the output container can hold, plus chapters (see streams.rs)
+    All,
+    // ffmpeg's default pick: one video and one audio stream
+    Primary,
+}
+
+// Everything a compression job needs to know besides input and output. `None` fields keep the
+// per-format defaults the app always used (encoder default audio bitrate, JPEG q:v 2, WebP 75...).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Preset {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    // Built-ins ship with the app and can't be edited or deleted
+    pub builtin: bool,
+    pub video_codec: VideoCodec,
+    // CRF on the x265 scale; each encoder translates it (see encoders.rs)
+    pub quality: u32,
+    // Only applied when the output ends up H.264; None leaves it to the encoder
+    pub h264_profile: Option<H264Profile>,
+    pub h264_level: Option<String>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_fps: Option<f64>,
+    pub audio_codec: AudioCodec,
+    pub audio_bitrate_kbps: Option<u32>,
+    // 1-100, higher is better. Only used by lossy image formats.
+    pub image_quality: Option<u32>,
+    // Video jobs with a target go through the target-size pipeline
+    pub target_size_kb: Option<f64>,
+    pub metadata: MetadataPolicy,
+    // Which input streams a video job keeps
+    pub streams: StreamPolicy,
+}
+
+// Also fills in whatever an imported or newly created preset leaves out.
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            id: String::new(),
+            name: String::new(),
+            description: String::new(),
+            builtin: false,
+            video_codec: VideoCodec::Hevc,
+            quality: DEFAULT_QUALITY,
+            h264_profile: None,
+            h264_level: None,
+            max_width: None,
+            max_height: None,
+            max_fps: None,
+            audio_codec: AudioCodec::Aac,
+            audio_bitrate_kbps: None,
+            image_quality: None,
+            target_size_kb: None,
+            metadata: MetadataPolicy::Keep,
+            streams: StreamPolicy::All,
+        }
+    }
+}
+
+fn builtin(id: &str, name: &str, description: &str) -> Preset {
+    Preset {
+        id: id.to_string(),
+        name: name.to_string(),
+        description: description.to_string(),
+        builtin: true,
+        ..Preset::default()
+    }
+}
+
+// What commands use when no preset is given: the settings the app always had.
+pub fn default_preset() -> Preset {
+    builtin("default", "Default", "HEVC at CRF 24, original resolution")
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    vec![
+        default_preset(),
+        Preset {
+            video_codec: VideoCodec::H264,
+            quality: 26,
+            h264_profile: Some(H264Profile::High),
+            max_width: Some(1920),
+            max_height: Some(1080),
+            audio_bitrate_kbps: Some(128),
+            image_quality: Some(80),
+            metadata: MetadataPolicy::Strip,
+            ..builtin("web", "Web", "H.264 up to 1080p, plays in every browser")
+        },
+        Preset {
+            video_codec: VideoCodec::H264,
+            quality: 30,
+            h264_profile: Some(H264Profile::Main),
+            max_width: Some(1280),
+            max_height: Some(720),
+            max_fps: Some(30.0),
+            audio_bitrate_kbps: Some(96),
+            image_quality: Some(70),
+            target_size_kb: Some(20.0 * 1024.0),
+            metadata: MetadataPolicy::Strip,
+            ..builtin(
+                "email",
+                "Email",
+                "720p under 20 MB, fits most attachment limits",
+            )
+        },
+        Preset {
+            quality: 20,
+            audio_codec: AudioCodec::Copy,
+            image_quality: Some(95),
+            ..builtin(
+                "archive",
+                "Archive",
+                "High quality HEVC, original audio and metadata",
+            )
+        },
+        Preset {
+            video_codec: VideoCodec::H264,
+            quality: 28,
+            h264_profile: Some(H264Profile::High),
+            max_width: Some(1920),
+            max_height: Some(1080),
+            max_fps: Some(60.0),
+            audio_bitrate_kbps: Some(128),
+            image_quality: Some(85),
+            target_size_kb: Some(25.0 * 1024.0),
+            metadata: MetadataPolicy::Strip,
+            ..builtin(
+                "discord",
+                "Discord (25 MB)",
+                "H.264 under 25 MB so it embeds and plays inline",
+            )
+        },
+        Preset {
+            video_codec: VideoCodec::H264,
+            quality: 28,
+            h264_profile: Some(H264Profile::Main),
+            max_width: Some(1280),
+            max_height: Some(1280),
+            max_fps: Some(30.0),
+            audio_bitrate_kbps: Some(128),
+            image_quality: Some(80),
+            target_size_kb: Some(16.0 * 1024.0),
+            metadata: MetadataPolicy::Strip,
+            ..builtin(
+                "whatsapp",
+                "WhatsApp",
+                "720p H.264 under WhatsApp's 16 MB video limit",
+            )
+        },
+    ]
+}
+
+pub fn validate_h264_level(level: Option<&str>) -> Result<()> {
+    match level {
+        Some(level) if !H264_LEVELS.contains(&level) => Err(CompressError::invalid(format!(
+            "Unknown H.264 level '{}', expected one of {}",
+            level,
+            H264_LEVELS.join(", ")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "app")]
+impl Preset {
+    fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(CompressError::invalid("Preset name can't be empty"));
+        }
+        if self.quality > 51 {
+            return Err(CompressError::invalid("Quality must be between 0 and 51"));
+        }
+        validate_h264_level(self.h264_level.as_deref())?;
+        if self
+            .image_quality
+            .map(|q| q == 0 || q > 100)
+            .unwrap_or(false)
+        {
+            return Err(CompressError::invalid(
+                "Image quality must be between 1 and 100",
+            ));
+        }
+        if self.max_width.map(|w| w < 2).unwrap_or(false)
+            || self.max_height.map(|h| h < 2).unwrap_or(false)
+        {
+            return Err(CompressError::invalid(
+                "Resolution cap must be at least 2 pixels",
+            ));
+        }
+        if self.max_fps.map(|f| f <= 0.0).unwrap_or(false) {
+            return Err(CompressError::invalid("Frame rate cap must be positive"));
+        }
+        if self.audio_bitrate_kbps == Some(0)
+            || self.target_size_kb.map(|t| t <= 0.0).unwrap_or(false)
+        {
+            return Err(CompressError::invalid(
+                "Bitrates and target sizes must be positive",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Preset {
+    // Downscale-only filter for the resolution cap. Video needs even dimensions for 4:2:0.
+    pub fn scale_filter(&self, even: bool) -> Option<String> {
+        let round = if even { ":force_divisible_by=2" } else { "" };
+        let keep = if even { "-2" } else { "-1" };
+        match (self.max_width, self.max_height) {
+            (Some(w), Some(h)) => Some(format!(
+                "scale=w='min(iw,{})':h='min(ih,{})':force_original_aspect_ratio=decrease{}",
+                w, h, round
+            )),
+            (Some(w), None) => Some(format!("scale=w='min(iw,{})':h={}", w, keep)),
+            (None, Some(h)) => Some(format!("scale=w={}:h='min(ih,{})'", keep, h)),
+            (None, None) => None,
+        }
+    }
+
+    // -fpsmax only drops frames when the source is faster than the cap
+    pub fn fps_args(&self) -> Vec<String> {
+        match self.max_fps {
+            Some(fps) => vec!["-fpsmax".to_string(), fps.to_string()],
+            None => vec![],
+        }
+    }
+
+    // Takes the codec separately for containers that only accept one (Opus in WebM).
+    pub fn audio_args(&self, codec: AudioCodec) -> Vec<String> {
+        let mut args = vec!["-c:a".to_string(), codec.encoder().to_string()];
+        if let Some(kbps) = self
+            .audio_bitrate_kbps
+            .filter(|_| codec != AudioCodec::Copy)
+        {
+            args.extend(["-b:a".to_string(), format!("{}k", kbps)]);
+        }
+        args
+    }
+
+    pub fn metadata_args(&self) -> Vec<String> {
+        match self.metadata {
+            MetadataPolicy::Keep => vec![],
+            MetadataPolicy::Strip => vec!["-map_metadata".to_string(), "-1".to_string()],
+        }
+    }
+
+    // JPEG's q:v runs 2 (best) to 31 (worst).
+    pub fn jpeg_qscale(&self) -> Option<u32> {
+        self.image_quality
+            .map(|q| 2 + (100 - q.min(100)) * 29 / 100)
+    }
+}
+
+// User presets as stored in presets.json. Built-ins live in code and are never written out.
+#[derive(Serialize, Deserialize, Default)]
+struct PresetFile {
+    presets: Vec<Preset>,
+}
+
+// Import accepts an exported file, a bare list or a single preset.
+#[cfg(feature = "app")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PresetImport {
+    File(PresetFile),
+    List(Vec<Preset>),
+    Single(Preset),
+}
+
+#[derive(Default)]
+pub struct PresetStore {
+    presets: Mutex<Vec<Preset>>,
+    store_path: Mutex<Option<PathBuf>>,
+}
+
+#[cfg(feature = "app")]
+fn slug(name: &str) -> String {
+    let slug: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "preset".to_string()
+    } else {
+        slug
+    }
+}
+
+#[cfg(feature = "app")]
+fn is_builtin_id(id: &str) -> bool {
+    builtin_presets().iter().any(|p| p.id == id)
+}
+
+impl PresetStore {
+    pub fn load(&self, path: PathBuf) {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            match serde_json::from_str::<PresetFile>(&text) {
+                Ok(file) => *self.presets.lock().unwrap() = file.presets,
+                Err(e) => eprintln!(
+                    "⚠️ PRESETS: Ignoring unreadable preset file {:?}: {}",
+                    path, e
+                ),
+            }
+        }
+        *self.store_path.lock().unwrap() = Some(path);
+    }
+
+    pub fn all(&self) -> Vec<Preset> {
+        let mut all = builtin_presets();
+        all.extend(self.presets.lock().unwrap().iter().cloned());
+        all
+    }
+
+    pub fn get(&self, id: &str) -> Option<Preset> {
+        self.all().into_iter().find(|p| p.id == id)
+    }
+}
+
+// Saving and editing only happen through the app's preset commands
+#[cfg(feature = "app")]
+impl PresetStore {
+    fn save(&self, presets: &[Preset]) -> Result<()> {
+        let store_path = self.store_path.lock().unwrap();
+        if let Some(path) = store_path.as_ref() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let text = serde_json::to_string_pretty(&PresetFile {
+                presets: presets.to_vec(),
+            })
+            .map_err(|e| CompressError::internal(e.to_string()))?;
+            std::fs::write(path, text)?;
+        }
+        Ok(())
+    }
+
+    // Next free id based on the name: "my-preset", "my-preset-2", ...
+    fn unique_id(presets: &[Preset], name: &str) -> String {
+        let base = slug(name);
+        let taken = |id: &str| is_builtin_id(id) || presets.iter().any(|p| p.id == id);
+        if !taken(&base) {
+            return base;
+        }
+        (2..)
+            .map(|n| format!("{}-{}", base, n))
+            .find(|id| !taken(id))
+            .unwrap()
+    }
+
+    fn insert(&self, mut preset: Preset) -> Result<Preset> {
+        preset.validate()?;
+        preset.builtin = false;
+        let mut presets = self.presets.lock().unwrap();
+        if preset.id.is_empty() {
+            preset.id = Self::unique_id(&presets, &preset.name);
+        } else if is_builtin_id(&preset.id) || presets.iter().any(|p| p.id == preset.id) {
+            return Err(CompressError::invalid(format!(
+                "A preset with id '{}' already exists",
+                preset.id
+            )));
+        }
+        presets.push(preset.clone());
+        self.save(&presets)?;
+        Ok(preset)
+    }
+}
+
+// Resolves the preset a command was called with; no preset means the app defaults.
+pub fn resolve(engine: &Engine, id: Option<&str>) -> Result<Preset> {
+    match id {
+        None | Some("") => Ok(default_preset()),
+        Some(id) => engine
+            .presets
+            .get(id)
+            .ok_or_else(|| CompressError::PresetNotFound { id: id.to_string() }),
+    }
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn list_presets(engine: tauri::State<'_, Engine>) -> Vec<Preset> {
+    engine.presets.all()
+}
+
+// An empty id gets one derived from the name.
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn create_preset(engine: tauri::State<'_, Engine>, preset: Preset) -> Result<Preset> {
+    engine.presets.insert(preset)
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn update_preset(engine: tauri::State<'_, Engine>, preset: Preset) -> Result<Preset> {
+    let store = &engine.presets;
+    if is_builtin_id(&preset.id) {
+        return Err(CompressError::invalid(
+            "Built-in presets can't be changed, save a copy instead",
+        ));
+    }
+    preset.validate()?;
+    let mut presets = store.presets.lock().unwrap();
+    let slot = presets
+        .iter_mut()
+        .find(|p| p.id == preset.id)
+        .ok_or_else(|| CompressError::PresetNotFound {
+            id: preset.id.clone(),
+        })?;
+    *slot = Preset {
+        builtin: false,
+        ..preset
+    };
+    let updated = slot.clone();
+    store.save(&presets)?;
+    Ok(updated)
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn delete_preset(engine: tauri::State<'_, Engine>, id: String) -> Result<()> {
+    let store = &engine.presets;
+    if is_builtin_id(&id) {
+        return Err(CompressError::invalid("Built-in presets can't be deleted"));
+    }
+    let mut presets = store.presets.lock().unwrap();
+    let before = presets.len();
+    presets.retain(|p| p.id != id);
+    if presets.len() == before {
+        return Err(CompressError::PresetNotFound { id });
+    }
+    store.save(&presets)
+}
+
+// Writes the given presets (all user presets when `ids` is empty) to `path`.
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn export_presets(
+    engine: tauri::State<'_, Engine>,
+    path: String,
+    ids: Option<Vec<String>>,
+) -> Result<usize> {
+    let store = &engine.presets;
+    let ids = ids.unwrap_or_default();
+    let presets: Vec<Preset> = if ids.is_empty() {
+        store.presets.lock().unwrap().clone()
+    } else {
+        ids.iter()
+            .map(|id| {
+                store
+                    .get(id)
+                    .ok_or_else(|| CompressError::PresetNotFound { id: id.clone() })
+            })
+            .collect::<Result<_>>()?
+    };
+    let text = serde_json::to_string_pretty(&PresetFile {
+        presets: presets.clone(),
+    })
+    .map_err(|e| CompressError::internal(e.to_string()))?;
+    std::fs::write(&path, text)?;
+    Ok(presets.len())
+}
+
+// Adds every preset in the file as a user preset. Ids that are already taken get a fresh one,
+// so importing never overwrites anything.
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn import_presets(engine: tauri::State<'_, Engine>, path: String) -> Result<Vec<Preset>> {
+    let store = &engine.presets;
+    if !std::path::Path::new(&path).exists() {
+        return Err(CompressError::input_not_found(&path));
+    }
+    let text = std::fs::read_to_string(&path)?;
+    let incoming = match serde_json::from_str::<PresetImport>(&text) {
+        Ok(PresetImport::File(file)) => file.presets,
+        Ok(PresetImport::List(list)) => list,
+        Ok(PresetImport::Single(preset)) => vec![preset],
+        Err(_) => {
+            return Err(CompressError::invalid(format!(
+                "{} is not a preset file",
+                path
+            )))
+        }
+    };
+    for preset in incoming.iter() {
+        preset.validate()?;
+    }
+    let mut presets = store.presets.lock().unwrap();
+    let mut imported = vec![];
+    for mut preset in incoming {
+        if preset.id.is_empty()
+            || is_builtin_id(&preset.id)
+            || presets.iter().any(|p| p.id == preset.id)
+        {
+            preset.id = PresetStore::unique_id(&presets, &preset.name);
+        }
+        preset.builtin = false;
+        presets.push(preset.clone());
+        imported.push(preset);
+    }
+    store.save(&presets)?;
+    Ok(imported)
+}
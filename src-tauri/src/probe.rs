@@ -0,0 +1,799 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::engine::Tool;
+use crate::error::{CompressError, Result};
+use crate::process::{JobScope, TrackedOutput};
+
+// --- Typed view of `ffprobe -print_format json -show_format -show_streams -show_chapters` ---
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rational {
+    pub num: i64,
+    pub den: i64,
+}
+
+impl Rational {
+    // ffprobe writes rates as "30000/1001"; "0/0" means unknown.
+    pub fn parse(s: &str) -> Option<Rational> {
+        let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+        let num = num.trim().parse::<i64>().ok()?;
+        let den = den.trim().parse::<i64>().ok()?;
+        if num <= 0 || den <= 0 {
+            return None;
+        }
+        Some(Rational { num, den })
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStream {
+    pub index: u32,
+    pub codec: String,
+    pub profile: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    // Clockwise degrees the player has to rotate the picture (0, 90, 180, 270)
+    pub rotation: i32,
+    pub pix_fmt: Option<String>,
+    pub bit_depth: u32,
+    pub color_space: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_range: Option<String>,
+    // HDR10 static metadata, when the container carries it at stream level
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light: Option<ContentLight>,
+    pub dolby_vision_profile: Option<u32>,
+    pub frame_rate: Option<Rational>,
+    pub frame_count: Option<u64>,
+    pub bit_rate: Option<u64>,
+    // Cover art in audio files and thumbnails in MP4s show up as single-frame video streams
+    pub attached_pic: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DynamicRange {
+    Sdr,
+    // PQ (SMPTE ST 2084), what HDR10 and most Dolby Vision base layers use
+    Hdr10,
+    // Hybrid log-gamma, iPhone and broadcast HDR
+    Hlg,
+}
+
+impl VideoStream {
+    pub fn dynamic_range(&self) -> DynamicRange {
+        match self.color_transfer.as_deref() {
+            Some("smpte2084") => DynamicRange::Hdr10,
+            Some("arib-std-b67") => DynamicRange::Hlg,
+            _ => DynamicRange::Sdr,
+        }
+    }
+
+    // Width and height the way players show the picture, and the way ffmpeg's filters see it
+    // after autorotation
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.rotation % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+// SMPTE ST 2086 mastering display, in the units x265 takes: chromaticities in 0.00002 steps,
+// luminance in 0.0001 cd/m².
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MasteringDisplay {
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+    pub white_point: (u32, u32),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+// MaxCLL / MaxFALL in cd/m²
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentLight {
+    pub max_content: u32,
+    pub max_average: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioStream {
+    pub index: u32,
+    pub codec: String,
+    pub channels: u32,
+    pub channel_layout: Option<String>,
+    pub sample_rate: u32,
+    pub bit_rate: Option<u64>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleStream {
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub default: bool,
+    pub forced: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    pub format_name: String,
+    pub duration: f64,
+    // Timestamp of the first packet; MPEG-TS and MP4s with an edit list don't start at 0
+    pub start_time: f64,
+    pub size: u64,
+    pub bit_rate: Option<u64>,
+    pub video: Vec<VideoStream>,
+    pub audio: Vec<AudioStream>,
+    pub subtitles: Vec<SubtitleStream>,
+    pub chapters: Vec<Chapter>,
+    pub tags: HashMap<String, String>,
+}
+
+impl MediaInfo {
+    // The stream that actually carries the picture, skipping embedded cover art.
+    pub fn primary_video(&self) -> Option<&VideoStream> {
+        self.video.iter().find(|v| !v.attached_pic)
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.primary_video().is_some()
+    }
+
+    pub fn has_audio(&self) -> bool {
+        !self.audio.is_empty()
+    }
+
+    pub fn fps(&self) -> Option<f64> {
+        self.primary_video()
+            .and_then(|v| v.frame_rate)
+            .map(|r| r.as_f64())
+    }
+
+    // Container frame count when the muxer stores one, otherwise duration * fps.
+    pub fn total_frames(&self) -> Option<u64> {
+        let video = self.primary_video()?;
+        if let Some(count) = video.frame_count.filter(|c| *c > 0) {
+            return Some(count);
+        }
+        let fps = video.frame_rate?.as_f64();
+        if self.duration > 0.0 {
+            Some((self.duration * fps).round() as u64)
+        } else {
+            None
+        }
+    }
+
+    pub fn audio_bit_rate(&self) -> u64 {
+        self.audio.iter().filter_map(|a| a.bit_rate).sum()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+    #[serde(default)]
+    chapters: Vec<RawChapter>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawDisposition {
+    #[serde(default)]
+    default: u8,
+    #[serde(default)]
+    forced: u8,
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+// ffprobe prints chromaticities and luminances as fractions ("34000/50000")
+#[derive(Deserialize)]
+struct RawSideData {
+    side_data_type: Option<String>,
+    rotation: Option<f64>,
+    red_x: Option<String>,
+    red_y: Option<String>,
+    green_x: Option<String>,
+    green_y: Option<String>,
+    blue_x: Option<String>,
+    blue_y: Option<String>,
+    white_point_x: Option<String>,
+    white_point_y: Option<String>,
+    min_luminance: Option<String>,
+    max_luminance: Option<String>,
+    max_content: Option<u32>,
+    max_average: Option<u32>,
+    dv_profile: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    pix_fmt: Option<String>,
+    bits_per_raw_sample: Option<String>,
+    color_space: Option<String>,
+    color_primaries: Option<String>,
+    color_transfer: Option<String>,
+    color_range: Option<String>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    nb_frames: Option<String>,
+    bit_rate: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_rate: Option<String>,
+    #[serde(default)]
+    disposition: RawDisposition,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+#[derive(Deserialize)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    start_time: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RawChapter {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+fn parse_num<T: std::str::FromStr>(s: &Option<String>) -> Option<T> {
+    s.as_deref().and_then(|v| v.trim().parse::<T>().ok())
+}
+
+// Tag keys differ in case between containers (MP4 "language", MKV "LANGUAGE")
+fn tag(tags: &HashMap<String, String>, key: &str) -> Option<String> {
+    tags.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.clone())
+}
+
+fn bit_depth(raw: &RawStream) -> u32 {
+    if let Some(bits) = parse_num::<u32>(&raw.bits_per_raw_sample).filter(|b| *b > 0) {
+        return bits;
+    }
+    // Only explicit depth suffixes count: "nv12" and "yuv410p" are 8-bit
+    let pix_fmt = raw.pix_fmt.as_deref().unwrap_or("");
+    let ends_with = |suffixes: &[&str]| suffixes.iter().any(|s| pix_fmt.ends_with(s));
+    if ends_with(&["p016", "16le", "16be"]) {
+        16
+    } else if ends_with(&["p12", "12le", "12be"]) {
+        12
+    } else if ends_with(&["p10", "p010", "10le", "10be"]) {
+        10
+    } else {
+        8
+    }
+}
+
+// "34000/50000" rescaled to `unit` steps per 1.0
+fn fraction(value: &Option<String>, unit: i64) -> Option<u32> {
+    let (num, den) = value.as_deref()?.split_once('/')?;
+    let (num, den) = (
+        num.trim().parse::<i64>().ok()?,
+        den.trim().parse::<i64>().ok()?,
+    );
+    if den <= 0 || num < 0 {
+        return None;
+    }
+    u32::try_from(num * unit / den).ok()
+}
+
+fn side_data<'a>(list: &'a [RawSideData], kind: &str) -> Option<&'a RawSideData> {
+    list.iter()
+        .find(|d| d.side_data_type.as_deref() == Some(kind))
+}
+
+fn mastering_display(list: &[RawSideData]) -> Option<MasteringDisplay> {
+    let d = side_data(list, "Mastering display metadata")?;
+    let chroma =
+        |x: &Option<String>, y: &Option<String>| Some((fraction(x, 50000)?, fraction(y, 50000)?));
+    Some(MasteringDisplay {
+        red: chroma(&d.red_x, &d.red_y)?,
+        green: chroma(&d.green_x, &d.green_y)?,
+        blue: chroma(&d.blue_x, &d.blue_y)?,
+        white_point: chroma(&d.white_point_x, &d.white_point_y)?,
+        max_luminance: fraction(&d.max_luminance, 10000)?,
+        min_luminance: fraction(&d.min_luminance, 10000)?,
+    })
+}
+
+fn content_light(list: &[RawSideData]) -> Option<ContentLight> {
+    let d = side_data(list, "Content light level metadata")?;
+    Some(ContentLight {
+        max_content: d.max_content?,
+        max_average: d.max_average?,
+    })
+}
+
+fn rotation(raw: &RawStream) -> i32 {
+    // ffprobe 5+ reports a display matrix rotation (counter-clockwise, negative for clockwise);
+    // older builds only have the "rotate" tag, which is already clockwise.
+    let degrees = raw
+        .side_data_list
+        .iter()
+        .find_map(|d| d.rotation)
+        .map(|r| -r.round() as i32)
+        .or_else(|| tag(&raw.tags, "rotate").and_then(|r| r.parse::<i32>().ok()))
+        .unwrap_or(0);
+    degrees.rem_euclid(360)
+}
+
+fn probe_failed(detail: impl Into<String>) -> CompressError {
+    CompressError::ProbeFailed {
+        detail: detail.into(),
+    }
+}
+
+pub fn parse_ffprobe_json(json: &str) -> Result<MediaInfo> {
+    let raw: RawProbe = serde_json::from_str(json)
+        .map_err(|e| probe_failed(format!("Invalid ffprobe output: {}", e)))?;
+    let format = raw
+        .format
+        .ok_or_else(|| probe_failed("ffprobe did not report a container format"))?;
+
+    let mut info = MediaInfo {
+        format_name: format.format_name.clone().unwrap_or_default(),
+        duration: parse_num(&format.duration).unwrap_or(0.0),
+        start_time: parse_num(&format.start_time).unwrap_or(0.0),
+        size: parse_num(&format.size).unwrap_or(0),
+        bit_rate: parse_num(&format.bit_rate),
+        video: vec![],
+        audio: vec![],
+        subtitles: vec![],
+        chapters: vec![],
+        tags: format.tags,
+    };
+
+    for s in raw.streams.iter() {
+        let codec = s
+            .codec_name
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string());
+        match s.codec_type.as_deref() {
+            Some("video") => {
+                // avg_frame_rate is the real cadence for VFR phone clips; r_frame_rate is the fallback
+                let frame_rate = s
+                    .avg_frame_rate
+                    .as_deref()
+                    .and_then(Rational::parse)
+                    .or_else(|| s.r_frame_rate.as_deref().and_then(Rational::parse));
+                info.video.push(VideoStream {
+                    index: s.index,
+                    codec,
+                    profile: s.profile.clone(),
+                    width: s.width.unwrap_or(0),
+                    height: s.height.unwrap_or(0),
+                    rotation: rotation(s),
+                    pix_fmt: s.pix_fmt.clone(),
+                    bit_depth: bit_depth(s),
+                    color_space: s.color_space.clone(),
+                    color_primaries: s.color_primaries.clone(),
+                    color_transfer: s.color_transfer.clone(),
+                    color_range: s.color_range.clone(),
+                    mastering_display: mastering_display(&s.side_data_list),
+                    content_light: content_light(&s.side_data_list),
+                    dolby_vision_profile: side_data(&s.side_data_list, "DOVI configuration record")
+                        .and_then(|d| d.dv_profile),
+                    frame_rate,
+                    frame_count: parse_num(&s.nb_frames),
+                    bit_rate: parse_num(&s.bit_rate),
+                    attached_pic: s.disposition.attached_pic == 1,
+                });
+            }
+            Some("audio") => info.audio.push(AudioStream {
+                index: s.index,
+                codec,
+                channels: s.channels.unwrap_or(0),
+                channel_layout: s.channel_layout.clone(),
+                sample_rate: parse_num(&s.sample_rate).unwrap_or(0),
+                bit_rate: parse_num(&s.bit_rate),
+                language: tag(&s.tags, "language"),
+                title: tag(&s.tags, "title"),
+            }),
+            Some("subtitle") => info.subtitles.push(SubtitleStream {
+                index: s.index,
+                codec,
+                language: tag(&s.tags, "language"),
+                title: tag(&s.tags, "title"),
+                default: s.disposition.default == 1,
+                forced: s.disposition.forced == 1,
+            }),
+            _ => {}
+        }
+    }
+
+    for c in raw.chapters.iter() {
+        info.chapters.push(Chapter {
+            start: parse_num(&c.start_time).unwrap_or(0.0),
+            end: parse_num(&c.end_time).unwrap_or(0.0),
+            title: tag(&c.tags, "title"),
+        });
+    }
+
+    Ok(info)
+}
+
+pub fn ffprobe_args(input: &str) -> Vec<String> {
+    vec![
+        "-v".to_string(),
+        "error".to_string(),
+        "-print_format".to_string(),
+        "json".to_string(),
+        "-show_format".to_string(),
+        "-show_streams".to_string(),
+        "-show_chapters".to_string(),
+        input.to_string(),
+    ]
+}
+
+// Runs ffprobe as one of the job's processes, so cancelling the job kills it too
+async fn run(scope: &JobScope<'_>, args: Vec<String>) -> Result<TrackedOutput> {
+    let output = scope
+        .output(Tool::Ffprobe, args)
+        .await
+        .map_err(|e| match e {
+            CompressError::Cancelled => e,
+            e => probe_failed(format!("Failed to run ffprobe: {}", e)),
+        })?;
+    if !output.success() {
+        return Err(probe_failed(String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output)
+}
+
+pub async fn run_ffprobe(scope: &JobScope<'_>, input: &str) -> Result<String> {
+    if !Path::new(input).exists() {
+        return Err(CompressError::input_not_found(input));
+    }
+    let output = run(scope, ffprobe_args(input)).await?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub async fn probe(scope: &JobScope<'_>, input: &str) -> Result<MediaInfo> {
+    parse_ffprobe_json(&run_ffprobe(scope, input).await?)
+}
+
+// Packet timestamps of the keyframes of stream `index`, read from packet flags so nothing is decoded.
+// They are on the container's clock, which starts at the file's start_time.
+pub async fn keyframes(scope: &JobScope<'_>, input: &str, index: u32) -> Result<Vec<f64>> {
+    let args = vec![
+        "-v".to_string(),
+        "error".to_string(),
+        "-select_streams".to_string(),
+        index.to_string(),
+        "-show_entries".to_string(),
+        "packet=pts_time,flags".to_string(),
+        "-of".to_string(),
+        "csv=p=0".to_string(),
+        input.to_string(),
+    ];
+    let output = run(scope, args).await?;
+    // "12.345000,K__"
+    let mut times: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(','))
+        .filter(|(_, flags)| flags.starts_with('K'))
+        .filter_map(|(pts, _)| pts.trim().parse().ok())
+        .collect();
+    times.sort_by(|a, b| a.total_cmp(b));
+    times.dedup();
+    Ok(times)
+}
+
+#[derive(Deserialize)]
+struct RawFrames {
+    #[serde(default)]
+    frames: Vec<RawFrame>,
+}
+
+#[derive(Deserialize)]
+struct RawFrame {
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+// HDR10 metadata from the first frame of video stream `index`. HEVC/AV1 carry it in the bitstream
+// (SEI/OBU), where the stream-level probe doesn't look.
+pub async fn hdr_metadata(
+    scope: &JobScope<'_>,
+    input: &str,
+    index: u32,
+) -> Result<(Option<MasteringDisplay>, Option<ContentLight>)> {
+    let args = vec![
+        "-v".to_string(),
+        "error".to_string(),
+        "-select_streams".to_string(),
+        index.to_string(),
+        "-read_intervals".to_string(),
+        "%+#1".to_string(),
+        "-show_entries".to_string(),
+        "frame=side_data_list".to_string(),
+        "-print_format".to_string(),
+        "json".to_string(),
+        input.to_string(),
+    ];
+    let output = run(scope, args).await?;
+    let raw: RawFrames = serde_json::from_slice(&output.stdout)
+        .map_err(|e| probe_failed(format!("Invalid ffprobe output: {}", e)))?;
+    let list = raw
+        .frames
+        .into_iter()
+        .next()
+        .map(|f| f.side_data_list)
+        .unwrap_or_default();
+    Ok((mastering_display(&list), content_light(&list)))
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub async fn probe_media(
+    engine: tauri::State<'_, crate::engine::Engine>,
+    input: String,
+) -> Result<MediaInfo> {
+    let scope = JobScope::new(&engine, None);
+    probe(&scope, &input).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // iPhone HLG clip, shot upright: Dolby Vision 8.4 over HLG, rotation in the display matrix
+    const IPHONE_HLG: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_name": "hevc", "profile": "Main 10", "codec_type": "video", "width": 1920, "height": 1080,
+          "pix_fmt": "yuv420p10le", "color_range": "tv", "color_space": "bt2020nc", "color_transfer": "arib-std-b67",
+          "color_primaries": "bt2020", "r_frame_rate": "30/1", "avg_frame_rate": "30/1", "bits_per_raw_sample": "10",
+          "bit_rate": "8234561", "nb_frames": "184",
+          "disposition": { "default": 1, "attached_pic": 0 },
+          "tags": { "language": "und", "handler_name": "Core Media Video" },
+          "side_data_list": [
+            { "side_data_type": "DOVI configuration record", "dv_version_major": 1, "dv_version_minor": 0, "dv_profile": 8, "dv_level": 4 },
+            { "side_data_type": "Display Matrix", "displaymatrix": "\n00000000:            0       65536           0\n", "rotation": -90 }
+          ] },
+        { "index": 1, "codec_name": "aac", "profile": "LC", "codec_type": "audio", "sample_rate": "44100", "channels": 2,
+          "channel_layout": "stereo", "bit_rate": "160098", "tags": { "language": "und", "handler_name": "Core Media Audio" } }
+      ],
+      "format": { "filename": "IMG_0042.MOV", "nb_streams": 2, "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+        "start_time": "0.000000", "duration": "6.133333", "size": "6440217", "bit_rate": "8400284",
+        "tags": { "major_brand": "qt  ", "com.apple.quicktime.make": "Apple" } }
+    }"#;
+
+    // MP3 with an embedded JPEG cover
+    const MP3_COVER: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_name": "mp3", "codec_type": "audio", "sample_fmt": "fltp", "sample_rate": "44100", "channels": 2,
+          "channel_layout": "stereo", "bit_rate": "320000", "disposition": { "default": 0, "attached_pic": 0 } },
+        { "index": 1, "codec_name": "mjpeg", "codec_type": "video", "width": 600, "height": 600, "pix_fmt": "yuvj420p",
+          "r_frame_rate": "90000/1", "avg_frame_rate": "0/0", "bits_per_raw_sample": "8",
+          "disposition": { "default": 0, "attached_pic": 1 }, "tags": { "comment": "Cover (front)" } }
+      ],
+      "format": { "format_name": "mp3", "start_time": "0.025057", "duration": "212.950204", "size": "8531204", "bit_rate": "320495",
+        "tags": { "title": "Song", "artist": "Band" } }
+    }"#;
+
+    // Raw H.264 elementary stream: no duration, no frame count, no average rate
+    const RAW_H264: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_name": "h264", "profile": "High", "codec_type": "video", "width": 1280, "height": 720,
+          "pix_fmt": "yuv420p", "r_frame_rate": "25/1", "avg_frame_rate": "0/0", "bits_per_raw_sample": "8" }
+      ],
+      "format": { "format_name": "h264", "size": "1048576" }
+    }"#;
+
+    // MKV remux of a UHD disc: HDR10 metadata at stream level, upper-case MKV tags, PGS subtitles
+    const MKV_HDR10: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_name": "hevc", "profile": "Main 10", "codec_type": "video", "width": 3840, "height": 2160,
+          "pix_fmt": "yuv420p10le", "color_range": "tv", "color_space": "bt2020nc", "color_transfer": "smpte2084",
+          "color_primaries": "bt2020", "r_frame_rate": "24000/1001", "avg_frame_rate": "24000/1001",
+          "disposition": { "default": 1 }, "tags": { "BPS": "58235224", "DURATION": "00:00:10.010000000" },
+          "side_data_list": [
+            { "side_data_type": "Mastering display metadata", "red_x": "34000/50000", "red_y": "16000/50000",
+              "green_x": "13250/50000", "green_y": "34500/50000", "blue_x": "7500/50000", "blue_y": "3000/50000",
+              "white_point_x": "15635/50000", "white_point_y": "16450/50000", "min_luminance": "1/10000", "max_luminance": "40000000/10000" },
+            { "side_data_type": "Content light level metadata", "max_content": 1015, "max_average": 370 }
+          ] },
+        { "index": 1, "codec_name": "truehd", "codec_type": "audio", "sample_rate": "48000", "channels": 8, "channel_layout": "7.1",
+          "tags": { "LANGUAGE": "eng", "title": "TrueHD 7.1" } },
+        { "index": 2, "codec_name": "hdmv_pgs_subtitle", "codec_type": "subtitle",
+          "disposition": { "default": 0, "forced": 1 }, "tags": { "LANGUAGE": "ger", "title": "Forced" } }
+      ],
+      "format": { "format_name": "matroska,webm", "start_time": "0.000000", "duration": "10.010000", "size": "72874515" },
+      "chapters": [
+        { "id": 1, "time_base": "1/1000000000", "start_time": "0.000000", "end_time": "5.005000", "tags": { "title": "Chapter 01" } },
+        { "id": 2, "time_base": "1/1000000000", "start_time": "5.005000", "end_time": "10.010000" }
+      ]
+    }"#;
+
+    #[test]
+    fn rotation_side_data_turns_the_display_size() {
+        let info = parse_ffprobe_json(IPHONE_HLG).unwrap();
+        let video = info.primary_video().unwrap();
+
+        assert_eq!(video.rotation, 90);
+        assert_eq!(video.display_size(), (1080, 1920));
+        assert_eq!(
+            (
+                video.bit_depth,
+                video.dynamic_range(),
+                video.dolby_vision_profile
+            ),
+            (10, DynamicRange::Hlg, Some(8))
+        );
+        assert_eq!((info.fps(), info.total_frames()), (Some(30.0), Some(184)));
+        assert_eq!(info.audio[0].language.as_deref(), Some("und"));
+    }
+
+    #[test]
+    fn rotate_tag_of_older_ffprobe_is_already_clockwise() {
+        let json = RAW_H264.replace(
+            r#""bits_per_raw_sample": "8""#,
+            r#""bits_per_raw_sample": "8", "tags": { "rotate": "270" }"#,
+        );
+
+        let info = parse_ffprobe_json(&json).unwrap();
+
+        assert_eq!(info.video[0].rotation, 270);
+        assert_eq!(info.video[0].display_size(), (720, 1280));
+    }
+
+    #[test]
+    fn attached_pic_is_not_the_picture() {
+        let info = parse_ffprobe_json(MP3_COVER).unwrap();
+
+        assert!(info.video[0].attached_pic);
+        assert!(info.primary_video().is_none() && !info.has_video() && info.has_audio());
+        assert_eq!((info.fps(), info.total_frames()), (None, None));
+        assert_eq!((info.start_time, info.audio_bit_rate()), (0.025057, 320000));
+        assert_eq!(tag(&info.tags, "TITLE").as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn missing_duration_is_zero_and_frames_are_unknown() {
+        let info = parse_ffprobe_json(RAW_H264).unwrap();
+
+        assert_eq!(
+            (info.duration, info.start_time, info.bit_rate),
+            (0.0, 0.0, None)
+        );
+        // avg_frame_rate "0/0" falls back to r_frame_rate
+        assert_eq!(info.fps(), Some(25.0));
+        assert_eq!(info.total_frames(), None);
+    }
+
+    #[test]
+    fn hdr10_side_data_and_mkv_tags() {
+        let info = parse_ffprobe_json(MKV_HDR10).unwrap();
+        let video = info.primary_video().unwrap();
+
+        // No bits_per_raw_sample, the depth comes from the pixel format
+        assert_eq!(
+            (video.bit_depth, video.dynamic_range()),
+            (10, DynamicRange::Hdr10)
+        );
+        assert_eq!(
+            video.mastering_display,
+            Some(MasteringDisplay {
+                red: (34000, 16000),
+                green: (13250, 34500),
+                blue: (7500, 3000),
+                white_point: (15635, 16450),
+                max_luminance: 40000000,
+                min_luminance: 1,
+            })
+        );
+        assert_eq!(
+            video.content_light,
+            Some(ContentLight {
+                max_content: 1015,
+                max_average: 370
+            })
+        );
+        assert_eq!(info.total_frames(), Some(240));
+        // MKV writes its tag keys in upper case
+        assert_eq!(
+            (
+                info.audio[0].language.as_deref(),
+                info.audio[0].title.as_deref()
+            ),
+            (Some("eng"), Some("TrueHD 7.1"))
+        );
+        let subs = &info.subtitles[0];
+        assert_eq!(
+            (subs.language.as_deref(), subs.default, subs.forced),
+            (Some("ger"), false, true)
+        );
+        assert_eq!(info.chapters.len(), 2);
+        assert_eq!(
+            (
+                info.chapters[1].start,
+                info.chapters[1].end,
+                info.chapters[1].title.as_deref()
+            ),
+            (5.005, 10.01, None)
+        );
+    }
+
+    #[test]
+    fn bit_depth_falls_back_to_the_pixel_format() {
+        let depth = |bits: Option<&str>, pix_fmt: Option<&str>| {
+            let raw = RawStream {
+                bits_per_raw_sample: bits.map(String::from),
+                pix_fmt: pix_fmt.map(String::from),
+                ..serde_json::from_str(r#"{ "index": 0 }"#).unwrap()
+            };
+            bit_depth(&raw)
+        };
+
+        assert_eq!(depth(Some("10"), Some("yuv420p")), 10);
+        // Some muxers write 0 for unknown
+        assert_eq!(depth(Some("0"), Some("yuv422p12le")), 12);
+        assert_eq!(depth(None, Some("p010le")), 10);
+        assert_eq!(depth(None, Some("gbrp12be")), 12);
+        assert_eq!(depth(None, Some("p016le")), 16);
+        assert_eq!(depth(None, None), 8);
+        // The digits here are chroma layouts, not bit depths
+        assert_eq!(depth(None, Some("nv12")), 8);
+        assert_eq!(depth(None, Some("yuv410p")), 8);
+    }
+
+    #[test]
+    fn garbage_is_a_probe_failure() {
+        assert!(matches!(
+            parse_ffprobe_json("not json"),
+            Err(CompressError::ProbeFailed { .. })
+        ));
+        assert!(matches!(
+            parse_ffprobe_json(r#"{ "streams": [] }"#),
+            Err(CompressError::ProbeFailed { .. })
+        ));
+    }
+}
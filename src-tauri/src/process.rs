@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::mpsc::Receiver;
+
+use crate::engine::{ChildProcess, Engine, ProcessEvent, Tool};
+use crate::error::{CompressError, Result};
+
+// Everything a single job has started: the ffmpeg processes, the realesrgan lanes and the
+// scratch files they write. Cancelling a job only touches what is listed here.
+#[derive(Default)]
+struct JobProcesses {
+    tools: HashMap<u32, Box<dyn ChildProcess>>,
+    temp_paths: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    cancelled: bool,
+}
+
+#[derive(Default)]
+pub struct ProcessRegistry {
+    jobs: Mutex<HashMap<String, JobProcesses>>,
+    next_direct: AtomicU64,
+}
+
+fn remove_path(path: &Path) {
+    if path.is_dir() {
+        let _ = std::fs::remove_dir_all(path);
+    } else {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+impl ProcessRegistry {
+    fn track_tool(&self, job: &str, pid: u32, handle: Box<dyn ChildProcess>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.entry(job.to_string()).or_default();
+        if entry.cancelled {
+            // Cancelled between spawn and registration
+            handle.kill();
+        } else {
+            entry.tools.insert(pid, handle);
+        }
+    }
+
+    fn release_tool(&self, job: &str, pid: u32) {
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(job) {
+            entry.tools.remove(&pid);
+        }
+    }
+
+    fn track_temp(&self, job: &str, path: PathBuf) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entry(job.to_string())
+            .or_default()
+            .temp_paths
+            .push(path);
+    }
+
+    fn track_output(&self, job: &str, path: PathBuf) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entry(job.to_string())
+            .or_default()
+            .outputs
+            .push(path);
+    }
+
+    pub fn is_cancelled(&self, job: &str) -> bool {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(job)
+            .map(|e| e.cancelled)
+            .unwrap_or(false)
+    }
+
+    // Kills every process of the job, then deletes its scratch files and half-written outputs.
+    // A job that has not spawned anything yet is only flagged, so its first spawn is refused.
+    // Ids without a live scope (unknown or already finished) are left alone. Returns whether any
+    // process was killed.
+    pub fn cancel(&self, job: &str) -> bool {
+        let (tools, paths) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let entry = match jobs.get_mut(job) {
+                Some(entry) => entry,
+                None => return false,
+            };
+            entry.cancelled = true;
+            let mut paths: Vec<PathBuf> = entry.temp_paths.drain(..).collect();
+            paths.append(&mut entry.outputs);
+            (entry.tools.drain().collect::<Vec<_>>(), paths)
+        };
+        eprintln!("🛑 CANCEL: Job {} ({} processes)", job, tools.len());
+        let killed = !tools.is_empty();
+        for (_, handle) in tools {
+            handle.kill();
+        }
+        for path in paths {
+            remove_path(&path);
+        }
+        killed
+    }
+
+    // Kills the job's processes without cancelling it, for when one of several parallel tools failed
+    fn kill_tools(&self, job: &str) {
+        let tools: Vec<_> = match self.jobs.lock().unwrap().get_mut(job) {
+            Some(entry) => entry.tools.drain().collect(),
+            None => return,
+        };
+        for (_, handle) in tools {
+            handle.kill();
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        let ids: Vec<String> = self.jobs.lock().unwrap().keys().cloned().collect();
+        for id in ids {
+            self.cancel(&id);
+        }
+    }
+
+    fn finish(&self, job: &str) {
+        let entry = self.jobs.lock().unwrap().remove(job);
+        if let Some(entry) = entry {
+            for path in entry.temp_paths {
+                remove_path(&path);
+            }
+        }
+    }
+}
+
+pub struct TrackedOutput {
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl TrackedOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+// Drains a tool's events into one buffer per stream, like `Command::output()`.
+pub async fn collect_output(mut rx: Receiver<ProcessEvent>) -> TrackedOutput {
+    let mut out = TrackedOutput {
+        code: None,
+        stdout: vec![],
+        stderr: vec![],
+    };
+    while let Some(event) = rx.recv().await {
+        match event {
+            ProcessEvent::Stdout(line) => {
+                out.stdout.extend(line);
+                out.stdout.push(b'\n');
+            }
+            ProcessEvent::Stderr(line) => {
+                out.stderr.extend(line);
+                out.stderr.push(b'\n');
+            }
+            ProcessEvent::Exited(code) => out.code = code,
+        }
+    }
+    out
+}
+
+// Handle a pipeline holds while it runs. Every process started through it is registered under the
+// job id so `cancel_job` can find it; dropping the scope unregisters the job and removes its temp files.
+pub struct JobScope<'a> {
+    pub engine: &'a Engine,
+    pub id: String,
+}
+
+impl<'a> JobScope<'a> {
+    // Queue jobs pass their own id; direct command calls get a private one.
+    pub fn new(engine: &'a Engine, job_id: Option<String>) -> Self {
+        let registry = &engine.registry;
+        let id = job_id.unwrap_or_else(|| {
+            format!(
+                "direct-{}",
+                registry.next_direct.fetch_add(1, Ordering::Relaxed)
+            )
+        });
+        registry.jobs.lock().unwrap().entry(id.clone()).or_default();
+        JobScope { engine, id }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.engine.registry.is_cancelled(&self.id)
+    }
+
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(CompressError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn spawn(&self, tool: Tool, args: Vec<String>) -> Result<(Receiver<ProcessEvent>, u32)> {
+        self.check_cancelled()?;
+        let spawned = self.engine.runner.spawn(tool, args)?;
+        self.engine
+            .registry
+            .track_tool(&self.id, spawned.pid, spawned.handle);
+        Ok((spawned.events, spawned.pid))
+    }
+
+    // Tracked replacement for `Command::output()`.
+    pub async fn output(&self, tool: Tool, args: Vec<String>) -> Result<TrackedOutput> {
+        let (rx, pid) = self.spawn(tool, args)?;
+        let out = collect_output(rx).await;
+        self.release(pid);
+        self.check_cancelled()?;
+        Ok(out)
+    }
+
+    // Called once a spawned tool has terminated.
+    pub fn release(&self, pid: u32) {
+        self.engine.registry.release_tool(&self.id, pid);
+    }
+
+    pub fn kill_running(&self) {
+        self.engine.registry.kill_tools(&self.id);
+    }
+
+    pub fn track_temp(&self, path: PathBuf) {
+        self.engine.registry.track_temp(&self.id, path);
+    }
+
+    pub fn track_output(&self, path: PathBuf) {
+        self.engine.registry.track_output(&self.id, path);
+    }
+
+    // Private directory for files the job's tools leave behind (two-pass logs, quality samples),
+    // so concurrent jobs never share a name. Removed with the job's other temp files.
+    pub fn work_dir(&self) -> Result<PathBuf> {
+        let dir = self
+            .engine
+            .temp_dir
+            .join(format!("compress-io-{}", self.id));
+        std::fs::create_dir_all(&dir)?;
+        self.track_temp(dir.clone());
+        Ok(dir)
+    }
+}
+
+impl Drop for JobScope<'_> {
+    fn drop(&mut self) {
+        self.engine.registry.finish(&self.id);
+    }
+}
+
+#[cfg(feature = "app")]
+#[tauri::command]
+pub fn stop_job(engine: tauri::State<'_, Engine>, job_id: Option<String>) -> bool {
+    match job_id {
+        Some(id) => engine.registry.cancel(&id),
+        None => {
+            eprintln!("🛑 FORCE STOP: Killing all media processes started by this app...");
+            engine.registry.cancel_all();
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{engine_with, Script, ScriptedRunner, TempDir};
+
+    #[test]
+    fn cancel_kills_running_tools_and_removes_outputs() {
+        let dir = TempDir::new("cancel");
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+        let kills = runner.kills();
+        let engine = engine_with(runner, &dir.0);
+        let output = dir.file("half-written.mp4", b"partial");
+
+        let scope = JobScope::new(&engine, Some("3".to_string()));
+        scope.spawn(Tool::Ffmpeg, vec![]).unwrap();
+        scope.track_output(output.clone().into());
+
+        assert!(engine.registry.cancel("3"));
+        assert_eq!(kills.load(Ordering::SeqCst), 1);
+        assert!(scope.is_cancelled());
+        assert!(!Path::new(&output).exists());
+        assert_eq!(scope.check_cancelled(), Err(CompressError::Cancelled));
+    }
+
+    #[test]
+    fn cancelling_an_unknown_job_leaves_nothing_behind() {
+        let dir = TempDir::new("cancel-unknown");
+        let engine = engine_with(ScriptedRunner::new(|_, _| Script::ok()), &dir.0);
+
+        // Finished, or never started
+        drop(JobScope::new(&engine, Some("4".to_string())));
+        assert!(!engine.registry.cancel("4"));
+        assert!(!engine.registry.cancel("5"));
+
+        assert!(engine.registry.jobs.lock().unwrap().is_empty());
+        let scope = JobScope::new(&engine, Some("4".to_string()));
+        assert!(scope.spawn(Tool::Ffmpeg, vec![]).is_ok());
+    }
+}
@@ -0,0 +1,480 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::task::Poll;
+use tokio::sync::mpsc::Receiver;
+
+use crate::engine::{ProcessEvent, Tool};
+use crate::error::{CompressError, Result, STDERR_TAIL_LINES};
+use crate::process::JobScope;
+
+// Payload of the `job-progress` event, built from ffmpeg's `-progress pipe:1` key=value blocks.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressUpdate {
+    pub job_id: String,
+    pub pass: u32,
+    pub total_passes: u32,
+    pub frames_done: u64,
+    pub total_frames: Option<u64>,
+    // Seconds of output written so far
+    pub out_time: f64,
+    // Overall percent across all passes, 0-100
+    pub percent: f64,
+    pub fps: f64,
+    // Encoding speed as a multiple of realtime (ffmpeg's "speed=2.5x")
+    pub speed: f64,
+    pub output_size: u64,
+    pub eta_seconds: Option<f64>,
+    pub finished: bool,
+}
+
+pub struct ProgressTracker {
+    job_id: String,
+    duration: f64,
+    total_frames: Option<u64>,
+    pass: u32,
+    total_passes: u32,
+    current: ProgressUpdate,
+}
+
+fn parse_out_time(value: &str) -> Option<f64> {
+    // "00:01:02.500000"
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let h = parts[0].parse::<f64>().ok()?;
+    let m = parts[1].parse::<f64>().ok()?;
+    let s = parts[2].parse::<f64>().ok()?;
+    Some(h * 3600.0 + m * 60.0 + s)
+}
+
+impl ProgressTracker {
+    pub fn new(job_id: &str, duration: f64, total_frames: Option<u64>) -> Self {
+        ProgressTracker {
+            job_id: job_id.to_string(),
+            duration,
+            total_frames,
+            pass: 1,
+            total_passes: 1,
+            current: ProgressUpdate::default(),
+        }
+    }
+
+    pub fn with_pass(mut self, pass: u32, total_passes: u32) -> Self {
+        self.pass = pass;
+        self.total_passes = total_passes.max(1);
+        self
+    }
+
+    // Feeds one stdout line. Returns an update when ffmpeg closes a block with `progress=continue|end`.
+    pub fn feed_line(&mut self, line: &str) -> Option<ProgressUpdate> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key.trim() {
+            "frame" => self.current.frames_done = value.parse().unwrap_or(self.current.frames_done),
+            "fps" => self.current.fps = value.parse().unwrap_or(0.0),
+            "total_size" => {
+                self.current.output_size = value.parse().unwrap_or(self.current.output_size)
+            }
+            // Despite the name, out_time_ms is in microseconds as well
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    self.current.out_time = (us.max(0) as f64) / 1_000_000.0;
+                }
+            }
+            "out_time" => {
+                if let Some(secs) = parse_out_time(value) {
+                    self.current.out_time = secs;
+                }
+            }
+            "speed" => {
+                self.current.speed = value.trim_end_matches('x').trim().parse().unwrap_or(0.0)
+            }
+            "progress" => return Some(self.snapshot(value == "end")),
+            _ => {}
+        }
+        None
+    }
+
+    fn snapshot(&mut self, finished: bool) -> ProgressUpdate {
+        let fraction = if finished {
+            1.0
+        } else if self.duration > 0.0 {
+            self.current.out_time / self.duration
+        } else if let Some(total) = self.total_frames.filter(|t| *t > 0) {
+            self.current.frames_done as f64 / total as f64
+        } else {
+            0.0
+        };
+        let fraction = fraction.clamp(0.0, 1.0);
+        let overall = ((self.pass - 1) as f64 + fraction) / self.total_passes as f64;
+
+        let eta_seconds = if finished && self.pass == self.total_passes {
+            Some(0.0)
+        } else if self.speed_known() && self.duration > 0.0 {
+            let left_in_pass = (self.duration - self.current.out_time).max(0.0);
+            let later_passes = (self.total_passes - self.pass) as f64 * self.duration;
+            Some((left_in_pass + later_passes) / self.current.speed)
+        } else {
+            None
+        };
+
+        let mut update = self.current.clone();
+        update.job_id = self.job_id.clone();
+        update.pass = self.pass;
+        update.total_passes = self.total_passes;
+        update.total_frames = self.total_frames;
+        update.percent = (overall * 10000.0).round() / 100.0;
+        update.eta_seconds = eta_seconds;
+        update.finished = finished;
+        update
+    }
+
+    // Overall update for several processes encoding parts of this tracker's duration at once.
+    // Times, frames and sizes add up; so do fps and speed, since the parts run side by side.
+    pub fn combine(&mut self, parts: &[ProgressUpdate]) -> ProgressUpdate {
+        self.current.out_time = parts.iter().map(|p| p.out_time).sum();
+        self.current.frames_done = parts.iter().map(|p| p.frames_done).sum();
+        self.current.output_size = parts.iter().map(|p| p.output_size).sum();
+        self.current.fps = parts.iter().filter(|p| !p.finished).map(|p| p.fps).sum();
+        self.current.speed = parts.iter().filter(|p| !p.finished).map(|p| p.speed).sum();
+        self.snapshot(parts.iter().all(|p| p.finished))
+    }
+
+    fn speed_known(&self) -> bool {
+        self.current.speed > 0.0
+    }
+}
+
+// Runs ffmpeg with args that include `-progress pipe:1`, emitting `job-progress` updates and
+// forwarding stderr to `ffmpeg-progress` for the log view. Failures carry the last stderr lines.
+pub async fn run_ffmpeg(
+    scope: &JobScope<'_>,
+    args: Vec<String>,
+    mut tracker: ProgressTracker,
+    log_prefix: &str,
+) -> Result<()> {
+    let (mut rx, pid) = scope.spawn(Tool::Ffmpeg, args)?;
+    let mut stderr_tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut exit_code = None;
+    while let Some(event) = rx.recv().await {
+        match event {
+            ProcessEvent::Stdout(line_bytes) => {
+                let line = String::from_utf8_lossy(&line_bytes);
+                if let Some(update) = tracker.feed_line(&line) {
+                    scope.engine.emit("job-progress", update);
+                }
+            }
+            ProcessEvent::Stderr(line_bytes) => {
+                let line = String::from_utf8_lossy(&line_bytes);
+                if stderr_tail.len() == STDERR_TAIL_LINES {
+                    stderr_tail.pop_front();
+                }
+                stderr_tail.push_back(format!("{}{}", log_prefix, line));
+                scope
+                    .engine
+                    .emit("ffmpeg-progress", format!("{}{}", log_prefix, line));
+            }
+            ProcessEvent::Exited(code) => exit_code = code,
+        }
+    }
+    scope.release(pid);
+    scope.check_cancelled()?;
+    match exit_code {
+        Some(0) => Ok(()),
+        code => Err(CompressError::from_ffmpeg(code, stderr_tail.into())),
+    }
+}
+
+struct Running {
+    part: usize,
+    rx: Receiver<ProcessEvent>,
+    pid: u32,
+    tracker: ProgressTracker,
+    stderr_tail: VecDeque<String>,
+    exit_code: Option<i32>,
+}
+
+// Runs several ffmpeg processes, at most `workers` at a time, each with its own tracker over its
+// part of the job. `job-progress` gets the combined view from `total`; stderr lines are tagged
+// with `label` and the part number. The first failure kills the rest and is returned.
+pub async fn run_ffmpeg_parallel(
+    scope: &JobScope<'_>,
+    runs: Vec<(Vec<String>, ProgressTracker)>,
+    workers: usize,
+    mut total: ProgressTracker,
+    label: &str,
+) -> Result<()> {
+    let mut parts = vec![ProgressUpdate::default(); runs.len()];
+    let mut pending = runs.into_iter().enumerate();
+    let mut running: Vec<Running> = vec![];
+    // Where polling starts, rotated so one chatty process can't starve the others' pipes
+    let mut first = 0;
+    loop {
+        while running.len() < workers.max(1) {
+            let Some((part, (args, tracker))) = pending.next() else {
+                break;
+            };
+            let spawned = scope.spawn(Tool::Ffmpeg, args);
+            let (rx, pid) = match spawned {
+                Ok(spawned) => spawned,
+                Err(e) => {
+                    scope.kill_running();
+                    return Err(e);
+                }
+            };
+            running.push(Running {
+                part,
+                rx,
+                pid,
+                tracker,
+                stderr_tail: VecDeque::with_capacity(STDERR_TAIL_LINES),
+                exit_code: None,
+            });
+        }
+        if running.is_empty() {
+            return Ok(());
+        }
+
+        first = (first + 1) % running.len();
+        let (i, event) = poll_fn(|cx| {
+            let count = running.len();
+            for offset in 0..count {
+                let i = (first + offset) % count;
+                if let Poll::Ready(event) = running[i].rx.poll_recv(cx) {
+                    return Poll::Ready((i, event));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        let run = &mut running[i];
+        match event {
+            Some(ProcessEvent::Stdout(line_bytes)) => {
+                let line = String::from_utf8_lossy(&line_bytes);
+                if let Some(mut update) = run.tracker.feed_line(&line) {
+                    // The job is only finished once every part is
+                    update.finished = false;
+                    parts[run.part] = update;
+                    scope.engine.emit("job-progress", total.combine(&parts));
+                }
+            }
+            Some(ProcessEvent::Stderr(line_bytes)) => {
+                let line = format!(
+                    "[{} {}] {}",
+                    label,
+                    run.part + 1,
+                    String::from_utf8_lossy(&line_bytes)
+                );
+                if run.stderr_tail.len() == STDERR_TAIL_LINES {
+                    run.stderr_tail.pop_front();
+                }
+                run.stderr_tail.push_back(line.clone());
+                scope.engine.emit("ffmpeg-progress", line);
+            }
+            Some(ProcessEvent::Exited(code)) => run.exit_code = code,
+            // Channel closed: the process is gone and all its output has been read
+            None => {
+                let done = running.swap_remove(i);
+                scope.release(done.pid);
+                if let Err(e) = scope.check_cancelled() {
+                    scope.kill_running();
+                    return Err(e);
+                }
+                if done.exit_code != Some(0) {
+                    scope.kill_running();
+                    return Err(CompressError::from_ffmpeg(
+                        done.exit_code,
+                        done.stderr_tail.into(),
+                    ));
+                }
+                parts[done.part] = ProgressUpdate {
+                    out_time: done.tracker.duration,
+                    finished: true,
+                    ..parts[done.part].clone()
+                };
+                scope.engine.emit("job-progress", total.combine(&parts));
+            }
+        }
+    }
+}
+
+pub fn progress_args() -> Vec<String> {
+    vec!["-progress".to_string(), "pipe:1".to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds a `-progress` block line by line, returning the update its `progress=` line closes it with
+    fn feed(tracker: &mut ProgressTracker, block: &str) -> ProgressUpdate {
+        let updates: Vec<ProgressUpdate> = block
+            .lines()
+            .filter_map(|line| tracker.feed_line(line))
+            .collect();
+        assert_eq!(updates.len(), 1);
+        updates[0].clone()
+    }
+
+    #[test]
+    fn parse_out_time_reads_hours_minutes_seconds() {
+        assert_eq!(parse_out_time("00:01:02.500000"), Some(62.5));
+        assert_eq!(parse_out_time("01:00:00.000000\n"), Some(3600.0));
+        assert_eq!(parse_out_time("N/A"), None);
+        assert_eq!(parse_out_time("62.5"), None);
+    }
+
+    #[test]
+    fn blocks_before_the_first_frame_report_nothing_yet() {
+        let mut tracker = ProgressTracker::new("1", 10.0, Some(300));
+
+        let update = feed(&mut tracker, "frame=0\nfps=0.00\nbitrate=N/A\ntotal_size=N/A\nout_time_us=N/A\nout_time_ms=N/A\nout_time=N/A\nspeed=N/A\nprogress=continue");
+
+        assert_eq!(
+            (
+                update.out_time,
+                update.percent,
+                update.speed,
+                update.output_size
+            ),
+            (0.0, 0.0, 0.0, 0)
+        );
+        assert_eq!((update.eta_seconds, update.finished), (None, false));
+    }
+
+    #[test]
+    fn block_fills_in_time_size_speed_and_eta() {
+        let mut tracker = ProgressTracker::new("1", 10.0, Some(300));
+
+        let update = feed(&mut tracker, "frame=75\nfps=49.87\ntotal_size=262192\nout_time_us=2500000\nout_time_ms=2500000\nout_time=00:00:02.500000\nspeed=1.25x\nprogress=continue");
+
+        assert_eq!(update.job_id, "1");
+        assert_eq!(
+            (update.frames_done, update.total_frames, update.output_size),
+            (75, Some(300), 262192)
+        );
+        assert_eq!(
+            (update.out_time, update.percent, update.fps, update.speed),
+            (2.5, 25.0, 49.87, 1.25)
+        );
+        assert_eq!(update.eta_seconds, Some(6.0));
+    }
+
+    #[test]
+    fn speed_na_after_a_known_speed_drops_the_eta() {
+        let mut tracker = ProgressTracker::new("1", 10.0, None);
+        feed(
+            &mut tracker,
+            "out_time_us=2000000\nspeed=2x\nprogress=continue",
+        );
+
+        // A stalled pipe or a stream copy prints N/A; the time read so far stays
+        let update = feed(
+            &mut tracker,
+            "out_time_us=N/A\nout_time=N/A\nspeed=N/A\nprogress=continue",
+        );
+
+        assert_eq!(
+            (update.out_time, update.speed, update.eta_seconds),
+            (2.0, 0.0, None)
+        );
+    }
+
+    #[test]
+    fn percent_falls_back_to_frames_without_a_duration() {
+        let mut tracker = ProgressTracker::new("1", 0.0, Some(200));
+
+        let update = feed(
+            &mut tracker,
+            "frame=50\nout_time_us=-9223372036854775807\nspeed=2x\nprogress=continue",
+        );
+
+        assert_eq!(
+            (update.out_time, update.percent, update.eta_seconds),
+            (0.0, 25.0, None)
+        );
+    }
+
+    #[test]
+    fn progress_end_finishes_the_pass() {
+        let mut last = ProgressTracker::new("1", 10.0, None).with_pass(2, 2);
+        let mut first = ProgressTracker::new("1", 10.0, None).with_pass(1, 2);
+
+        let done = feed(&mut last, "out_time_us=9960000\nspeed=2.5x\nprogress=end");
+        let halfway = feed(&mut first, "out_time_us=9960000\nspeed=2.5x\nprogress=end");
+
+        assert_eq!(
+            (done.finished, done.percent, done.eta_seconds),
+            (true, 100.0, Some(0.0))
+        );
+        // The second pass is still to come
+        assert_eq!(
+            (
+                halfway.finished,
+                halfway.pass,
+                halfway.total_passes,
+                halfway.percent
+            ),
+            (true, 1, 2, 50.0)
+        );
+        assert!((halfway.eta_seconds.unwrap() - 4.016).abs() < 1e-9);
+    }
+
+    #[test]
+    fn combine_adds_up_parallel_parts() {
+        let mut total = ProgressTracker::new("1", 20.0, Some(600));
+        let part = |out_time, frames, size, fps, speed, finished| ProgressUpdate {
+            out_time,
+            frames_done: frames,
+            output_size: size,
+            fps,
+            speed,
+            finished,
+            ..Default::default()
+        };
+
+        let running = total.combine(&[
+            part(5.0, 150, 1000, 60.0, 2.0, false),
+            part(3.0, 90, 600, 45.0, 1.5, false),
+        ]);
+        // A finished part adds its time and size but no longer its speed
+        let one_done = total.combine(&[
+            part(10.0, 300, 2000, 60.0, 2.0, true),
+            part(6.0, 180, 1200, 45.0, 1.5, false),
+        ]);
+        let all_done = total.combine(&[
+            part(10.0, 300, 2000, 0.0, 0.0, true),
+            part(10.0, 300, 2000, 0.0, 0.0, true),
+        ]);
+
+        assert_eq!(
+            (
+                running.out_time,
+                running.frames_done,
+                running.output_size,
+                running.percent
+            ),
+            (8.0, 240, 1600, 40.0)
+        );
+        assert_eq!(
+            (running.fps, running.speed, running.eta_seconds),
+            (105.0, 3.5, Some(12.0 / 3.5))
+        );
+        assert_eq!(
+            (
+                one_done.percent,
+                one_done.fps,
+                one_done.speed,
+                one_done.finished
+            ),
+            (80.0, 45.0, 1.5, false)
+        );
+        assert_eq!(
+            (all_done.percent, all_done.finished, all_done.eta_seconds),
+            (100.0, true, Some(0.0))
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::encoders::EncoderBackend;
+use crate::engine::Tool;
+use crate::error::{CompressError, Result, STDERR_TAIL_LINES};
+use crate::hdr::ColorPlan;
+use crate::probe::MediaInfo;
+use crate::process::JobScope;
+
+// --- Quality-targeted CRF search ---
+// Encodes a few short samples of the source with the job's encoder, scores them against the
+// original with libvmaf or ssim, and binary searches the highest CRF whose average score still
+// meets the target. Samples come from the whole source, before any trimming.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMetric {
+    // 0-100, needs an ffmpeg built with libvmaf
+    Vmaf,
+    // 0-1, always available
+    Ssim,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityTarget {
+    pub metric: QualityMetric,
+    pub score: f64,
+}
+
+impl QualityTarget {
+    pub fn validate(&self) -> Result<()> {
+        let max = match self.metric {
+            QualityMetric::Vmaf => 100.0,
+            QualityMetric::Ssim => 1.0,
+        };
+        if self.score <= 0.0 || self.score > max {
+            return Err(CompressError::invalid(format!(
+                "Quality target must be between 0 and {}",
+                max
+            )));
+        }
+        Ok(())
+    }
+}
+
+const SAMPLE_SECONDS: f64 = 4.0;
+// Where the samples sit, as a fraction of the duration
+const SAMPLE_POSITIONS: &[f64] = &[0.2, 0.5, 0.8];
+// CRFs (x265 scale) the search stays within
+const CRF_RANGE: (u32, u32) = (14, 40);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchResult {
+    pub crf: u32,
+    pub score: f64,
+    // False when even the lowest CRF missed the target; `crf` is then that lowest one
+    pub met: bool,
+}
+
+// (start, length) of each sample. Short clips are measured in one piece.
+fn sample_windows(duration: f64) -> Vec<(f64, f64)> {
+    if duration <= SAMPLE_SECONDS * SAMPLE_POSITIONS.len() as f64 {
+        return vec![(0.0, duration.max(0.1))];
+    }
+    SAMPLE_POSITIONS
+        .iter()
+        .map(|p| {
+            (
+                (duration * p - SAMPLE_SECONDS / 2.0).clamp(0.0, duration - SAMPLE_SECONDS),
+                SAMPLE_SECONDS,
+            )
+        })
+        .collect()
+}
+
+fn metric_filter(metric: QualityMetric) -> &'static str {
+    match metric {
+        QualityMetric::Vmaf => "libvmaf",
+        QualityMetric::Ssim => "ssim",
+    }
+}
+
+// "[Parsed_libvmaf_4 @ 0x...] VMAF score: 94.731201" / "[Parsed_ssim_4 @ 0x...] SSIM Y:0.98 ... All:0.981234 (17.25)"
+pub fn parse_score(metric: QualityMetric, stderr: &str) -> Option<f64> {
+    stderr.lines().rev().find_map(|line| match metric {
+        QualityMetric::Vmaf => line
+            .split("VMAF score:")
+            .nth(1)
+            .and_then(|v| v.trim().parse().ok()),
+        QualityMetric::Ssim => line
+            .contains("SSIM ")
+            .then(|| line.split("All:").nth(1))
+            .flatten()
+            .and_then(|v| v.split_whitespace().next())
+            .and_then(|v| v.parse().ok()),
+    })
+}
+
+struct Search<'a, 'b> {
+    scope: &'a JobScope<'b>,
+    input: &'a str,
+    encoder: &'a dyn EncoderBackend,
+    transform: Option<&'a str>,
+    scale: Option<&'a str>,
+    color: &'a ColorPlan,
+    target: QualityTarget,
+    samples: Vec<(f64, f64, PathBuf)>,
+}
+
+impl Search<'_, '_> {
+    async fn ffmpeg(&self, args: Vec<String>) -> Result<String> {
+        let out = self.scope.output(Tool::Ffmpeg, args).await?;
+        let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+        if out.success() {
+            return Ok(stderr);
+        }
+        if stderr.contains("No such filter: 'libvmaf'") {
+            return Err(CompressError::unsupported(
+                "This ffmpeg build has no libvmaf, use an SSIM target instead",
+            ));
+        }
+        let lines: Vec<String> = stderr.lines().map(String::from).collect();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].to_vec();
+        Err(CompressError::from_ffmpeg(out.code, tail))
+    }
+
+    // Average score over all samples at this CRF
+    async fn score(&self, crf: u32) -> Result<f64> {
+        let mut total = 0.0;
+        for (start, length, path) in &self.samples {
+            let window = [
+                "-ss".to_string(),
+                start.to_string(),
+                "-t".to_string(),
+                length.to_string(),
+            ];
+            let mut encode = vec!["-y".to_string()];
+            encode.extend(window.clone());
+            encode.extend(["-i".to_string(), self.input.to_string()]);
+            let filters: Vec<String> = [
+                self.transform.map(String::from),
+                self.color.video_filter(self.scale.map(String::from)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if !filters.is_empty() {
+                encode.extend(["-vf".to_string(), filters.join(",")]);
+            }
+            encode.extend(self.encoder.constant_quality_args(crf, None));
+            encode.extend(self.color.args());
+            encode.extend([
+                "-an".to_string(),
+                "-sn".to_string(),
+                "-f".to_string(),
+                "matroska".to_string(),
+            ]);
+            encode.push(path.to_string_lossy().to_string());
+            self.ffmpeg(encode).await?;
+
+            // The reference is cropped, turned and tone-mapped like the sample and scaled to its
+            // size, libvmaf/ssim want the distorted clip first
+            let reference: String = [self.transform, self.color.tonemap.as_deref()]
+                .iter()
+                .flatten()
+                .map(|f| format!("{},", f))
+                .collect();
+            let graph = format!(
+                "[0:v]setpts=PTS-STARTPTS,format=yuv420p[d];[1:v]setpts=PTS-STARTPTS,{}format=yuv420p[r0];[r0][d]scale2ref=flags=bicubic[r][dd];[dd][r]{}",
+                reference,
+                metric_filter(self.target.metric)
+            );
+            let mut measure = vec!["-i".to_string(), path.to_string_lossy().to_string()];
+            measure.extend(window);
+            measure.extend([
+                "-i".to_string(),
+                self.input.to_string(),
+                "-lavfi".to_string(),
+                graph,
+            ]);
+            measure.extend(["-f".to_string(), "null".to_string(), "-".to_string()]);
+            let stderr = self.ffmpeg(measure).await?;
+            total += parse_score(self.target.metric, &stderr).ok_or_else(|| {
+                CompressError::internal(format!(
+                    "ffmpeg printed no {} score",
+                    metric_filter(self.target.metric)
+                ))
+            })?;
+        }
+        Ok(total / self.samples.len() as f64)
+    }
+}
+
+// Highest CRF in CRF_RANGE whose samples reach `target` with this encoder, crop/rotation, scaling
+// and color handling.
+#[allow(clippy::too_many_arguments)]
+pub async fn search(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    encoder: &dyn EncoderBackend,
+    transform: Option<&str>,
+    scale: Option<&str>,
+    color: &ColorPlan,
+    target: QualityTarget,
+) -> Result<SearchResult> {
+    target.validate()?;
+    let dir = scope.work_dir()?;
+    let samples = sample_windows(info.duration)
+        .into_iter()
+        .enumerate()
+        .map(|(n, (start, length))| (start, length, dir.join(format!("sample-{}.mkv", n))))
+        .collect();
+    let search = Search {
+        scope,
+        input,
+        encoder,
+        transform,
+        scale,
+        color,
+        target,
+        samples,
+    };
+
+    let (mut lo, mut hi) = CRF_RANGE;
+    let mut best: Option<SearchResult> = None;
+    let mut closest = SearchResult {
+        crf: lo,
+        score: 0.0,
+        met: false,
+    };
+    while lo <= hi {
+        let crf = (lo + hi) / 2;
+        let score = search.score(crf).await?;
+        scope.engine.note(format!(
+            "[Quality search] CRF {}: {} {:.3}",
+            crf,
+            metric_filter(target.metric),
+            score
+        ));
+        if score >= target.score {
+            best = Some(SearchResult {
+                crf,
+                score,
+                met: true,
+            });
+            lo = crf + 1;
+        } else {
+            closest = SearchResult {
+                crf,
+                score,
+                met: false,
+            };
+            hi = crf - 1;
+        }
+    }
+    Ok(best.unwrap_or(closest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoders::{software_encoder, VideoCodec};
+    use crate::probe::parse_ffprobe_json;
+    use crate::testing::{
+        block_on, engine_with, ffmpeg_calls, scoring, strings, ScriptedRunner, TempDir, PROBE_1080P,
+    };
+
+    #[test]
+    fn search_picks_the_highest_passing_crf() {
+        let dir = TempDir::new("quality-vmaf");
+        let runner = ScriptedRunner::new(scoring(PROBE_1080P));
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let scope = JobScope::new(&engine, None);
+        let info = parse_ffprobe_json(PROBE_1080P).unwrap();
+        let encoder = software_encoder(VideoCodec::Hevc);
+        let target = QualityTarget {
+            metric: QualityMetric::Vmaf,
+            score: 95.0,
+        };
+
+        let result = block_on(search(
+            &scope,
+            "in.mov",
+            &info,
+            encoder,
+            None,
+            None,
+            &ColorPlan::sdr(encoder),
+            target,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            result,
+            SearchResult {
+                crf: 20,
+                score: 95.0,
+                met: true
+            }
+        );
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        let sample = dir
+            .0
+            .join("compress-io-direct-0")
+            .join("sample-0.mkv")
+            .to_string_lossy()
+            .to_string();
+        // 27, 20, 23, 21: an encode and a measurement each
+        assert_eq!(ffmpeg.len(), 8);
+        assert_eq!(
+            ffmpeg[0],
+            strings(&[
+                "-y", "-ss", "0", "-t", "10", "-i", "in.mov", "-c:v", "libx265", "-preset",
+                "faster", "-crf", "27", "-pix_fmt", "yuv420p", "-an", "-sn", "-f", "matroska",
+                &sample
+            ])
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use crate::edit::fmt_time;
+use crate::error::Result;
+use crate::probe::{self, MediaInfo};
+use crate::process::JobScope;
+use crate::progress::{self, ProgressTracker};
+
+// --- Segment-parallel encoding ---
+// One libx265/libsvtav1 process rarely keeps a many-core machine busy. The source is split at its
+// own keyframes (where its encoder put the scene cuts, and where a segment can start without
+// borrowing frames from the one before), the segments are encoded by separate ffmpeg processes at
+// once and joined with the concat demuxer without re-encoding. Audio, subtitles and chapters come
+// straight from the source in that final mux, so A/V sync doesn't depend on the cut points.
+
+// Shorter segments spend more of their time warming up the encoder's lookahead than encoding
+const MIN_SEGMENT_SECONDS: f64 = 2.0;
+
+// Segment boundaries, 0 and `duration` included: the keyframes closest to an even split into
+// `count` parts. Fewer than two segments means the video isn't worth splitting.
+pub fn split_points(keyframes: &[f64], duration: f64, count: usize) -> Vec<f64> {
+    let mut points = vec![0.0];
+    for n in 1..count {
+        let ideal = duration * n as f64 / count as f64;
+        let last = *points.last().unwrap();
+        let best = keyframes
+            .iter()
+            // Whole milliseconds, so the -ss/-t of neighbouring segments meet exactly
+            .map(|k| (k * 1000.0).round() / 1000.0)
+            .filter(|k| *k >= last + MIN_SEGMENT_SECONDS && *k <= duration - MIN_SEGMENT_SECONDS)
+            .min_by(|a, b| (a - ideal).abs().total_cmp(&(b - ideal).abs()));
+        if let Some(point) = best {
+            points.push(point);
+        }
+    }
+    points.push(duration);
+    points
+}
+
+// Boundaries for `workers` segments of video stream `video_index`, None when it is too short or has
+// too few keyframes. Input -ss/-t count from the file's start, so keyframes are moved onto that clock.
+pub async fn plan(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    video_index: u32,
+    workers: usize,
+) -> Result<Option<Vec<f64>>> {
+    let duration = info.duration;
+    if workers < 2 || duration < MIN_SEGMENT_SECONDS * 2.0 {
+        return Ok(None);
+    }
+    let keyframes: Vec<f64> = probe::keyframes(scope, input, video_index)
+        .await?
+        .iter()
+        .map(|k| k - info.start_time)
+        .collect();
+    let points = split_points(&keyframes, duration, workers);
+    Ok((points.len() > 2).then_some(points))
+}
+
+// concat demuxer list line, single quotes escaped the way it wants them
+fn list_entry(path: &Path) -> String {
+    format!("file '{}'\n", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+// Encodes the video stream of `input` in segments, `workers` at a time. `encode_args` is everything
+// between the input and the segment file (filters, encoder, pix_fmt...). Returns the concat list to
+// feed to the final mux with `-f concat -safe 0 -i`.
+pub async fn encode(
+    scope: &JobScope<'_>,
+    input: &str,
+    video_index: u32,
+    points: &[f64],
+    encode_args: &[String],
+    workers: usize,
+    total: ProgressTracker,
+) -> Result<String> {
+    let dir = scope.work_dir()?;
+    let mut runs = vec![];
+    let mut list = String::new();
+    let last = points.len() - 2;
+    for (n, window) in points.windows(2).enumerate() {
+        let (start, end) = (window[0], window[1]);
+        let path = dir.join(format!("segment-{}.mkv", n));
+        let mut args = progress::progress_args();
+        args.push("-y".to_string());
+        if n > 0 {
+            args.extend(["-ss".to_string(), fmt_time(start)]);
+        }
+        // The last one runs to the end of the file, whatever the probed duration says
+        if n < last {
+            args.extend(["-t".to_string(), fmt_time(end - start)]);
+        }
+        args.extend([
+            "-i".to_string(),
+            input.to_string(),
+            "-map".to_string(),
+            format!("0:{}", video_index),
+        ]);
+        args.extend(encode_args.iter().cloned());
+        args.extend([
+            "-an".to_string(),
+            "-sn".to_string(),
+            "-dn".to_string(),
+            "-f".to_string(),
+            "matroska".to_string(),
+        ]);
+        args.push(path.to_string_lossy().to_string());
+        runs.push((args, ProgressTracker::new(&scope.id, end - start, None)));
+        list.push_str(&list_entry(&path));
+    }
+
+    scope.engine.note(format!(
+        "⚡ Encoding {} segments, {} at a time",
+        runs.len(),
+        workers
+    ));
+    progress::run_ffmpeg_parallel(scope, runs, workers, total, "Segment").await?;
+
+    let list_path = dir.join("segments.txt");
+    std::fs::write(&list_path, list)?;
+    Ok(list_path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Tool;
+    use crate::error::CompressError;
+    use crate::probe::parse_ffprobe_json;
+    use crate::testing::{block_on, engine_with, Script, ScriptedRunner, TempDir};
+
+    #[test]
+    fn split_points_pick_the_keyframes_nearest_an_even_split() {
+        let keyframes: Vec<f64> = (0..10).map(|k| k as f64).collect();
+
+        // Ties go to the earlier keyframe
+        assert_eq!(
+            split_points(&keyframes, 10.0, 4),
+            vec![0.0, 2.0, 5.0, 7.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn split_points_round_to_whole_milliseconds() {
+        assert_eq!(
+            split_points(&[0.0, 2.5021, 5.00549, 7.5], 10.0, 2),
+            vec![0.0, 5.005, 10.0]
+        );
+    }
+
+    #[test]
+    fn split_points_skip_keyframes_too_close_to_either_end() {
+        // 1 s after the start and 1 s before the end are both under MIN_SEGMENT_SECONDS
+        assert_eq!(split_points(&[0.0, 1.0, 9.0], 10.0, 2), vec![0.0, 10.0]);
+        // A second cut would leave less than MIN_SEGMENT_SECONDS after the first
+        assert_eq!(
+            split_points(&[0.0, 3.0, 4.0, 7.0], 10.0, 3),
+            vec![0.0, 3.0, 7.0, 10.0]
+        );
+        assert_eq!(
+            split_points(&[0.0, 4.0, 5.0], 10.0, 3),
+            vec![0.0, 4.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn split_points_without_keyframes_is_one_segment() {
+        assert_eq!(split_points(&[], 10.0, 4), vec![0.0, 10.0]);
+    }
+
+    // MPEG-TS whose clock starts at 1.4 s, with cover art ahead of the picture
+    const PROBE_TS: &str = r#"{
+      "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600, "disposition": { "attached_pic": 1 } },
+        { "index": 1, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "avg_frame_rate": "30/1" }
+      ],
+      "format": { "format_name": "mpegts", "start_time": "1.400000", "duration": "10.000000" }
+    }"#;
+
+    #[test]
+    fn plan_reads_the_encoded_stream_on_the_input_clock() {
+        let dir = TempDir::new("segments-start-time");
+        let runner = ScriptedRunner::new(|_, _| {
+            Script::stdout("1.400000,K__\n4.400000,K__\n6.405000,K__\n9.400000,K__")
+        });
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let info = parse_ffprobe_json(PROBE_TS).unwrap();
+
+        let scope = JobScope::new(&engine, None);
+
+        let points = block_on(plan(&scope, "in.ts", &info, 1, 2)).unwrap();
+
+        assert_eq!(points, Some(vec![0.0, 5.005, 10.0]));
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls[0].tool, Tool::Ffprobe);
+        assert!(calls[0]
+            .args
+            .windows(2)
+            .any(|w| w[0] == "-select_streams" && w[1] == "1"));
+    }
+
+    #[test]
+    fn keyframe_scan_is_cancelled_with_its_job() {
+        let dir = TempDir::new("segments-cancel");
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+        let calls = runner.calls();
+        let engine = engine_with(runner, &dir.0);
+        let info = parse_ffprobe_json(PROBE_TS).unwrap();
+        let scope = JobScope::new(&engine, Some("5".to_string()));
+        engine.registry.cancel("5");
+
+        let result = block_on(plan(&scope, "in.ts", &info, 1, 2));
+
+        assert_eq!(result, Err(CompressError::Cancelled));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+}
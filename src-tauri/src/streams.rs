@@ -0,0 +1,242 @@
+use crate::presets::{AudioCodec, Preset, StreamPolicy};
+use crate::probe::MediaInfo;
+
+// --- Stream mapping for video outputs ---
+// ffmpeg's default selection keeps one video and one audio stream. With `StreamPolicy::All` we map
+// the picture, every audio track and every subtitle track the container can hold, plus chapters.
+// Global and per-stream tags (language, title) are carried over by ffmpeg unless metadata is stripped.
+
+// Audio codecs (ffprobe names) each container takes as-is
+fn accepts_audio(container: &str, codec: &str) -> bool {
+    match container {
+        "mkv" => true,
+        "mp4" | "m4v" => matches!(codec, "aac" | "mp3" | "ac3" | "eac3" | "alac" | "opus"),
+        "mov" => matches!(
+            codec,
+            "aac" | "mp3" | "ac3" | "eac3" | "alac" | "pcm_s16le" | "pcm_s24le"
+        ),
+        "webm" => matches!(codec, "opus" | "vorbis"),
+        "ts" => matches!(codec, "aac" | "mp3" | "ac3" | "eac3"),
+        "avi" => matches!(codec, "mp3" | "ac3" | "pcm_s16le"),
+        "flv" => matches!(codec, "aac" | "mp3"),
+        _ => false,
+    }
+}
+
+// ffprobe name of what our encoder for `codec` produces
+fn probe_name(codec: AudioCodec) -> &'static str {
+    match codec {
+        AudioCodec::Aac => "aac",
+        AudioCodec::Opus => "opus",
+        AudioCodec::Mp3 => "mp3",
+        AudioCodec::Copy => "",
+    }
+}
+
+pub fn is_text_subtitle(codec: &str) -> bool {
+    matches!(
+        codec,
+        "subrip" | "srt" | "ass" | "ssa" | "mov_text" | "webvtt" | "text"
+    )
+}
+
+// Encoder for a subtitle track in this container, None when it can't be carried.
+// MP4/MOV and WebM only hold text subtitles, so bitmap ones (PGS, VobSub, DVB) are dropped there.
+pub fn subtitle_encoder(container: &str, codec: &str) -> Option<&'static str> {
+    match container {
+        "mkv" => Some("copy"),
+        "mp4" | "m4v" | "mov" if is_text_subtitle(codec) => Some("mov_text"),
+        "webm" if is_text_subtitle(codec) => Some("webvtt"),
+        _ => None,
+    }
+}
+
+// Audio codec a track gets re-encoded to when it has to be: the preset's, or the container's
+// usual one when the preset asked to copy.
+fn fallback_codec(container: &str, wanted: AudioCodec) -> AudioCodec {
+    match wanted {
+        AudioCodec::Copy if container == "webm" => AudioCodec::Opus,
+        AudioCodec::Copy => AudioCodec::Aac,
+        other => other,
+    }
+}
+
+// Map, codec and bitrate args for everything but the video encoder itself. `audio_codec` is what
+// the preset (or the container, for WebM) wants; a track that already is that codec at no more
+// than the preset bitrate is copied instead of being encoded a second time. `audio_filter` goes on
+// the first audio track, which is then always encoded.
+pub fn mapping_args(
+    preset: &Preset,
+    info: &MediaInfo,
+    container: &str,
+    audio_codec: AudioCodec,
+    audio_filter: Option<&str>,
+) -> Vec<String> {
+    let video = match info.primary_video() {
+        Some(video) if preset.streams == StreamPolicy::All => video,
+        _ => return primary_audio_args(preset, container, audio_codec, audio_filter),
+    };
+    source_args(
+        preset,
+        info,
+        container,
+        audio_codec,
+        audio_filter,
+        format!("0:{}", video.index),
+        0,
+    )
+}
+
+fn primary_audio_args(
+    preset: &Preset,
+    container: &str,
+    audio_codec: AudioCodec,
+    audio_filter: Option<&str>,
+) -> Vec<String> {
+    let audio_codec = if audio_filter.is_some() {
+        fallback_codec(container, audio_codec)
+    } else {
+        audio_codec
+    };
+    let mut args = preset.audio_args(audio_codec);
+    if let Some(filter) = audio_filter {
+        args.extend(["-filter:a:0".to_string(), filter.to_string()]);
+    }
+    args
+}
+
+// The same for a mux whose video is already encoded: the picture comes from input 0 (a concat list
+// of segments) and everything else from the source at input `source`.
+pub fn remux_args(
+    preset: &Preset,
+    info: &MediaInfo,
+    container: &str,
+    audio_codec: AudioCodec,
+    audio_filter: Option<&str>,
+    source: usize,
+) -> Vec<String> {
+    if preset.streams == StreamPolicy::Primary {
+        let mut args = vec![
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-map".to_string(),
+            format!("{}:a:0?", source),
+        ];
+        args.extend(primary_audio_args(
+            preset,
+            container,
+            audio_codec,
+            audio_filter,
+        ));
+        return args;
+    }
+    source_args(
+        preset,
+        info,
+        container,
+        audio_codec,
+        audio_filter,
+        "0:v:0".to_string(),
+        source,
+    )
+}
+
+// Source subtitle tracks `source_args` maps for this container, in output order
+pub fn carried_subtitles(info: &MediaInfo, container: &str) -> Vec<u32> {
+    info.subtitles
+        .iter()
+        .filter(|s| subtitle_encoder(container, &s.codec).is_some())
+        .map(|s| s.index)
+        .collect()
+}
+
+fn source_args(
+    preset: &Preset,
+    info: &MediaInfo,
+    container: &str,
+    audio_codec: AudioCodec,
+    audio_filter: Option<&str>,
+    video_map: String,
+    source: usize,
+) -> Vec<String> {
+    let mut args = vec!["-map".to_string(), video_map];
+
+    for (n, track) in info.audio.iter().enumerate() {
+        args.extend(["-map".to_string(), format!("{}:{}", source, track.index)]);
+        let same_codec = audio_codec == AudioCodec::Copy || track.codec == probe_name(audio_codec);
+        let within_bitrate = match (preset.audio_bitrate_kbps, track.bit_rate) {
+            (Some(kbps), Some(bps)) => bps <= kbps as u64 * 1000,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let filter = audio_filter.filter(|_| n == 0);
+        if filter.is_none()
+            && same_codec
+            && (within_bitrate || audio_codec == AudioCodec::Copy)
+            && accepts_audio(container, &track.codec)
+        {
+            args.extend([format!("-c:a:{}", n), "copy".to_string()]);
+            continue;
+        }
+        let codec = fallback_codec(container, audio_codec);
+        args.extend([format!("-c:a:{}", n), codec.encoder().to_string()]);
+        if let Some(kbps) = preset.audio_bitrate_kbps {
+            args.extend([format!("-b:a:{}", n), format!("{}k", kbps)]);
+        }
+        if let Some(filter) = filter {
+            args.extend([format!("-filter:a:{}", n), filter.to_string()]);
+        }
+    }
+
+    let mut out = 0;
+    for track in &info.subtitles {
+        if let Some(encoder) = subtitle_encoder(container, &track.codec) {
+            args.extend(["-map".to_string(), format!("{}:{}", source, track.index)]);
+            args.extend([format!("-c:s:{}", out), encoder.to_string()]);
+            out += 1;
+        }
+    }
+    // Fonts for ASS subtitles travel as attachments
+    if container == "mkv" {
+        args.extend(["-map".to_string(), format!("{}:t?", source)]);
+    }
+    args.extend(["-map_chapters".to_string(), source.to_string()]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::default_preset;
+    use crate::probe::parse_ffprobe_json;
+    use crate::testing::{strings, PROBE_MULTI_TRACK};
+
+    #[test]
+    fn mp4_keeps_every_track_it_can_hold() {
+        let info = parse_ffprobe_json(PROBE_MULTI_TRACK).unwrap();
+
+        // The AC-3 track is encoded, the PGS track and the cover art are left out
+        assert_eq!(
+            mapping_args(&default_preset(), &info, "mp4", AudioCodec::Aac, None),
+            strings(&[
+                "-map",
+                "0:0",
+                "-map",
+                "0:1",
+                "-c:a:0",
+                "copy",
+                "-map",
+                "0:2",
+                "-c:a:1",
+                "aac",
+                "-map",
+                "0:3",
+                "-c:s:0",
+                "mov_text",
+                "-map_chapters",
+                "0"
+            ])
+        );
+        assert_eq!(carried_subtitles(&info, "mp4"), vec![3]);
+    }
+}
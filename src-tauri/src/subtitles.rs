@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::edit::{fmt_time, EditPlan};
+use crate::error::{CompressError, Result};
+use crate::probe::MediaInfo;
+use crate::streams;
+
+// --- Captions: burn-in and soft subtitle tracks ---
+// A subtitle comes from an external .srt/.ass/.vtt file or from a text track of the source. Burned
+// in, it is drawn into the picture by libass through the `subtitles` filter, ahead of scaling so it
+// keeps its layout. As a soft track it is muxed next to the picture, converted to what the container
+// takes (mov_text for MP4/MOV, WebVTT for WebM, as-is for MKV) and tagged with its language.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleMode {
+    // Selectable track, players can switch it off
+    #[default]
+    Soft,
+    // Part of the picture, shows everywhere
+    Burn,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubtitleOptions {
+    // External subtitle file, or...
+    pub file: Option<String>,
+    // ...a subtitle track of the source, by its probe index
+    pub track: Option<u32>,
+    pub mode: SubtitleMode,
+    // ISO 639-2 code ("eng", "deu") and title for a soft track; an embedded track keeps its own unless set
+    pub language: Option<String>,
+    pub title: Option<String>,
+    // Players turn a default track on by themselves
+    pub default: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SubtitlePlan {
+    // Drawing filter for -vf, chained after tone mapping and before scaling
+    pub burn: Option<String>,
+    // Seek options and -i for an external soft track, after the job's other inputs
+    pub input_args: Vec<String>,
+    // Map, codec, tags and disposition of the soft track
+    pub output_args: Vec<String>,
+}
+
+impl SubtitlePlan {
+    // `scale` with the subtitles drawn in first
+    pub fn video_filter(&self, scale: Option<String>) -> Option<String> {
+        match (&self.burn, scale) {
+            (Some(burn), Some(scale)) => Some(format!("{},{}", burn, scale)),
+            (Some(burn), None) => Some(burn.clone()),
+            (None, scale) => scale,
+        }
+    }
+}
+
+// ffprobe codec name of an external subtitle file
+fn file_codec(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match ext.as_str() {
+        "srt" => Some("subrip"),
+        "ass" | "ssa" => Some("ass"),
+        "vtt" => Some("webvtt"),
+        _ => None,
+    }
+}
+
+// A path as a filter option value. Escaped twice, once for the option parser (':' '\' '\'') and
+// once for the filter graph ('[' ',' ';'...). Forward slashes work on Windows too and need no escaping.
+fn filter_path(path: &str) -> String {
+    let path = if cfg!(target_os = "windows") {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    };
+    let mut option = String::new();
+    for c in path.chars() {
+        if matches!(c, '\\' | ':' | '\'') {
+            option.push('\\');
+        }
+        option.push(c);
+    }
+    let mut graph = String::new();
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph.push('\\');
+        }
+        graph.push(c);
+    }
+    graph
+}
+
+fn check_language(language: &str) -> Result<()> {
+    if language.len() == 3 && language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(());
+    }
+    Err(CompressError::invalid(format!(
+        "Subtitle language '{}' should be a three-letter ISO 639-2 code like eng",
+        language
+    )))
+}
+
+// Works out the burn filter or the soft track for `container`. `mapped` lists the source subtitle
+// tracks the job's other options already map (in output order), None when they rely on ffmpeg's
+// default stream selection, which any explicit -map switches off.
+pub fn plan(
+    input: &str,
+    info: &MediaInfo,
+    options: &SubtitleOptions,
+    container: &str,
+    edit: &EditPlan,
+    mapped: Option<Vec<u32>>,
+) -> Result<SubtitlePlan> {
+    if edit.is_filtered() {
+        return Err(CompressError::invalid(
+            "Subtitles can't be combined with cutting or joining",
+        ));
+    }
+    let (source, codec, position) = match (&options.file, options.track) {
+        (Some(file), None) => {
+            if !Path::new(file).exists() {
+                return Err(CompressError::input_not_found(file));
+            }
+            let codec = file_codec(file).ok_or_else(|| {
+                CompressError::unsupported("Subtitle files must be .srt, .ass, .ssa or .vtt")
+            })?;
+            (None, codec.to_string(), 0)
+        }
+        (None, Some(index)) => {
+            let position = info
+                .subtitles
+                .iter()
+                .position(|s| s.index == index)
+                .ok_or_else(|| {
+                    CompressError::invalid(format!("The source has no subtitle track {}", index))
+                })?;
+            (
+                Some(index),
+                info.subtitles[position].codec.clone(),
+                position,
+            )
+        }
+        _ => {
+            return Err(CompressError::invalid(
+                "Pick either a subtitle file or a subtitle track",
+            ))
+        }
+    };
+    if let Some(language) = &options.language {
+        check_language(language)?;
+    }
+
+    if options.mode == SubtitleMode::Burn {
+        if !streams::is_text_subtitle(&codec) {
+            return Err(CompressError::unsupported("Only text subtitles can be burned in, picture-based ones (PGS, VobSub) can be kept as soft tracks in MKV"));
+        }
+        let filter = match &options.file {
+            Some(file) => format!("subtitles=filename={}", filter_path(file)),
+            None => format!("subtitles=filename={}:si={}", filter_path(input), position),
+        };
+        // The subtitles filter times its cues on the source timeline, a trimmed input starts at 0
+        let burn = if edit.start > 0.0 {
+            format!(
+                "setpts=PTS+{}/TB,{},setpts=PTS-STARTPTS",
+                fmt_time(edit.start),
+                filter
+            )
+        } else {
+            filter
+        };
+        return Ok(SubtitlePlan {
+            burn: Some(burn),
+            ..Default::default()
+        });
+    }
+
+    let encoder = streams::subtitle_encoder(container, &codec).ok_or_else(|| {
+        CompressError::unsupported(format!(
+            "{} can't hold this subtitle as a soft track, use MKV or burn it in",
+            container.to_uppercase()
+        ))
+    })?;
+    let mut plan = SubtitlePlan::default();
+    let out = match (&mapped, source) {
+        // Already in the output with the other tracks
+        (Some(tracks), Some(index)) if tracks.contains(&index) => {
+            tracks.iter().position(|t| *t == index).unwrap_or(0)
+        }
+        _ => {
+            if mapped.is_none() {
+                // Explicit maps turn off default selection, so the picture and sound need theirs
+                let video = info.primary_video().map(|v| v.index).unwrap_or(0);
+                plan.output_args.extend([
+                    "-map".to_string(),
+                    format!("0:{}", video),
+                    "-map".to_string(),
+                    "0:a:0?".to_string(),
+                ]);
+            }
+            let map = match source {
+                Some(index) => format!("0:{}", index),
+                None => {
+                    plan.input_args.extend(edit.seek_args.iter().cloned());
+                    plan.input_args
+                        .extend(["-i".to_string(), options.file.clone().unwrap_or_default()]);
+                    format!("{}:0", edit.inputs())
+                }
+            };
+            plan.output_args.extend(["-map".to_string(), map]);
+            let out = mapped.as_ref().map(|t| t.len()).unwrap_or(0);
+            plan.output_args
+                .extend([format!("-c:s:{}", out), encoder.to_string()]);
+            out
+        }
+    };
+    if let Some(language) = &options.language {
+        plan.output_args.extend([
+            format!("-metadata:s:s:{}", out),
+            format!("language={}", language.to_lowercase()),
+        ]);
+    }
+    if let Some(title) = &options.title {
+        plan.output_args
+            .extend([format!("-metadata:s:s:{}", out), format!("title={}", title)]);
+    }
+    if options.default {
+        plan.output_args
+            .extend([format!("-disposition:s:{}", out), "default".to_string()]);
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::{self, EditList, KeepRange};
+    use crate::testing::{
+        block_on, planning, Script, ScriptedRunner, TempDir, PROBE_1080P, PROBE_MULTI_TRACK,
+    };
+
+    #[test]
+    fn filter_path_escapes_for_the_option_and_the_graph() {
+        // The option parser's escapes for ':' and '\'' get escaped again for the graph, with '[' ']' ','
+        assert_eq!(
+            filter_path("/home/me/it's [v2], final: cut.srt"),
+            r"/home/me/it\\\'s \[v2\]\, final\\: cut.srt"
+        );
+        assert_eq!(filter_path("/subs/plain.srt"), "/subs/plain.srt");
+    }
+
+    #[test]
+    fn filter_path_escapes_the_drive_colon() {
+        assert_eq!(
+            filter_path("C:/Users/Me/Videos/subs.srt"),
+            r"C\\:/Users/Me/Videos/subs.srt"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn filter_path_turns_windows_separators_into_slashes() {
+        assert_eq!(
+            filter_path(r"C:\Users\Me\Videos\subs.srt"),
+            r"C\\:/Users/Me/Videos/subs.srt"
+        );
+    }
+
+    // A backslash is an ordinary file name character elsewhere
+    #[cfg(unix)]
+    #[test]
+    fn filter_path_keeps_backslashes_in_unix_names() {
+        assert_eq!(filter_path(r"/subs/a\b.srt"), r"/subs/a\\\\b.srt");
+    }
+
+    #[test]
+    fn picture_subtitles_cannot_be_burned_in() {
+        let options = SubtitleOptions {
+            track: Some(4),
+            mode: SubtitleMode::Burn,
+            ..Default::default()
+        };
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+
+        let result = planning(PROBE_MULTI_TRACK, runner, |scope, info| {
+            let edit = block_on(edit::plan(scope, "in.mkv", info, &EditList::default()));
+            plan("in.mkv", info, &options, "mp4", &edit.unwrap(), None)
+        });
+
+        assert!(matches!(
+            result,
+            Err(CompressError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn burned_subtitles_follow_the_trim() {
+        let dir = TempDir::new("subtitles-burn");
+        let subs = dir.file("subs, v2.srt", b"");
+        let options = SubtitleOptions {
+            file: Some(subs.clone()),
+            mode: SubtitleMode::Burn,
+            ..Default::default()
+        };
+        let trim = EditList {
+            keep: vec![KeepRange {
+                start: 2.5,
+                end: None,
+            }],
+            append: vec![],
+        };
+        let runner = ScriptedRunner::new(|_, _| Script::ok());
+
+        let plan = planning(PROBE_1080P, runner, |scope, info| {
+            let edit = block_on(edit::plan(scope, "in.mov", info, &trim));
+            plan("in.mov", info, &options, "mp4", &edit.unwrap(), None)
+        })
+        .unwrap();
+
+        let burn = format!(
+            "setpts=PTS+2.5/TB,subtitles=filename={},setpts=PTS-STARTPTS",
+            subs.replace(',', "\\,")
+        );
+        assert_eq!(plan.burn.as_deref(), Some(burn.as_str()));
+        assert_eq!(
+            plan.video_filter(Some("scale=1280:-2".to_string())),
+            Some(format!("{},scale=1280:-2", burn))
+        );
+        assert!(plan.input_args.is_empty() && plan.output_args.is_empty());
+    }
+}
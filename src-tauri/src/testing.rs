@@ -0,0 +1,289 @@
+// Test doubles for the compression core: a ProcessRunner that never starts anything and replays
+// scripted output instead, and an Engine wired to it.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::channel;
+
+use crate::engine::{
+    ChildProcess, Engine, EventSink, ProcessEvent, ProcessRunner, SpawnedTool, Tool,
+};
+use crate::error::Result;
+use crate::probe::{parse_ffprobe_json, MediaInfo};
+use crate::process::JobScope;
+
+// Runs an async test body on its own runtime
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+// What a fake process prints and how it exits.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    pub code: Option<i32>,
+}
+
+impl Script {
+    pub fn ok() -> Self {
+        Script {
+            code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    pub fn failing(code: i32, stderr: &[&str]) -> Self {
+        Script {
+            stderr: stderr.iter().map(|s| s.to_string()).collect(),
+            code: Some(code),
+            ..Default::default()
+        }
+    }
+
+    pub fn stdout(text: &str) -> Self {
+        Script {
+            stdout: text.lines().map(String::from).collect(),
+            code: Some(0),
+            ..Default::default()
+        }
+    }
+}
+
+type Responder = Box<dyn Fn(Tool, &[String]) -> Script + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub tool: Tool,
+    pub args: Vec<String>,
+}
+
+// Records every spawn and answers with whatever the responder returns for it.
+pub struct ScriptedRunner {
+    calls: Arc<Mutex<Vec<Call>>>,
+    kills: Arc<AtomicUsize>,
+    next_pid: AtomicU32,
+    respond: Responder,
+}
+
+impl ScriptedRunner {
+    pub fn new(respond: impl Fn(Tool, &[String]) -> Script + Send + Sync + 'static) -> Self {
+        ScriptedRunner {
+            calls: Arc::default(),
+            kills: Arc::default(),
+            next_pid: AtomicU32::new(1000),
+            respond: Box::new(respond),
+        }
+    }
+
+    // Shared view of the recorded calls, still readable once the runner is owned by an Engine
+    pub fn calls(&self) -> Arc<Mutex<Vec<Call>>> {
+        self.calls.clone()
+    }
+
+    pub fn kills(&self) -> Arc<AtomicUsize> {
+        self.kills.clone()
+    }
+}
+
+struct FakeChild(Arc<AtomicUsize>);
+
+impl ChildProcess for FakeChild {
+    fn kill(self: Box<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl ProcessRunner for ScriptedRunner {
+    fn spawn(&self, tool: Tool, args: Vec<String>) -> Result<SpawnedTool> {
+        let script = (self.respond)(tool, &args);
+        self.calls.lock().unwrap().push(Call { tool, args });
+        let mut events = vec![];
+        events.extend(
+            script
+                .stdout
+                .into_iter()
+                .map(|l| ProcessEvent::Stdout(l.into_bytes())),
+        );
+        events.extend(
+            script
+                .stderr
+                .into_iter()
+                .map(|l| ProcessEvent::Stderr(l.into_bytes())),
+        );
+        events.push(ProcessEvent::Exited(script.code));
+        // Big enough for the whole script, so it is queued up front and the channel closes after `Exited`
+        let (tx, rx) = channel(events.len());
+        for event in events {
+            tx.try_send(event)
+                .expect("scripted output fits the channel");
+        }
+        Ok(SpawnedTool {
+            pid: self.next_pid.fetch_add(1, Ordering::SeqCst),
+            events: rx,
+            handle: Box::new(FakeChild(self.kills.clone())),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct RecordedEvents(pub Arc<Mutex<Vec<(String, serde_json::Value)>>>);
+
+impl EventSink for RecordedEvents {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        self.0.lock().unwrap().push((event.to_string(), payload));
+    }
+}
+
+// Scratch folder per test, removed on drop.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "compress-io-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    // Creates a file with the given contents and returns its path as a String, like the commands take it.
+    pub fn file(&self, name: &str, contents: &[u8]) -> String {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    pub fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn engine_with(runner: ScriptedRunner, dir: &Path) -> Engine {
+    Engine::new(
+        Box::new(runner),
+        Box::new(RecordedEvents::default()),
+        dir.to_path_buf(),
+        dir.join("ai_engine"),
+    )
+}
+
+// ffprobe output for a 10 s 1920x1080 30 fps H.264 file with one stereo AAC track
+pub const PROBE_1080P: &str = r#"{
+  "streams": [
+    { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+      "pix_fmt": "yuv420p", "avg_frame_rate": "30/1", "r_frame_rate": "30/1", "nb_frames": "300" },
+    { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000", "bit_rate": "128000" }
+  ],
+  "format": { "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.000000", "size": "5000000", "bit_rate": "4000000" }
+}"#;
+
+// MKV with a cover image, two audio tracks (AAC, 5.1 AC-3) and two subtitles (SRT, PGS)
+pub const PROBE_MULTI_TRACK: &str = r#"{
+  "streams": [
+    { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "avg_frame_rate": "24/1", "nb_frames": "240" },
+    { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000", "bit_rate": "160000" },
+    { "index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6, "sample_rate": "48000", "bit_rate": "448000" },
+    { "index": 3, "codec_type": "subtitle", "codec_name": "subrip" },
+    { "index": 4, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" },
+    { "index": 5, "codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600, "disposition": { "attached_pic": 1 } }
+  ],
+  "format": { "format_name": "matroska,webm", "duration": "10.000000", "size": "8000000" }
+}"#;
+
+// Runs a planning step against the ffprobe output `probe_json`, in the scope of a throwaway job on
+// an engine driven by `runner`.
+pub fn planning<T>(
+    probe_json: &str,
+    runner: ScriptedRunner,
+    run: impl FnOnce(&JobScope<'_>, &MediaInfo) -> T,
+) -> T {
+    let dir = TempDir::new("planning");
+    let engine = engine_with(runner, &dir.0);
+    let scope = JobScope::new(&engine, None);
+    run(&scope, &parse_ffprobe_json(probe_json).unwrap())
+}
+
+// Answers ffprobe with `probe_json`, every other call succeeds silently.
+pub fn probing(
+    probe_json: &'static str,
+) -> impl Fn(Tool, &[String]) -> Script + Send + Sync + 'static {
+    move |tool, _args| match tool {
+        Tool::Ffprobe => Script::stdout(probe_json),
+        _ => Script::ok(),
+    }
+}
+
+// Like `probing`, but every ffmpeg run that writes a file (last argument, not a null sink) leaves one
+// of `sizes` bytes behind, in order; the last size repeats.
+pub fn encoding(
+    probe_json: &'static str,
+    sizes: &[u64],
+) -> impl Fn(Tool, &[String]) -> Script + Send + Sync + 'static {
+    let sizes = sizes.to_vec();
+    let written = AtomicUsize::new(0);
+    move |tool, args| match tool {
+        Tool::Ffprobe => Script::stdout(probe_json),
+        Tool::Ffmpeg => {
+            if let Some(path) = args
+                .last()
+                .filter(|p| !matches!(p.as_str(), "-" | "/dev/null" | "NUL"))
+            {
+                let n = written.fetch_add(1, Ordering::SeqCst).min(sizes.len() - 1);
+                std::fs::write(path, vec![0u8; sizes[n] as usize]).unwrap();
+            }
+            Script::ok()
+        }
+        _ => Script::ok(),
+    }
+}
+
+// Like `probing`, with a fake libvmaf that loses half a point per CRF step of the last encode:
+// 95 at CRF 20, 94.5 at 21.
+pub fn scoring(
+    probe_json: &'static str,
+) -> impl Fn(Tool, &[String]) -> Script + Send + Sync + 'static {
+    let last_crf = Mutex::new(0.0);
+    move |tool, args| {
+        if tool == Tool::Ffprobe {
+            return Script::stdout(probe_json);
+        }
+        if let Some(i) = args.iter().position(|a| a == "-crf") {
+            *last_crf.lock().unwrap() = args[i + 1].parse::<f64>().unwrap();
+        }
+        if args.iter().any(|a| a.ends_with("libvmaf")) {
+            let score = 100.0 - (*last_crf.lock().unwrap() - 10.0) * 0.5;
+            return Script {
+                stderr: vec![format!("[Parsed_libvmaf_4 @ 0x55d0] VMAF score: {}", score)],
+                code: Some(0),
+                ..Default::default()
+            };
+        }
+        Script::ok()
+    }
+}
+
+pub fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+// Argument lists of the recorded ffmpeg runs, in order
+pub fn ffmpeg_calls(calls: &[Call]) -> Vec<Vec<String>> {
+    calls
+        .iter()
+        .filter(|c| c.tool == Tool::Ffmpeg)
+        .map(|c| c.args.clone())
+        .collect()
+}
@@ -0,0 +1,392 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Tool;
+use crate::error::{CompressError, Result};
+use crate::probe::MediaInfo;
+use crate::process::JobScope;
+
+// --- Rotation, flips and cropping ---
+// ffmpeg applies the source's rotation side data while decoding (autorotate), so every filter here
+// sees the picture the way players show it and crop rectangles are in those coordinates. The
+// encoded output is upright and ffmpeg drops the rotation from it. Auto-crop runs cropdetect on a few
+// short stretches of the source and keeps the box that fits all of them, so a dark scene doesn't
+// cut into the picture.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TransformOptions {
+    // Clockwise degrees (90, 180, 270) on top of the source's own rotation
+    pub rotate: u32,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    // Applied before rotating and flipping
+    pub crop: Option<CropRect>,
+    // Crop black bars found by cropdetect; an explicit `crop` wins
+    pub auto_crop: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TransformPlan {
+    filter: Option<String>,
+}
+
+impl TransformPlan {
+    // `rest` (color, subtitles, scaling) after the crop and rotation
+    pub fn video_filter(&self, rest: Option<String>) -> Option<String> {
+        match (&self.filter, rest) {
+            (Some(filter), Some(rest)) => Some(format!("{},{}", filter, rest)),
+            (Some(filter), None) => Some(filter.clone()),
+            (None, rest) => rest,
+        }
+    }
+}
+
+// Where cropdetect looks, as a fraction of the duration, and how many frames it reads there
+const DETECT_POSITIONS: &[f64] = &[0.1, 0.3, 0.5, 0.7, 0.9];
+const DETECT_FRAMES: u32 = 30;
+// Bars thinner than this aren't worth a non-standard frame size
+const MIN_BAR_PIXELS: u32 = 8;
+
+// "[Parsed_cropdetect_0 @ 0x...] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:... crop=1920:800:0:140"
+// Only the last line counts, cropdetect keeps growing its box while it reads. Black frames give a
+// negative size and no rectangle.
+pub fn parse_cropdetect(stderr: &str) -> Option<CropRect> {
+    let line = stderr.lines().rev().find(|l| l.contains("crop="))?;
+    let values: Vec<u32> = line
+        .split("crop=")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .split(':')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    match values.as_slice() {
+        [width, height, x, y] if *width > 0 && *height > 0 => Some(CropRect {
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+        }),
+        _ => None,
+    }
+}
+
+// The smallest rectangle holding every detected one, None when there are no bars to speak of
+async fn detect(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    width: u32,
+    height: u32,
+) -> Result<Option<CropRect>> {
+    let video = match info.primary_video() {
+        Some(video) => video,
+        None => return Ok(None),
+    };
+    let starts: Vec<f64> = if info.duration > 0.0 {
+        DETECT_POSITIONS.iter().map(|p| info.duration * p).collect()
+    } else {
+        vec![0.0]
+    };
+    let mut found: Option<(u32, u32, u32, u32)> = None;
+    for start in starts {
+        let args = vec![
+            "-ss".to_string(),
+            format!("{:.3}", start),
+            "-i".to_string(),
+            input.to_string(),
+            "-map".to_string(),
+            format!("0:{}", video.index),
+            "-vf".to_string(),
+            "cropdetect=limit=0.1:round=2:reset=0".to_string(),
+            "-frames:v".to_string(),
+            DETECT_FRAMES.to_string(),
+            "-an".to_string(),
+            "-f".to_string(),
+            "null".to_string(),
+            "-".to_string(),
+        ];
+        let out = scope.output(Tool::Ffmpeg, args).await?;
+        if !out.success() {
+            return Err(CompressError::from_ffmpeg_stderr(out.code, &out.stderr));
+        }
+        if let Some(r) = parse_cropdetect(&String::from_utf8_lossy(&out.stderr)) {
+            let (right, bottom) = (r.x + r.width, r.y + r.height);
+            found = Some(match found {
+                Some((x, y, rr, bb)) => (x.min(r.x), y.min(r.y), rr.max(right), bb.max(bottom)),
+                None => (r.x, r.y, right, bottom),
+            });
+        }
+    }
+    let (x, y, right, bottom) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let rect = CropRect {
+        x,
+        y,
+        width: (right.min(width) - x) & !1,
+        height: (bottom.min(height) - y) & !1,
+    };
+    if rect.width + MIN_BAR_PIXELS > width && rect.height + MIN_BAR_PIXELS > height {
+        return Ok(None);
+    }
+    Ok(Some(rect))
+}
+
+// Crop (given or detected), then rotation and flips
+pub async fn plan(
+    scope: &JobScope<'_>,
+    input: &str,
+    info: &MediaInfo,
+    options: &TransformOptions,
+) -> Result<TransformPlan> {
+    let video = match info.primary_video() {
+        Some(video) => video,
+        None => return Ok(TransformPlan::default()),
+    };
+    if !matches!(options.rotate, 0 | 90 | 180 | 270) {
+        return Err(CompressError::invalid(
+            "Rotation must be 90, 180 or 270 degrees",
+        ));
+    }
+    let (width, height) = video.display_size();
+
+    let crop = match options.crop {
+        Some(rect) => {
+            if rect.width < 2 || rect.height < 2 {
+                return Err(CompressError::invalid(
+                    "Crop rectangle must be at least 2x2 pixels",
+                ));
+            }
+            if width > 0
+                && height > 0
+                && (rect.x + rect.width > width || rect.y + rect.height > height)
+            {
+                return Err(CompressError::invalid(format!(
+                    "Crop {}x{} at {},{} doesn't fit in the {}x{} picture",
+                    rect.width, rect.height, rect.x, rect.y, width, height
+                )));
+            }
+            // 4:2:0 needs even dimensions
+            Some(CropRect {
+                width: rect.width & !1,
+                height: rect.height & !1,
+                ..rect
+            })
+        }
+        None if options.auto_crop => {
+            let detected = detect(scope, input, info, width, height).await?;
+            let note = match detected {
+                Some(r) => format!(
+                    "✂️ Auto-crop: {}x{} at {},{} (source {}x{})",
+                    r.width, r.height, r.x, r.y, width, height
+                ),
+                None => "✂️ Auto-crop: no black bars found".to_string(),
+            };
+            scope.engine.note(note);
+            detected
+        }
+        None => None,
+    };
+
+    let mut filters = vec![];
+    if let Some(r) = crop {
+        filters.push(format!("crop={}:{}:{}:{}", r.width, r.height, r.x, r.y));
+    }
+    match options.rotate {
+        90 => filters.push("transpose=clock".to_string()),
+        180 => filters.extend(["hflip".to_string(), "vflip".to_string()]),
+        270 => filters.push("transpose=cclock".to_string()),
+        _ => {}
+    }
+    if options.flip_horizontal {
+        filters.push("hflip".to_string());
+    }
+    if options.flip_vertical {
+        filters.push("vflip".to_string());
+    }
+    Ok(TransformPlan {
+        filter: (!filters.is_empty()).then(|| filters.join(",")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        block_on, ffmpeg_calls, planning, strings, Script, ScriptedRunner, PROBE_1080P,
+    };
+
+    fn transform_plan(options: &TransformOptions, runner: ScriptedRunner) -> Result<TransformPlan> {
+        planning(PROBE_1080P, runner, |scope, info| {
+            block_on(plan(scope, "in.mov", info, options))
+        })
+    }
+
+    // cropdetect's stderr for one sample whose final box is `crop`
+    fn cropdetect(crop: &str) -> Script {
+        Script { stderr: vec![format!("[Parsed_cropdetect_0 @ 0x55d0] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:90000 t:1.000000 {}", crop)], code: Some(0), ..Default::default() }
+    }
+
+    #[test]
+    fn parse_cropdetect_reads_the_last_box() {
+        let stderr = "frame=    1 fps=0.0\n\
+            [Parsed_cropdetect_0 @ 0x55d0] x1:0 x2:1919 y1:200 y2:879 w:1920 h:672 x:0 y:204 pts:0 t:0.000000 crop=1920:672:0:204\n\
+            [Parsed_cropdetect_0 @ 0x55d0] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:3003 t:0.033367 crop=1920:800:0:140\n\
+            frame=   30 fps=0.0 q=-0.0 Lsize=N/A";
+
+        assert_eq!(
+            parse_cropdetect(stderr),
+            Some(CropRect {
+                x: 0,
+                y: 140,
+                width: 1920,
+                height: 800
+            })
+        );
+    }
+
+    #[test]
+    fn parse_cropdetect_gives_nothing_for_black_frames_or_garbage() {
+        // All-black frames never move the box off its starting point, which comes out negative
+        assert_eq!(parse_cropdetect("[Parsed_cropdetect_0 @ 0x55d0] x1:1919 x2:0 y1:1079 y2:0 w:-1904 h:-1072 x:1912 y:1080 crop=-1904:-1072:1912:1080"), None);
+        assert_eq!(
+            parse_cropdetect("[Parsed_cropdetect_0 @ 0x55d0] crop=0:800:0:140"),
+            None
+        );
+        assert_eq!(
+            parse_cropdetect("[Parsed_cropdetect_0 @ 0x55d0] crop=1920:800"),
+            None
+        );
+        assert_eq!(
+            parse_cropdetect("frame=   30 fps=0.0 q=-0.0 Lsize=N/A"),
+            None
+        );
+    }
+
+    #[test]
+    fn auto_crop_takes_the_union_of_every_sample() {
+        // A wider letterbox in one scene and a pillarboxed shot in another
+        let runner = ScriptedRunner::new(|_, args: &[String]| {
+            if args.windows(2).any(|w| w[0] == "-ss" && w[1] == "3.000") {
+                cropdetect("crop=1600:880:160:100")
+            } else {
+                cropdetect("crop=1920:800:0:140")
+            }
+        });
+        let calls = runner.calls();
+
+        let plan = transform_plan(
+            &TransformOptions {
+                auto_crop: true,
+                ..Default::default()
+            },
+            runner,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.video_filter(None).as_deref(),
+            Some("crop=1920:880:0:100")
+        );
+        let ffmpeg = ffmpeg_calls(&calls.lock().unwrap());
+        assert_eq!(ffmpeg.len(), 5);
+        assert_eq!(
+            ffmpeg[0],
+            strings(&[
+                "-ss",
+                "1.000",
+                "-i",
+                "in.mov",
+                "-map",
+                "0:0",
+                "-vf",
+                "cropdetect=limit=0.1:round=2:reset=0",
+                "-frames:v",
+                "30",
+                "-an",
+                "-f",
+                "null",
+                "-"
+            ])
+        );
+    }
+
+    #[test]
+    fn auto_crop_ignores_bars_thinner_than_min_bar_pixels() {
+        // 6 px off either dimension is not worth a non-standard frame size
+        let thin = transform_plan(
+            &TransformOptions {
+                auto_crop: true,
+                ..Default::default()
+            },
+            ScriptedRunner::new(|_, _| cropdetect("crop=1914:1074:2:2")),
+        )
+        .unwrap();
+        // 8 px off one of them is
+        let wide = transform_plan(
+            &TransformOptions {
+                auto_crop: true,
+                ..Default::default()
+            },
+            ScriptedRunner::new(|_, _| cropdetect("crop=1912:1080:4:0")),
+        )
+        .unwrap();
+
+        assert_eq!(thin.video_filter(None), None);
+        assert_eq!(
+            wide.video_filter(None).as_deref(),
+            Some("crop=1912:1080:4:0")
+        );
+    }
+
+    #[test]
+    fn crop_runs_before_rotation_and_flips() {
+        let options = TransformOptions {
+            rotate: 90,
+            flip_horizontal: true,
+            crop: Some(CropRect {
+                x: 240,
+                y: 0,
+                width: 1441,
+                height: 1080,
+            }),
+            ..Default::default()
+        };
+
+        let plan = transform_plan(&options, ScriptedRunner::new(|_, _| Script::ok())).unwrap();
+
+        // The odd width is rounded down for 4:2:0
+        assert_eq!(
+            plan.video_filter(None).as_deref(),
+            Some("crop=1440:1080:240:0,transpose=clock,hflip")
+        );
+    }
+
+    #[test]
+    fn crop_outside_the_picture_is_rejected() {
+        let options = TransformOptions {
+            crop: Some(CropRect {
+                x: 100,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            }),
+            ..Default::default()
+        };
+
+        let result = transform_plan(&options, ScriptedRunner::new(|_, _| Script::ok()));
+
+        assert!(matches!(result, Err(CompressError::InvalidRequest { .. })));
+    }
+}
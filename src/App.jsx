@@ -437,9 +437,12 @@ export default function App() {
 
       } catch (e) {
         console.error(e);
-        setLogs("Error: " + e);
+        // Backend errors are { code, message, ... }; stderrTail holds the last ffmpeg log lines
+        if (e?.code === 'CANCELLED') continue;
+        const errorText = e?.stderrTail?.length ? `${e.message}\n\n${e.stderrTail.join('\n')}` : (e?.message ?? e);
+        setLogs("Error: " + (e?.message ?? e));
         setFileQueue(prev => prev.map((f, idx) => idx === i ? {...f, status: 'error'} : f));
-        await message(`Compression Failed:\n\n${e}`, { title: 'Process Error', kind: 'error' });
+        await message(`Compression Failed:\n\n${errorText}`, { title: 'Process Error', kind: 'error' });
       }
     }
 